@@ -0,0 +1,456 @@
+//! 串级姿态控制器
+//!
+//! 外环角度环由姿态四元数误差算出目标角速度，内环角速度环由陀螺仪角速度算出各轴力矩。
+//! 机体坐标系x向前、y向右、z向下，右倾、抬头、机头右转为正，角度单位弧度，角速度单位弧度每秒。
+//! 特技模式只有角速度环，摇杆按速率曲线对应目标角速度；自稳模式横滚、俯仰摇杆对应目标角度，偏航仍为角速度。
+
+use crate::acs::attitude_error::reduced_attitude_error;
+use crate::acs::gain_schedule::VbatCompConfig;
+use crate::acs::pid::{AntiWindup, Derivative, FeedForward, ItermRelax, Limit, Pid};
+use crate::acs::rates::RateProfile;
+use crate::driver::Quaternion;
+use crate::message::RemoteControl;
+use crate::param;
+use nalgebra::Vector3;
+
+/// 控制模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttitudeMode {
+    /// 特技，只控制角速度
+    Acro,
+    /// 自稳，横滚、俯仰控制角度
+    Angle,
+}
+
+/// 角速度环参数在参数表里的名字，依次为横滚、俯仰、偏航的kp、ki、kd
+pub const RATE_PARAMS: [[&str; 3]; 3] = [
+    ["rate_roll_p", "rate_roll_i", "rate_roll_d"],
+    ["rate_pitch_p", "rate_pitch_i", "rate_pitch_d"],
+    ["rate_yaw_p", "rate_yaw_i", "rate_yaw_d"],
+];
+
+/// MSP_PID的数据长度，横滚、俯仰、偏航、自稳、磁罗盘各3字节
+pub const MSP_PID_SIZE: usize = 15;
+
+//MSP_PID字节到增益的系数，依次为P、I、D，与Betaflight的PTERM_SCALE、ITERM_SCALE、DTERM_SCALE一致。
+//Betaflight角速度单位°/s，输出按1000归一；这里角速度单位rad/s，输出-1.0~1.0
+const MSP_PID_SCALE: [f32; 3] = [
+    0.032029 / 1000.0 * 57.29578,
+    0.244381 / 1000.0 * 57.29578,
+    0.000529 / 1000.0 * 57.29578,
+];
+
+/// 单个PID的参数
+#[derive(Debug, Clone, Copy)]
+pub struct PidGains {
+    pub kp: f32,
+    pub ki: f32,
+    pub kd: f32,
+}
+
+impl PidGains {
+    pub const fn new(kp: f32, ki: f32, kd: f32) -> Self {
+        Self { kp, ki, kd }
+    }
+}
+
+/// 姿态控制参数，下标0、1、2依次为横滚、俯仰、偏航
+#[derive(Debug, Clone, Copy)]
+pub struct AttitudeConfig {
+    pub rates: RateProfile,                          //特技模式摇杆速率曲线和TPA
+    pub max_rate: [f32; 3],                          //自稳模式角度环输出的最大角速度
+    pub max_angle: f32,                              //自稳模式最大倾斜角
+    pub angle: PidGains,                             //角度环，三轴共用
+    pub yaw_weight: f32,                             //跟踪目标姿态时偏航误差的权重，0.0-1.0
+    pub rate: [PidGains; 3],                         //角速度环
+    pub d_cutoff: f32,                               //角速度环微分低通截止频率，单位Hz
+    pub feed_forward: [Option<FeedForward<f32>>; 3], //角速度环前馈，None为不用
+    pub iterm_relax: [Option<ItermRelax<f32>>; 3],   //角速度环积分松弛，None为不用
+    pub max_torque: f32,                             //力矩输出限幅，0.0-1.0
+    pub vbat: Option<VbatCompConfig>,                //电压补偿，None为不补偿
+}
+
+impl AttitudeConfig {
+    pub const fn new() -> Self {
+        Self {
+            rates: RateProfile::new(),
+            max_rate: [3.49, 3.49, 3.49], //200°/s
+            max_angle: 0.61,              //35°
+            angle: PidGains::new(6.0, 0.0, 0.0),
+            yaw_weight: 0.4,
+            rate: [
+                PidGains::new(0.15, 0.3, 0.002),
+                PidGains::new(0.15, 0.3, 0.002),
+                PidGains::new(0.2, 0.3, 0.0),
+            ],
+            d_cutoff: 100.0,
+            //前馈和积分松弛默认关闭，需要时按轴设置
+            feed_forward: [None; 3],
+            iterm_relax: [None; 3],
+            max_torque: 1.0,
+            vbat: None,
+        }
+    }
+}
+
+impl AttitudeConfig {
+    /// 用参数表里设置过的角速度环参数覆盖
+    pub fn with_params(mut self) -> Self {
+        for (gains, names) in self.rate.iter_mut().zip(RATE_PARAMS.iter()) {
+            gains.kp = param::get_or(names[0], gains.kp);
+            gains.ki = param::get_or(names[1], gains.ki);
+            gains.kd = param::get_or(names[2], gains.kd);
+        }
+        self
+    }
+}
+
+impl Default for AttitudeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 角速度环参数转为MSP_PID格式，超出0~255的截断，自稳和磁罗盘没有对应的参数，填0
+pub fn msp_pid(rate: &[PidGains; 3]) -> [u8; MSP_PID_SIZE] {
+    let mut b = [0u8; MSP_PID_SIZE];
+    for (i, g) in rate.iter().enumerate() {
+        for (j, k) in [g.kp, g.ki, g.kd].iter().enumerate() {
+            b[i * 3 + j] = libm::roundf(k / MSP_PID_SCALE[j]).max(0.0).min(255.0) as u8;
+        }
+    }
+    b
+}
+
+/// 解析MSP_SET_PID，只取横滚、俯仰、偏航三组，不足9字节时返回None
+pub fn from_msp_pid(b: &[u8]) -> Option<[PidGains; 3]> {
+    if b.len() < 9 {
+        return None;
+    }
+    let gains = |i: usize| {
+        PidGains::new(
+            b[i * 3] as f32 * MSP_PID_SCALE[0],
+            b[i * 3 + 1] as f32 * MSP_PID_SCALE[1],
+            b[i * 3 + 2] as f32 * MSP_PID_SCALE[2],
+        )
+    };
+    Some([gains(0), gains(1), gains(2)])
+}
+
+/// 遥控指令转为机体轴方向的摇杆量，推杆为低头，俯仰取反
+pub fn stick(rc: &RemoteControl) -> Vector3<f32> {
+    Vector3::new(rc.roll, -rc.pitch, rc.yaw)
+}
+
+pub struct AttitudeController {
+    mode: AttitudeMode,
+    config: AttitudeConfig,
+    angle: [Pid<f32>; 3],  //三轴角度环
+    rate: [Pid<f32>; 3],   //三轴角速度环
+    rate_sp: Vector3<f32>, //本次的目标角速度
+    torque: Vector3<f32>,  //本次的力矩输出
+    output_scale: f32,     //电压补偿系数
+}
+
+impl AttitudeController {
+    pub fn new(config: AttitudeConfig) -> Self {
+        Self {
+            mode: AttitudeMode::Acro,
+            config,
+            angle: [
+                angle_pid(&config, 0),
+                angle_pid(&config, 1),
+                angle_pid(&config, 2),
+            ],
+            rate: [
+                rate_pid(&config, 0),
+                rate_pid(&config, 1),
+                rate_pid(&config, 2),
+            ],
+            rate_sp: Vector3::zeros(),
+            torque: Vector3::zeros(),
+            output_scale: 1.0,
+        }
+    }
+
+    pub fn mode(&self) -> AttitudeMode {
+        self.mode
+    }
+
+    /// 切换模式时清掉两个环的积分
+    pub fn set_mode(&mut self, mode: AttitudeMode) {
+        if mode != self.mode {
+            self.reset();
+            self.mode = mode;
+        }
+    }
+
+    pub fn config(&self) -> &AttitudeConfig {
+        &self.config
+    }
+
+    /// 修改参数后重建控制器，前馈和积分松弛也按新参数设置，积分清零
+    pub fn set_config(&mut self, config: AttitudeConfig) {
+        *self = Self {
+            mode: self.mode,
+            ..Self::new(config)
+        };
+    }
+
+    /// 角度环，axis为0横滚、1俯仰、2偏航
+    pub fn angle_pid_mut(&mut self, axis: usize) -> &mut Pid<f32> {
+        &mut self.angle[axis]
+    }
+
+    /// 角速度环，axis为0横滚、1俯仰、2偏航
+    pub fn rate_pid_mut(&mut self, axis: usize) -> &mut Pid<f32> {
+        &mut self.rate[axis]
+    }
+
+    /// 本次的目标角速度
+    pub fn rate_setpoint(&self) -> Vector3<f32> {
+        self.rate_sp
+    }
+
+    /// 本次的力矩输出
+    pub fn torque(&self) -> Vector3<f32> {
+        self.torque
+    }
+
+    /// 修改速率曲线和TPA，不重建控制器
+    pub fn set_rates(&mut self, rates: RateProfile) {
+        self.config.rates = rates;
+    }
+
+    /// 增益调度，每次update前调用，throttle为油门(0.0~1.0)，voltage为电池电压
+    ///
+    /// TPA修改角速度环的增益系数，电压补偿放大力矩输出，不重建控制器
+    pub fn schedule(&mut self, throttle: f32, voltage: Option<f32>) {
+        let (p, i, d) = self.config.rates.tpa.scale(throttle);
+        self.rate
+            .iter_mut()
+            .for_each(|pid| pid.set_gain_scale(p, i, d));
+        self.output_scale = self.config.vbat.map(|v| v.factor(voltage)).unwrap_or(1.0);
+    }
+
+    /// 电压补偿系数，update返回的力矩已经乘过，机型输出油门时需要乘这个系数
+    pub fn output_scale(&self) -> f32 {
+        self.output_scale
+    }
+
+    /// 解锁、着陆时清掉积分
+    pub fn reset(&mut self) {
+        self.angle.iter_mut().for_each(|pid| pid.reset());
+        self.rate.iter_mut().for_each(|pid| pid.reset());
+        self.rate_sp = Vector3::zeros();
+        self.torque = Vector3::zeros();
+    }
+
+    /// 计算三轴力矩(-max_torque~max_torque)
+    ///
+    /// stick为机体轴方向的摇杆量(-1.0~1.0)，attitude为估计的姿态，gyro为角速度，dt单位秒
+    pub fn update(
+        &mut self,
+        stick: &Vector3<f32>,
+        attitude: &Quaternion,
+        gyro: &Vector3<f32>,
+        dt: f32,
+    ) -> Vector3<f32> {
+        let stick = stick.map(|s| s.max(-1.0).min(1.0));
+        for axis in 0..3 {
+            self.rate_sp[axis] = self.config.rates.rate(axis, stick[axis]).to_radians();
+        }
+        if self.mode == AttitudeMode::Angle {
+            //目标偏航取当前偏航，偏航由摇杆直接控制角速度
+            let (_, _, yaw) = attitude.euler_angles();
+            let max = self.config.max_angle;
+            let target = Quaternion::from_euler_angles(stick[0] * max, stick[1] * max, yaw);
+            let error = reduced_attitude_error(attitude, &target, 0.0);
+            for axis in 0..2 {
+                self.rate_sp[axis] = self.angle[axis].next1(-error[axis], 0.0, dt);
+            }
+        }
+        self.rate_loop(gyro, dt)
+    }
+
+    /// 跟踪目标姿态，用于自动飞行，偏航误差按yaw_weight降阶
+    pub fn update_attitude(
+        &mut self,
+        target: &Quaternion,
+        attitude: &Quaternion,
+        gyro: &Vector3<f32>,
+        dt: f32,
+    ) -> Vector3<f32> {
+        let error = reduced_attitude_error(attitude, target, self.config.yaw_weight);
+        for axis in 0..3 {
+            self.rate_sp[axis] = self.angle[axis].next1(-error[axis], 0.0, dt);
+        }
+        self.rate_loop(gyro, dt)
+    }
+
+    //角速度环
+    fn rate_loop(&mut self, gyro: &Vector3<f32>, dt: f32) -> Vector3<f32> {
+        for axis in 0..3 {
+            let torque = self.rate[axis].next1(gyro[axis], self.rate_sp[axis], dt);
+            let max = self.config.max_torque;
+            self.torque[axis] = (torque * self.output_scale).max(-max).min(max);
+        }
+        self.torque
+    }
+}
+
+//角度环输入为姿态误差的相反数，目标为0，输出目标角速度，按最大角速度限幅
+fn angle_pid(config: &AttitudeConfig, axis: usize) -> Pid<f32> {
+    let g = config.angle;
+    let max = config.max_rate[axis];
+    Pid::new(g.kp, g.ki, g.kd)
+        .with_limit_integral(Limit::new(-max, max))
+        .with_limit_out(Limit::new(-max, max))
+        .with_anti_windup(AntiWindup::Clamping)
+}
+
+//角速度环对测量值求微分，避免打杆时的微分冲击
+fn rate_pid(config: &AttitudeConfig, axis: usize) -> Pid<f32> {
+    let g = config.rate[axis];
+    let max = config.max_torque;
+    let mut pid = Pid::new(g.kp, g.ki, g.kd)
+        .with_derivative(Derivative::Measurement)
+        .with_d_lowpass(config.d_cutoff)
+        .with_limit_integral(Limit::new(-max, max))
+        .with_limit_out(Limit::new(-max, max))
+        .with_anti_windup(AntiWindup::Clamping);
+    pid.set_feed_forward(config.feed_forward[axis]);
+    pid.set_iterm_relax(config.iterm_relax[axis]);
+    pid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    fn axis(i: usize, value: f32) -> Vector3<f32> {
+        let mut v = Vector3::zeros();
+        v[i] = value;
+        v
+    }
+
+    #[test]
+    fn rate_loop_opposes_rotation() {
+        //右滚、抬头、机头右转时力矩都往回拉
+        for i in 0..3 {
+            let mut ctl = AttitudeController::new(AttitudeConfig::new());
+            let torque = ctl.update(
+                &Vector3::zeros(),
+                &Quaternion::identity(),
+                &axis(i, 1.0),
+                DT,
+            );
+            assert!(torque[i] < 0.0, "axis {} torque {}", i, torque[i]);
+        }
+    }
+
+    #[test]
+    fn stick_to_body_rates() {
+        //右压杆、拉杆、右打舵对应右滚、抬头、机头右转
+        let rc = RemoteControl {
+            roll: 0.5,
+            pitch: -0.5,
+            yaw: 0.5,
+            ..Default::default()
+        };
+        let mut ctl = AttitudeController::new(AttitudeConfig::new());
+        let torque = ctl.update(&stick(&rc), &Quaternion::identity(), &Vector3::zeros(), DT);
+        for i in 0..3 {
+            assert!(ctl.rate_setpoint()[i] > 0.0, "axis {}", i);
+            assert!(torque[i] > 0.0, "axis {}", i);
+        }
+    }
+
+    #[test]
+    fn angle_mode_levels() {
+        //右倾、抬头时回中，横滚、俯仰目标角速度为负
+        for (i, attitude) in [
+            Quaternion::from_euler_angles(0.2, 0.0, 1.0),
+            Quaternion::from_euler_angles(0.0, 0.2, -2.0),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let mut ctl = AttitudeController::new(AttitudeConfig::new());
+            ctl.set_mode(AttitudeMode::Angle);
+            ctl.update(&Vector3::zeros(), attitude, &Vector3::zeros(), DT);
+            let sp = ctl.rate_setpoint();
+            assert!(sp[i] < 0.0, "axis {} setpoint {}", i, sp[i]);
+            assert!(sp[1 - i].abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn rate_loop_uses_config_extras() {
+        let mut config = AttitudeConfig::new();
+        config.feed_forward[0] = Some(FeedForward::new(0.01));
+        config.iterm_relax[0] = Some(ItermRelax {
+            cutoff: 10.0,
+            threshold: 1.0,
+        });
+        let mut ctl = AttitudeController::new(AttitudeConfig::new());
+        //自整定结束时会重建控制器，前馈和积分松弛仍然生效
+        ctl.set_config(config);
+        let q = Quaternion::identity();
+        ctl.update(&Vector3::zeros(), &q, &Vector3::zeros(), DT);
+        ctl.update(&Vector3::new(0.5, 0.0, 0.0), &q, &Vector3::zeros(), DT);
+        let roll = ctl.rate_pid_mut(0);
+        assert!(roll.f_out() > 0.0);
+        assert!(roll.relax() < 1.0);
+        let pitch = ctl.rate_pid_mut(1);
+        assert_eq!(pitch.f_out(), 0.0);
+        assert_eq!(pitch.relax(), 1.0);
+    }
+
+    #[test]
+    fn msp_pid_matches_betaflight() {
+        //Betaflight 4.x默认的横滚P45 I80 D40
+        let gains = from_msp_pid(&[45, 80, 40, 47, 84, 46, 45, 80, 0]).unwrap();
+        assert!((gains[0].kp - 0.08258).abs() < 1e-4);
+        assert!((gains[0].ki - 1.1202).abs() < 1e-3);
+        assert!((gains[0].kd - 0.0012124).abs() < 1e-6);
+        assert_eq!(gains[2].kd, 0.0);
+        let b = msp_pid(&gains);
+        assert_eq!(b[..9], [45, 80, 40, 47, 84, 46, 45, 80, 0]);
+        assert_eq!(b[9..], [0; 6]);
+        assert!(from_msp_pid(&b[..8]).is_none());
+    }
+
+    #[test]
+    fn msp_pid_round_trip() {
+        //默认参数换算后误差在半个字节以内，超出范围的截断
+        let rate = AttitudeConfig::new().rate;
+        let back = from_msp_pid(&msp_pid(&rate)).unwrap();
+        for (a, b) in rate.iter().zip(back.iter()) {
+            assert!((a.kp - b.kp).abs() <= MSP_PID_SCALE[0] * 0.5);
+            assert!((a.ki - b.ki).abs() <= MSP_PID_SCALE[1] * 0.5);
+            assert!((a.kd - b.kd).abs() <= MSP_PID_SCALE[2] * 0.5);
+        }
+        let b = msp_pid(&[PidGains::new(10.0, -1.0, 0.0); 3]);
+        assert_eq!(b[..3], [255, 0, 0]);
+    }
+
+    #[test]
+    fn angle_mode_stick_target() {
+        //前推杆低头，水平时俯仰目标角速度为负，到达目标角度后为0
+        let rc = RemoteControl {
+            pitch: 0.5,
+            ..Default::default()
+        };
+        let config = AttitudeConfig::new();
+        let mut ctl = AttitudeController::new(config);
+        ctl.set_mode(AttitudeMode::Angle);
+        ctl.update(&stick(&rc), &Quaternion::identity(), &Vector3::zeros(), DT);
+        assert!(ctl.rate_setpoint()[1] < 0.0);
+        let target = Quaternion::from_euler_angles(0.0, -0.5 * config.max_angle, 0.0);
+        ctl.update(&stick(&rc), &target, &Vector3::zeros(), DT);
+        assert!(ctl.rate_setpoint().norm() < 1e-3);
+    }
+}
@@ -0,0 +1,118 @@
+//! 四元数姿态误差
+//!
+//! 误差四元数q_err = q⁻¹·q_sp在机体坐标系下，取最短路径后转为旋转矢量(轴×角度)，
+//! 没有欧拉角的万向节锁，也不会在偏航±π处跳变。
+//! 降阶姿态先对齐机体z轴(横滚、俯仰)，偏航误差按权重叠加，保证偏航误差大时倾斜仍优先收敛。
+
+use crate::driver::Quaternion;
+use nalgebra::{UnitQuaternion, Vector3};
+
+/// 四元数转旋转矢量，取最短路径，单位弧度
+pub fn rotation_vector(q: &Quaternion) -> Vector3<f32> {
+    let q = q.quaternion();
+    //w为负时q与-q表示同一姿态，取反走最短路径
+    let (w, v) = if q.w < 0.0 {
+        (-q.w, -q.imag())
+    } else {
+        (q.w, q.imag())
+    };
+    let norm = v.norm();
+    if norm < 1e-6 {
+        //小角度时sin(θ/2)≈θ/2
+        return v * 2.0;
+    }
+    v * (2.0 * libm::atan2f(norm, w) / norm)
+}
+
+/// 完整姿态误差，q为当前姿态，q_sp为目标姿态，返回机体坐标系下的旋转矢量
+pub fn attitude_error(q: &Quaternion, q_sp: &Quaternion) -> Vector3<f32> {
+    rotation_vector(&(q.inverse() * q_sp))
+}
+
+/// 降阶姿态误差，yaw_weight为偏航误差的权重，0.0-1.0，0.0时只控制横滚、俯仰
+pub fn reduced_attitude_error(q: &Quaternion, q_sp: &Quaternion, yaw_weight: f32) -> Vector3<f32> {
+    //机体z轴在世界坐标系下的方向
+    let e_z = q * Vector3::z();
+    let e_z_sp = q_sp * Vector3::z();
+    //只转动z轴的最短旋转，z轴反向时没有唯一解，退回完整姿态
+    let q_red = match UnitQuaternion::rotation_between(&e_z, &e_z_sp) {
+        Some(r) => r * q,
+        None => return attitude_error(q, q_sp),
+    };
+    //降阶目标到完整目标之间只差绕z轴的旋转，按权重取其中一部分
+    let mix = (q_red.inverse() * q_sp).into_inner();
+    let (w, z) = if mix.w < 0.0 {
+        (-mix.w, -mix.k)
+    } else {
+        (mix.w, mix.k)
+    };
+    let yaw = 2.0 * libm::atan2f(z, w) * yaw_weight.max(0.0).min(1.0);
+    let q_target = q_red * UnitQuaternion::from_axis_angle(&Vector3::z_axis(), yaw);
+    attitude_error(q, &q_target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).norm() < 1e-4
+    }
+
+    #[test]
+    fn small_angle() {
+        let q = Quaternion::identity();
+        let q_sp = Quaternion::from_scaled_axis(Vector3::new(0.01, -0.02, 0.005));
+        let e = attitude_error(&q, &q_sp);
+        assert!(close(e, Vector3::new(0.01, -0.02, 0.005)));
+        //极小角度走近似分支
+        let tiny = Quaternion::from_axis_angle(&Vector3::x_axis(), 1e-7);
+        assert!(close(rotation_vector(&tiny), Vector3::new(1e-7, 0.0, 0.0)));
+        assert!(attitude_error(&q_sp, &q_sp).norm() < 1e-6);
+    }
+
+    #[test]
+    fn yaw_wrap() {
+        //从偏航+170°到-170°，应该继续正转20°而不是反转340°
+        let q = Quaternion::from_euler_angles(0.0, 0.0, 170f32.to_radians());
+        let q_sp = Quaternion::from_euler_angles(0.0, 0.0, -170f32.to_radians());
+        let e = attitude_error(&q, &q_sp);
+        assert!(close(e, Vector3::new(0.0, 0.0, 20f32.to_radians())));
+        let e = attitude_error(&q_sp, &q);
+        assert!(close(e, Vector3::new(0.0, 0.0, -20f32.to_radians())));
+    }
+
+    #[test]
+    fn shortest_path() {
+        //w<0的四元数表示同一姿态，误差一样
+        let q = Quaternion::from_axis_angle(&Vector3::y_axis(), 0.5);
+        let neg = Quaternion::new_unchecked(-q.into_inner());
+        assert!(neg.w < 0.0);
+        assert!(close(rotation_vector(&neg), Vector3::new(0.0, 0.5, 0.0)));
+        //旋转超过π时取反方向的短路径
+        let q = Quaternion::from_axis_angle(&Vector3::z_axis(), 1.5 * PI);
+        assert!(close(
+            rotation_vector(&q),
+            Vector3::new(0.0, 0.0, -0.5 * PI)
+        ));
+    }
+
+    #[test]
+    fn yaw_weight() {
+        let q = Quaternion::identity();
+        let q_sp = Quaternion::from_euler_angles(0.2, 0.0, 1.0);
+        //权重1与完整误差一致
+        let full = attitude_error(&q, &q_sp);
+        assert!(close(reduced_attitude_error(&q, &q_sp, 1.0), full));
+        //权重0只对齐z轴，没有偏航分量，且对齐后z轴重合
+        let e = reduced_attitude_error(&q, &q_sp, 0.0);
+        assert!(e.z.abs() < 1e-4);
+        let rot = Quaternion::from_scaled_axis(e);
+        assert!(((q * rot) * Vector3::z() - q_sp * Vector3::z()).norm() < 1e-4);
+        //纯偏航目标在权重0时没有误差
+        let yaw_only = Quaternion::from_euler_angles(0.0, 0.0, 1.0);
+        assert!(reduced_attitude_error(&q, &yaw_only, 0.0).norm() < 1e-4);
+        assert!((reduced_attitude_error(&q, &yaw_only, 0.5).z - 0.5).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,357 @@
+//! 继电反馈PID自整定
+//!
+//! 整定时被测轴的角速度环换成继电器：角速度误差大于回差时输出+d，小于-回差时输出-d，
+//! 系统进入等幅振荡。由振荡周期Tu和误差幅值a得到临界增益Ku=4d/(π·sqrt(a²-ε²))，
+//! 再按整定规则算出PID参数。一次整定一个轴，依次为横滚、俯仰、偏航，结果写入参数表。
+//!
+//! 飞行模式为Autotune时，在姿态控制器算出力矩之后调用Autotune::update，替换被测轴的力矩。
+//!
+use crate::acs::attitude::{PidGains, RATE_PARAMS};
+use crate::param;
+use core::f32::consts::PI;
+use nalgebra::Vector3;
+
+/// 整定规则
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TuneRule {
+    /// Ziegler–Nichols经典规则，响应最快，超调最大
+    ZieglerNichols,
+    /// 比例系数约为ZN的一半，超调较小
+    SomeOvershoot,
+    /// 比例系数为ZN的三分之一，最保守
+    NoOvershoot,
+}
+
+impl TuneRule {
+    /// 由临界增益和临界周期(秒)计算PID参数
+    pub fn gains(&self, ultimate: &UltimateGain) -> PidGains {
+        let (ku, tu) = (ultimate.ku, ultimate.tu);
+        //比例系数、积分时间、微分时间
+        let (kp, ti, td) = match self {
+            TuneRule::ZieglerNichols => (0.6 * ku, tu / 2.0, tu / 8.0),
+            TuneRule::SomeOvershoot => (0.33 * ku, tu / 2.0, tu / 3.0),
+            TuneRule::NoOvershoot => (0.2 * ku, tu / 2.0, tu / 3.0),
+        };
+        PidGains::new(kp, kp / ti, kp * td)
+    }
+}
+
+/// 临界增益和临界周期
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UltimateGain {
+    pub ku: f32,
+    pub tu: f32, //单位秒
+}
+
+/// 继电器参数
+#[derive(Debug, Clone, Copy)]
+pub struct RelayConfig {
+    pub amplitude: f32,     //继电器输出幅值，力矩单位
+    pub hysteresis: f32,    //回差，角速度单位，避免噪声来回切换
+    pub settle_cycles: u8,  //开始测量前丢掉的振荡周期数
+    pub measure_cycles: u8, //参与平均的振荡周期数
+    pub timeout: f32,       //超时，单位秒
+}
+
+impl RelayConfig {
+    pub const fn new() -> Self {
+        Self {
+            amplitude: 0.2,
+            hysteresis: 0.05,
+            settle_cycles: 2,
+            measure_cycles: 4,
+            timeout: 10.0,
+        }
+    }
+}
+
+impl Default for RelayConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 继电器状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RelayState {
+    Running,
+    Done(UltimateGain),
+    /// 超时或者振荡幅值不超过回差
+    Failed,
+}
+
+/// 单轴继电反馈
+#[derive(Debug, Clone)]
+pub struct RelayTuner {
+    config: RelayConfig,
+    state: RelayState,
+    output: f32,            //继电器方向，1.0或-1.0
+    time: f32,              //开始后的时间
+    last_rise: Option<f32>, //上次向正方向切换的时间
+    max: f32,               //本周期误差最大值
+    min: f32,               //本周期误差最小值
+    cycles: u8,             //完整周期数
+    period_sum: f32,
+    amplitude_sum: f32,
+}
+
+impl RelayTuner {
+    pub fn new(config: RelayConfig) -> Self {
+        Self {
+            config,
+            state: RelayState::Running,
+            output: 1.0,
+            time: 0.0,
+            last_rise: None,
+            max: f32::MIN,
+            min: f32::MAX,
+            cycles: 0,
+            period_sum: 0.0,
+            amplitude_sum: 0.0,
+        }
+    }
+
+    pub fn state(&self) -> RelayState {
+        self.state
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// 输入误差(目标值-测量值)，返回继电器输出，结束后输出0
+    pub fn update(&mut self, error: f32, dt: f32) -> f32 {
+        if self.state != RelayState::Running {
+            return 0.0;
+        }
+        self.time += dt;
+        if self.time > self.config.timeout {
+            log::warn!("relay autotune timeout");
+            self.state = RelayState::Failed;
+            return 0.0;
+        }
+        self.max = self.max.max(error);
+        self.min = self.min.min(error);
+        let eps = self.config.hysteresis;
+        if self.output < 0.0 && error > eps {
+            self.output = 1.0;
+            if let Some(t0) = self.last_rise {
+                self.cycle(self.time - t0);
+            }
+            self.last_rise = Some(self.time);
+            self.max = error;
+            self.min = error;
+        } else if self.output > 0.0 && error < -eps {
+            self.output = -1.0;
+        }
+        if self.state == RelayState::Running {
+            self.output * self.config.amplitude
+        } else {
+            0.0
+        }
+    }
+
+    //一个完整周期结束
+    fn cycle(&mut self, period: f32) {
+        self.cycles += 1;
+        if self.cycles <= self.config.settle_cycles {
+            return;
+        }
+        self.period_sum += period;
+        self.amplitude_sum += (self.max - self.min) / 2.0;
+        let n = (self.cycles - self.config.settle_cycles) as f32;
+        if n < self.config.measure_cycles as f32 {
+            return;
+        }
+        let a = self.amplitude_sum / n;
+        let eps = self.config.hysteresis;
+        self.state = if a > eps {
+            RelayState::Done(UltimateGain {
+                ku: 4.0 * self.config.amplitude / (PI * libm::sqrtf(a * a - eps * eps)),
+                tu: self.period_sum / n,
+            })
+        } else {
+            RelayState::Failed
+        };
+    }
+}
+
+/// 三轴依次自整定
+pub struct Autotune {
+    rule: TuneRule,
+    axis: usize, //正在整定的轴，3表示结束
+    tuner: RelayTuner,
+    results: [Option<PidGains>; 3],
+}
+
+impl Autotune {
+    pub fn new(config: RelayConfig, rule: TuneRule) -> Self {
+        Self {
+            rule,
+            axis: 0,
+            tuner: RelayTuner::new(config),
+            results: [None; 3],
+        }
+    }
+
+    /// 正在整定的轴，0横滚、1俯仰、2偏航，全部结束返回None
+    pub fn axis(&self) -> Option<usize> {
+        if self.axis < 3 {
+            Some(self.axis)
+        } else {
+            None
+        }
+    }
+
+    /// 各轴的整定结果，失败的轴为None
+    pub fn results(&self) -> [Option<PidGains>; 3] {
+        self.results
+    }
+
+    /// 用继电器输出替换被测轴的力矩，rate_sp为目标角速度，gyro为角速度，dt单位秒
+    pub fn update(
+        &mut self,
+        torque: &mut Vector3<f32>,
+        rate_sp: &Vector3<f32>,
+        gyro: &Vector3<f32>,
+        dt: f32,
+    ) {
+        let axis = match self.axis() {
+            Some(axis) => axis,
+            None => return,
+        };
+        torque[axis] = self.tuner.update(rate_sp[axis] - gyro[axis], dt);
+        match self.tuner.state() {
+            RelayState::Running => return,
+            RelayState::Done(ultimate) => {
+                let gains = self.rule.gains(&ultimate);
+                log::info!("autotune axis {} {:?} {:?}", axis, ultimate, gains);
+                store(axis, &gains);
+                self.results[axis] = Some(gains);
+            }
+            RelayState::Failed => log::warn!("autotune axis {} failed", axis),
+        }
+        self.axis += 1;
+        self.tuner.reset();
+    }
+}
+
+/// 整定结果写入参数表
+pub fn store(axis: usize, gains: &PidGains) {
+    let names = RATE_PARAMS[axis];
+    param::set(names[0], gains.kp);
+    param::set(names[1], gains.ki);
+    param::set(names[2], gains.kd);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    //一阶惯性加纯滞后对象 K·e^(-Ls)/(τs+1)
+    struct Fopdt {
+        k: f32,
+        tau: f32,
+        delay: [f32; 64],
+        steps: usize, //滞后的采样数
+        index: usize,
+        y: f32,
+    }
+
+    impl Fopdt {
+        fn new(k: f32, tau: f32, l: f32) -> Self {
+            Self {
+                k,
+                tau,
+                delay: [0.0; 64],
+                steps: libm::roundf(l / DT) as usize,
+                index: 0,
+                y: 0.0,
+            }
+        }
+
+        fn step(&mut self, u: f32) -> f32 {
+            let delayed = self.delay[self.index];
+            self.delay[self.index] = u;
+            self.index = (self.index + 1) % self.steps;
+            self.y += DT * (self.k * delayed - self.y) / self.tau;
+            self.y
+        }
+    }
+
+    //相位滞后atan(ωτ)+ωL=π处为临界频率，Ku=sqrt(1+(ωτ)²)/K
+    fn ultimate(k: f32, tau: f32, l: f32) -> UltimateGain {
+        let (mut lo, mut hi) = (0.0, PI / l);
+        for _ in 0..50 {
+            let w = (lo + hi) / 2.0;
+            if libm::atanf(w * tau) + w * l < PI {
+                lo = w;
+            } else {
+                hi = w;
+            }
+        }
+        UltimateGain {
+            ku: libm::sqrtf(1.0 + (lo * tau) * (lo * tau)) / k,
+            tu: 2.0 * PI / lo,
+        }
+    }
+
+    #[test]
+    fn relay_matches_fopdt_ultimate() {
+        let (k, tau, l) = (1.0, 0.02, 0.04);
+        let mut plant = Fopdt::new(k, tau, l);
+        let mut relay = RelayTuner::new(RelayConfig {
+            hysteresis: 0.005,
+            ..RelayConfig::new()
+        });
+        let mut y = 0.0;
+        while relay.state() == RelayState::Running {
+            y = plant.step(relay.update(-y, DT));
+        }
+        let expected = ultimate(k, tau, l);
+        let measured = match relay.state() {
+            RelayState::Done(u) => u,
+            state => panic!("{:?}", state),
+        };
+        //描述函数法是近似，滞后占主导时误差在几个百分点以内
+        assert!((measured.ku - expected.ku).abs() / expected.ku < 0.1);
+        assert!((measured.tu - expected.tu).abs() / expected.tu < 0.1);
+    }
+
+    #[test]
+    fn relay_fails_without_oscillation() {
+        let mut relay = RelayTuner::new(RelayConfig::new());
+        //测量值始终等于目标值，误差不超过回差，继电器不切换
+        while relay.state() == RelayState::Running {
+            relay.update(0.0, DT);
+        }
+        assert_eq!(relay.state(), RelayState::Failed);
+    }
+
+    #[test]
+    fn autotune_all_axes() {
+        let mut plants = [
+            Fopdt::new(1.0, 0.02, 0.04),
+            Fopdt::new(1.0, 0.02, 0.04),
+            Fopdt::new(0.5, 0.02, 0.04),
+        ];
+        let mut tune = Autotune::new(RelayConfig::new(), TuneRule::ZieglerNichols);
+        let mut gyro = Vector3::zeros();
+        while tune.axis().is_some() {
+            let mut torque = Vector3::zeros();
+            tune.update(&mut torque, &Vector3::zeros(), &gyro, DT);
+            for (axis, plant) in plants.iter_mut().enumerate() {
+                gyro[axis] = plant.step(torque[axis]);
+            }
+        }
+        let results = tune.results();
+        let ku = ultimate(1.0, 0.02, 0.04).ku;
+        let roll = results[0].unwrap();
+        assert!((roll.kp - 0.6 * ku).abs() / (0.6 * ku) < 0.15);
+        //对象增益减半，临界增益加倍
+        let yaw = results[2].unwrap();
+        assert!((yaw.kp / roll.kp - 2.0).abs() < 0.2);
+    }
+}
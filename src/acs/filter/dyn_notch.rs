@@ -0,0 +1,426 @@
+//! ### 动态陷波
+//!
+//! 陀螺仪每个轴保留最近FFT_SIZE个(降采样后的)数据，加汉宁窗做FFT，在最低和最高频率之间
+//! 找出最强的几个峰值，用抛物线插值求出峰值频率，平滑后重新设置该轴的陷波器。
+//! 每次只分析一个轴，三个轴轮流，把FFT的计算量分散到各个采样周期。
+//! 降采样使FFT的采样率不低于最高频率的2.5倍，最高频率600Hz时频点间隔约25~45Hz，靠插值细化。
+//! IMU为1kHz时不降采样，最高频率受奈奎斯特频率限制为450Hz，陷波频段为150~450Hz，频点间隔约16Hz。
+//! 全部用定长数组，内存约2KB；一次分析是192次蝶形运算加一次32点排序，不分配堆内存。
+use super::iir_filter::{Biquad, BiquadCoeffs};
+use super::Filter;
+use core::f32::consts::PI;
+use nalgebra::Vector3;
+
+/// FFT点数，2的整数次幂
+pub const FFT_SIZE: usize = 64;
+/// 每个轴最多陷波器个数
+pub const MAX_PEAKS: usize = 4;
+/// 每隔多少个降采样后的数据分析一个轴
+const HOP: usize = 8;
+/// 峰值至少为噪声基底的倍数
+const PEAK_THRESHOLD: f32 = 10.0;
+/// 峰值至少为最大峰值的比例，过滤窗函数旁瓣
+const PEAK_MIN_RATIO: f32 = 0.01;
+
+/// 动态陷波参数
+#[derive(Debug, Clone, Copy)]
+pub struct DynNotchConfig {
+    pub count: u8,      //每个轴的陷波器个数，1-4
+    pub q: f32,         //品质因数
+    pub min_hz: f32,    //最低陷波频率
+    pub max_hz: f32,    //最高陷波频率
+    pub smoothing: f32, //每次分析后中心频率向新峰值靠近的比例，0.0-1.0
+}
+
+impl DynNotchConfig {
+    pub const fn new() -> Self {
+        Self {
+            count: 3,
+            q: 3.5,
+            min_hz: 150.0,
+            max_hz: 600.0,
+            smoothing: 0.4,
+        }
+    }
+}
+
+impl Default for DynNotchConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 频谱峰值
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Peak {
+    pub hz: f32,
+    pub power: f32,
+}
+
+/// 原位基2 FFT，twiddle[k]为(cos, -sin)(2πk/N)
+pub fn fft(
+    re: &mut [f32; FFT_SIZE],
+    im: &mut [f32; FFT_SIZE],
+    twiddle: &[(f32, f32); FFT_SIZE / 2],
+) {
+    //位反转置换
+    let bits = FFT_SIZE.trailing_zeros();
+    for i in 0..FFT_SIZE {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= FFT_SIZE {
+        let step = FFT_SIZE / len;
+        for start in (0..FFT_SIZE).step_by(len) {
+            for k in 0..len / 2 {
+                let (wr, wi) = twiddle[k * step];
+                let (a, b) = (start + k, start + k + len / 2);
+                let tr = re[b] * wr - im[b] * wi;
+                let ti = re[b] * wi + im[b] * wr;
+                re[b] = re[a] - tr;
+                im[b] = im[a] - ti;
+                re[a] += tr;
+                im[a] += ti;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// 在功率谱power(下标为频点，间隔bin_hz)的[min_hz, max_hz]里找最强的几个局部峰值，
+/// 结果按频率从低到高放在peaks里，返回个数。噪声基底取power[1..]中最多FFT_SIZE/2个频点的中位数
+pub fn find_peaks(
+    power: &[f32],
+    bin_hz: f32,
+    min_hz: f32,
+    max_hz: f32,
+    peaks: &mut [Peak],
+) -> usize {
+    if power.len() < 3 || peaks.is_empty() || bin_hz <= 0.0 {
+        return 0;
+    }
+    let start = (libm::ceilf(min_hz / bin_hz) as usize).max(1);
+    let end = (libm::floorf(max_hz / bin_hz) as usize).min(power.len() - 2);
+    if start > end {
+        return 0;
+    }
+    //整个频谱功率的中位数作为噪声基底，不受几个大峰值影响
+    let mut sorted = [0.0; FFT_SIZE / 2];
+    let n = (power.len() - 1).min(sorted.len());
+    let sorted = &mut sorted[..n];
+    sorted.copy_from_slice(&power[1..=n]);
+    sorted.sort_unstable_by(|x, y| x.partial_cmp(y).unwrap_or(core::cmp::Ordering::Equal));
+    let max = power[start..=end].iter().fold(0.0f32, |m, &p| m.max(p));
+    let floor = (sorted[n / 2] * PEAK_THRESHOLD).max(max * PEAK_MIN_RATIO);
+    let mut count = 0;
+    for i in start..=end {
+        let (a, b, c) = (power[i - 1], power[i], power[i + 1]);
+        if b <= a || b < c || b <= floor {
+            continue;
+        }
+        //按功率从大到小插入
+        let pos = peaks[..count]
+            .iter()
+            .position(|p| p.power < b)
+            .unwrap_or(count);
+        if pos >= peaks.len() {
+            continue;
+        }
+        if count < peaks.len() {
+            count += 1;
+        }
+        peaks.copy_within(pos..count - 1, pos + 1);
+        //对幅值做抛物线插值
+        let (a, b2, c) = (libm::sqrtf(a), libm::sqrtf(b), libm::sqrtf(c));
+        let d = a - 2.0 * b2 + c;
+        let offset = if d != 0.0 { 0.5 * (a - c) / d } else { 0.0 };
+        peaks[pos] = Peak {
+            hz: (i as f32 + offset.max(-0.5).min(0.5)) * bin_hz,
+            power: b,
+        };
+    }
+    peaks[..count].sort_unstable_by(|x, y| {
+        x.hz.partial_cmp(&y.hz)
+            .unwrap_or(core::cmp::Ordering::Equal)
+    });
+    count
+}
+
+/// 三轴动态陷波
+pub struct DynNotch {
+    config: DynNotchConfig,
+    sample_hz: f32,
+    enabled: bool,
+    decimate: usize,                     //降采样倍数
+    bin_hz: f32,                         //频点间隔
+    max_hz: f32,                         //按降采样后的奈奎斯特频率限制过的最高频率
+    window: [f32; FFT_SIZE],             //汉宁窗
+    twiddle: [(f32, f32); FFT_SIZE / 2], //旋转因子
+    acc: Vector3<f32>,                   //降采样累加
+    acc_count: usize,
+    buffer: [[f32; FFT_SIZE]; 3],   //环形缓冲
+    index: usize,                   //下一个写入位置
+    filled: usize,                  //缓冲里的数据个数
+    hop: usize,                     //距上次分析的数据个数
+    axis: usize,                    //下一个分析的轴
+    centers: [[f32; MAX_PEAKS]; 3], //陷波中心频率，0为还没有找到峰值
+    notches: [[Biquad; MAX_PEAKS]; 3],
+}
+
+impl DynNotch {
+    pub fn new(config: DynNotchConfig, sample_hz: f32) -> Self {
+        let decimate = ((sample_hz / (config.max_hz * 2.5)) as usize).max(1);
+        let fft_hz = sample_hz / decimate as f32;
+        let max_hz = config.max_hz.min(fft_hz * 0.45);
+        let enabled = config.min_hz < max_hz;
+        if !enabled {
+            log::warn!(
+                "Dynamic notch disabled, sample rate {}Hz too low for {}Hz",
+                sample_hz,
+                config.min_hz
+            );
+        }
+        let mut window = [0.0; FFT_SIZE];
+        for (i, w) in window.iter_mut().enumerate() {
+            *w = 0.5 - 0.5 * libm::cosf(2.0 * PI * i as f32 / FFT_SIZE as f32);
+        }
+        let mut twiddle = [(0.0, 0.0); FFT_SIZE / 2];
+        for (k, t) in twiddle.iter_mut().enumerate() {
+            let a = 2.0 * PI * k as f32 / FFT_SIZE as f32;
+            *t = (libm::cosf(a), -libm::sinf(a));
+        }
+        Self {
+            config,
+            sample_hz,
+            enabled,
+            decimate,
+            bin_hz: fft_hz / FFT_SIZE as f32,
+            max_hz,
+            window,
+            twiddle,
+            acc: Vector3::zeros(),
+            acc_count: 0,
+            buffer: [[0.0; FFT_SIZE]; 3],
+            index: 0,
+            filled: 0,
+            hop: 0,
+            axis: 0,
+            centers: [[0.0; MAX_PEAKS]; 3],
+            notches: [[Biquad::new(BiquadCoeffs::passthrough()); MAX_PEAKS]; 3],
+        }
+    }
+
+    /// 某个轴当前的陷波中心频率，0为未启用
+    pub fn centers(&self, axis: usize) -> [f32; MAX_PEAKS] {
+        self.centers[axis]
+    }
+
+    //写入一个数据，缓冲满后每HOP个数据分析一个轴
+    fn push(&mut self, input: &Vector3<f32>) {
+        self.acc += input;
+        self.acc_count += 1;
+        if self.acc_count < self.decimate {
+            return;
+        }
+        let v = self.acc / self.acc_count as f32;
+        self.acc = Vector3::zeros();
+        self.acc_count = 0;
+        for axis in 0..3 {
+            self.buffer[axis][self.index] = v[axis];
+        }
+        self.index = (self.index + 1) % FFT_SIZE;
+        self.filled = (self.filled + 1).min(FFT_SIZE);
+        self.hop += 1;
+        if self.filled == FFT_SIZE && self.hop >= HOP {
+            self.hop = 0;
+            self.analyze(self.axis);
+            self.axis = (self.axis + 1) % 3;
+        }
+    }
+
+    //分析一个轴的频谱，重新设置该轴的陷波器
+    fn analyze(&mut self, axis: usize) {
+        let mut re = [0.0; FFT_SIZE];
+        let mut im = [0.0; FFT_SIZE];
+        //从最老的数据开始，先去掉直流再加窗
+        let buf = &self.buffer[axis];
+        let mean = buf.iter().sum::<f32>() / FFT_SIZE as f32;
+        for (i, r) in re.iter_mut().enumerate() {
+            *r = (buf[(self.index + i) % FFT_SIZE] - mean) * self.window[i];
+        }
+        fft(&mut re, &mut im, &self.twiddle);
+        let mut power = [0.0; FFT_SIZE / 2];
+        for (i, p) in power.iter_mut().enumerate() {
+            *p = re[i] * re[i] + im[i] * im[i];
+        }
+        let count = (self.config.count as usize).max(1).min(MAX_PEAKS);
+        let mut peaks = [Peak::default(); MAX_PEAKS];
+        let n = find_peaks(
+            &power,
+            self.bin_hz,
+            self.config.min_hz,
+            self.max_hz,
+            &mut peaks[..count],
+        );
+        let k = self.config.smoothing.max(0.0).min(1.0);
+        for (slot, peak) in peaks[..n].iter().enumerate() {
+            let center = &mut self.centers[axis][slot];
+            *center = if *center > 0.0 {
+                *center + k * (peak.hz - *center)
+            } else {
+                peak.hz
+            };
+            let hz = center.max(self.config.min_hz).min(self.max_hz);
+            self.notches[axis][slot].set_coeffs(BiquadCoeffs::notch(
+                hz,
+                self.config.q,
+                self.sample_hz,
+            ));
+        }
+    }
+}
+
+impl Filter<Vector3<f32>, Vector3<f32>> for DynNotch {
+    fn do_filter(&mut self, input: Vector3<f32>, output: &mut Vector3<f32>) {
+        if !self.enabled {
+            *output = input;
+            return;
+        }
+        self.push(&input);
+        let mut v = input;
+        for axis in 0..3 {
+            for (notch, &center) in self.notches[axis].iter_mut().zip(self.centers[axis].iter()) {
+                if center > 0.0 {
+                    v[axis] = notch.apply(v[axis]);
+                }
+            }
+        }
+        *output = v;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn twiddle() -> [(f32, f32); FFT_SIZE / 2] {
+        let mut twiddle = [(0.0, 0.0); FFT_SIZE / 2];
+        for (k, t) in twiddle.iter_mut().enumerate() {
+            let a = 2.0 * PI * k as f32 / FFT_SIZE as f32;
+            *t = (libm::cosf(a), -libm::sinf(a));
+        }
+        twiddle
+    }
+
+    //加汉宁窗后的功率谱
+    fn spectrum(tones: &[(f32, f32)], fs: f32) -> [f32; FFT_SIZE / 2] {
+        let mut re = [0.0; FFT_SIZE];
+        let mut im = [0.0; FFT_SIZE];
+        for (i, r) in re.iter_mut().enumerate() {
+            let w = 0.5 - 0.5 * libm::cosf(2.0 * PI * i as f32 / FFT_SIZE as f32);
+            let t = i as f32 / fs;
+            *r = w * tones
+                .iter()
+                .map(|(hz, amp)| amp * libm::sinf(2.0 * PI * hz * t))
+                .sum::<f32>();
+        }
+        fft(&mut re, &mut im, &twiddle());
+        let mut power = [0.0; FFT_SIZE / 2];
+        for (i, p) in power.iter_mut().enumerate() {
+            *p = re[i] * re[i] + im[i] * im[i];
+        }
+        power
+    }
+
+    #[test]
+    fn fft_matches_dft() {
+        let mut re = [0.0; FFT_SIZE];
+        let mut im = [0.0; FFT_SIZE];
+        for (i, r) in re.iter_mut().enumerate() {
+            *r = libm::sinf(i as f32 * 0.7) + 0.3 * libm::cosf(i as f32 * 2.1) + 0.1;
+        }
+        let input = re;
+        fft(&mut re, &mut im, &twiddle());
+        for k in 0..FFT_SIZE {
+            let (mut dr, mut di) = (0.0, 0.0);
+            for (n, x) in input.iter().enumerate() {
+                let a = 2.0 * PI * (k * n % FFT_SIZE) as f32 / FFT_SIZE as f32;
+                dr += x * libm::cosf(a);
+                di -= x * libm::sinf(a);
+            }
+            assert!((re[k] - dr).abs() < 1e-3 && (im[k] - di).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn one_peak() {
+        let fs = 1000.0;
+        let bin_hz = fs / FFT_SIZE as f32;
+        let power = spectrum(&[(220.0, 1.0)], fs);
+        let mut peaks = [Peak::default(); MAX_PEAKS];
+        let n = find_peaks(&power, bin_hz, 150.0, 450.0, &mut peaks);
+        assert_eq!(n, 1);
+        //插值后误差小于频点间隔的四分之一
+        assert!((peaks[0].hz - 220.0).abs() < bin_hz / 4.0);
+        //频段以外的峰值不算
+        assert_eq!(find_peaks(&power, bin_hz, 250.0, 450.0, &mut peaks), 0);
+    }
+
+    #[test]
+    fn two_peaks() {
+        let fs = 1000.0;
+        let bin_hz = fs / FFT_SIZE as f32;
+        let power = spectrum(&[(370.0, 0.5), (180.0, 1.0)], fs);
+        let mut peaks = [Peak::default(); MAX_PEAKS];
+        let n = find_peaks(&power, bin_hz, 150.0, 450.0, &mut peaks);
+        assert_eq!(n, 2);
+        //按频率排列
+        assert!((peaks[0].hz - 180.0).abs() < bin_hz / 4.0);
+        assert!((peaks[1].hz - 370.0).abs() < bin_hz / 4.0);
+        assert!(peaks[0].power > peaks[1].power);
+        //只有一个位置时保留最强的
+        let n = find_peaks(&power, bin_hz, 150.0, 450.0, &mut peaks[..1]);
+        assert_eq!(n, 1);
+        assert!((peaks[0].hz - 180.0).abs() < bin_hz / 4.0);
+    }
+
+    //输入正弦，返回最后一秒的输出峰值
+    fn run(notch: &mut DynNotch, hz: f32, fs: f32, seconds: usize) -> f32 {
+        let mut output = Vector3::zeros();
+        let mut peak = 0.0f32;
+        let n = fs as usize * seconds;
+        for i in 0..n {
+            let x = libm::sinf(2.0 * PI * hz * i as f32 / fs);
+            notch.do_filter(Vector3::repeat(x), &mut output);
+            if i > n - fs as usize {
+                peak = peak.max(output.x.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn tracks_peak() {
+        let fs = 1000.0;
+        let mut notch = DynNotch::new(DynNotchConfig::default(), fs);
+        assert!(run(&mut notch, 220.0, fs, 2) < 0.1);
+        assert!((notch.centers(0)[0] - 220.0).abs() < 3.0);
+        //峰值移动后陷波跟随
+        run(&mut notch, 300.0, fs, 2);
+        assert!((notch.centers(0)[0] - 300.0).abs() < 3.0);
+    }
+
+    #[test]
+    fn enabled_at_imu_rate() {
+        let notch = DynNotch::new(DynNotchConfig::default(), 1000.0);
+        assert!(notch.enabled);
+        assert_eq!(notch.decimate, 1);
+        assert_eq!(notch.max_hz, 450.0);
+        let notch = DynNotch::new(DynNotchConfig::default(), 100.0);
+        assert!(!notch.enabled);
+    }
+}
@@ -1,12 +1,513 @@
-use nalgebra::{Point3, Vector3};
+//! ### 误差状态卡尔曼滤波(ESKF)组合导航
+//!
+//! 名义状态为位置、速度(北东地，原点为导航起点)、姿态(机体前右下到北东地的四元数)、
+//! 陀螺仪零偏和加速度计零偏，由IMU数据积分传播；误差状态为15维，只用来估计名义状态的误差，
+//! 协方差按误差状态的线性模型传播。GPS位置速度、气压计高度、磁力计航向到达时做校正，
+//! 把估计出的误差注入名义状态后误差清零。
+//!
+//! 姿态误差定义在机体系：q = q̂ ⊗ Exp(δθ)。注入后的复位雅可比接近单位阵，忽略。
+//! 参考: Joan Solà, Quaternion kinematics for the error-state Kalman filter
+//!
+use super::klf::{finite, KalmanError};
+use nalgebra::{Cholesky, Matrix3, SMatrix, SVector, UnitQuaternion, Vector3};
 
-pub struct ErrorStateKalmanFilter {
-    filter: eskf::ESKF,
+/// 重力加速度，单位m/s²
+pub const GRAVITY: f32 = 9.80665;
+
+/// 误差状态维数
+pub const ERROR_STATES: usize = 15;
+//误差状态中各量的起始下标
+const P: usize = 0;
+const V: usize = 3;
+const TH: usize = 6;
+const BG: usize = 9;
+const BA: usize = 12;
+
+/// 误差状态协方差
+pub type Covariance = SMatrix<f32, ERROR_STATES, ERROR_STATES>;
+
+/// ESKF参数，噪声为连续时间的噪声密度
+#[derive(Debug, Clone, Copy)]
+pub struct EskfConfig {
+    pub accel_noise: f32,     //加速度计噪声，单位m/s²/√Hz
+    pub gyro_noise: f32,      //陀螺仪噪声，单位rad/s/√Hz
+    pub accel_bias_walk: f32, //加速度计零偏随机游走，单位m/s³/√Hz
+    pub gyro_bias_walk: f32,  //陀螺仪零偏随机游走，单位rad/s²/√Hz
+    pub gate: f32,            //新息门限，单位为标准差的倍数，0为不检查
+    pub declination: f32,     //磁偏角，单位弧度，东偏为正
+    pub gps_pos_sd: f32,      //GPS没有给出精度时的位置标准差，单位米
+    pub gps_vel_sd: f32,      //GPS没有给出精度时的速度标准差，单位m/s
+    pub baro_sd: f32,         //气压计高度标准差，单位米
+    pub heading_sd: f32,      //磁力计航向标准差，单位弧度
+}
+
+impl EskfConfig {
+    pub const fn new() -> Self {
+        Self {
+            accel_noise: 0.05,
+            gyro_noise: 0.005,
+            accel_bias_walk: 0.001,
+            gyro_bias_walk: 0.0001,
+            gate: 5.0,
+            declination: 0.0,
+            gps_pos_sd: 2.5,
+            gps_vel_sd: 0.3,
+            baro_sd: 0.5,
+            heading_sd: 0.1,
+        }
+    }
+}
+
+impl Default for EskfConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 误差状态卡尔曼滤波
+#[derive(Debug, Clone)]
+pub struct Eskf {
+    config: EskfConfig,
+    p: Vector3<f32>, //位置，北东地，单位米
+    v: Vector3<f32>, //速度，北东地，单位m/s
+    q: UnitQuaternion<f32>,
+    bg: Vector3<f32>, //陀螺仪零偏，单位rad/s
+    ba: Vector3<f32>, //加速度计零偏，单位m/s²
+    cov: Covariance,
 }
 
-impl ErrorStateKalmanFilter {
-    pub fn new() -> Self {
-        let filter = eskf::Builder::new().build();
-        Self { filter }
+impl Eskf {
+    /// 初始姿态由initial_attitude得到，位置速度为0
+    pub fn new(config: EskfConfig, attitude: UnitQuaternion<f32>) -> Self {
+        let mut diag = SVector::<f32, ERROR_STATES>::zeros();
+        diag.fixed_rows_mut::<3>(P).fill(100.0);
+        diag.fixed_rows_mut::<3>(V).fill(1.0);
+        diag.fixed_rows_mut::<3>(TH).fill(0.01);
+        diag[TH + 2] = 1.0;
+        diag.fixed_rows_mut::<3>(BG).fill(1e-4);
+        diag.fixed_rows_mut::<3>(BA).fill(0.04);
+        Self {
+            config,
+            p: Vector3::zeros(),
+            v: Vector3::zeros(),
+            q: attitude,
+            bg: Vector3::zeros(),
+            ba: Vector3::zeros(),
+            cov: Covariance::from_diagonal(&diag),
+        }
+    }
+
+    pub fn config(&self) -> &EskfConfig {
+        &self.config
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.p
+    }
+
+    pub fn velocity(&self) -> Vector3<f32> {
+        self.v
+    }
+
+    pub fn attitude(&self) -> UnitQuaternion<f32> {
+        self.q
+    }
+
+    pub fn gyro_bias(&self) -> Vector3<f32> {
+        self.bg
+    }
+
+    pub fn accel_bias(&self) -> Vector3<f32> {
+        self.ba
+    }
+
+    pub fn covariance(&self) -> &Covariance {
+        &self.cov
+    }
+
+    /// 直接设置位置，清除位置与其他状态的相关性，sd为标准差，单位米
+    pub fn set_position(&mut self, p: Vector3<f32>, sd: f32) {
+        self.p = p;
+        self.reset_block(P, sd * sd);
+    }
+
+    /// 直接设置速度，sd为标准差，单位m/s
+    pub fn set_velocity(&mut self, v: Vector3<f32>, sd: f32) {
+        self.v = v;
+        self.reset_block(V, sd * sd);
+    }
+
+    fn reset_block(&mut self, i: usize, var: f32) {
+        self.cov.fixed_rows_mut::<3>(i).fill(0.0);
+        self.cov.fixed_columns_mut::<3>(i).fill(0.0);
+        self.cov
+            .fixed_slice_mut::<3, 3>(i, i)
+            .copy_from(&(Matrix3::identity() * var));
+    }
+
+    /// IMU传播，accel为比力(静止水平时为(0,0,-g))，单位m/s²，gyro单位rad/s，均为机体前右下，dt单位秒
+    pub fn predict(
+        &mut self,
+        accel: &Vector3<f32>,
+        gyro: &Vector3<f32>,
+        dt: f32,
+    ) -> Result<(), KalmanError> {
+        if !dt.is_finite() || dt < 0.0 {
+            return Err(KalmanError::InvalidDt);
+        }
+        if !finite(accel.iter().chain(gyro.iter())) {
+            return Err(KalmanError::NotFinite);
+        }
+        let r = *self.q.to_rotation_matrix().matrix();
+        let a_b = accel - self.ba;
+        let w = gyro - self.bg;
+        let a_n = r * a_b + Vector3::new(0.0, 0.0, GRAVITY);
+        //名义状态
+        self.p += self.v * dt + a_n * (0.5 * dt * dt);
+        self.v += a_n * dt;
+        let dq = UnitQuaternion::from_scaled_axis(w * dt);
+        self.q *= dq;
+        //误差状态转移矩阵
+        let mut f = Covariance::identity();
+        f.fixed_slice_mut::<3, 3>(P, V)
+            .copy_from(&(Matrix3::identity() * dt));
+        f.fixed_slice_mut::<3, 3>(V, TH)
+            .copy_from(&(-r * skew(&a_b) * dt));
+        f.fixed_slice_mut::<3, 3>(V, BA).copy_from(&(-r * dt));
+        f.fixed_slice_mut::<3, 3>(TH, TH)
+            .copy_from(&dq.to_rotation_matrix().matrix().transpose());
+        f.fixed_slice_mut::<3, 3>(TH, BG)
+            .copy_from(&(Matrix3::identity() * -dt));
+        let c = &self.config;
+        let mut q = SVector::<f32, ERROR_STATES>::zeros();
+        q.fixed_rows_mut::<3>(V)
+            .fill(c.accel_noise * c.accel_noise * dt);
+        q.fixed_rows_mut::<3>(TH)
+            .fill(c.gyro_noise * c.gyro_noise * dt);
+        q.fixed_rows_mut::<3>(BG)
+            .fill(c.gyro_bias_walk * c.gyro_bias_walk * dt);
+        q.fixed_rows_mut::<3>(BA)
+            .fill(c.accel_bias_walk * c.accel_bias_walk * dt);
+        let cov = f * self.cov * f.transpose() + Covariance::from_diagonal(&q);
+        self.cov = (cov + cov.transpose()) * 0.5;
+        Ok(())
+    }
+
+    /// GPS位置校正，z为相对原点的北东地位置，sd为标准差，单位米
+    pub fn update_position(&mut self, z: &Vector3<f32>, sd: f32) -> Result<(), KalmanError> {
+        let mut h = SMatrix::<f32, 3, ERROR_STATES>::zeros();
+        h.fixed_slice_mut::<3, 3>(0, P)
+            .copy_from(&Matrix3::identity());
+        self.correct(&h, &(z - self.p), &(Matrix3::identity() * sd * sd))
+    }
+
+    /// GPS速度校正，z为北东地速度，sd为标准差，单位m/s
+    pub fn update_velocity(&mut self, z: &Vector3<f32>, sd: f32) -> Result<(), KalmanError> {
+        let mut h = SMatrix::<f32, 3, ERROR_STATES>::zeros();
+        h.fixed_slice_mut::<3, 3>(0, V)
+            .copy_from(&Matrix3::identity());
+        self.correct(&h, &(z - self.v), &(Matrix3::identity() * sd * sd))
+    }
+
+    /// 气压计高度校正，altitude为相对原点的高度，向上为正，sd为标准差，单位米
+    pub fn update_altitude(&mut self, altitude: f32, sd: f32) -> Result<(), KalmanError> {
+        let mut h = SMatrix::<f32, 1, ERROR_STATES>::zeros();
+        h[P + 2] = -1.0;
+        let y = SVector::<f32, 1>::new(altitude + self.p.z);
+        self.correct(&h, &y, &SMatrix::<f32, 1, 1>::new(sd * sd))
+    }
+
+    /// 磁力计航向校正，mag为机体系磁场，单位任意，sd为标准差，单位弧度。
+    /// 用当前的横滚俯仰做倾斜补偿，磁场水平分量为0时返回Singular
+    pub fn update_heading(&mut self, mag: &Vector3<f32>, sd: f32) -> Result<(), KalmanError> {
+        let (roll, pitch, yaw) = self.q.euler_angles();
+        let tilt = UnitQuaternion::from_euler_angles(roll, pitch, 0.0);
+        let heading = heading(&tilt, mag, self.config.declination)?;
+        //绕北东地z轴的转角，对机体系姿态误差的导数为旋转矩阵的第三行
+        let r = self.q.to_rotation_matrix();
+        let mut h = SMatrix::<f32, 1, ERROR_STATES>::zeros();
+        h.fixed_slice_mut::<1, 3>(0, TH)
+            .copy_from(&r.matrix().row(2));
+        let y = SVector::<f32, 1>::new(wrap_pi(heading - yaw));
+        self.correct(&h, &y, &SMatrix::<f32, 1, 1>::new(sd * sd))
+    }
+
+    //卡尔曼校正，y为新息，校正后把误差注入名义状态
+    fn correct<const M: usize>(
+        &mut self,
+        h: &SMatrix<f32, M, ERROR_STATES>,
+        y: &SVector<f32, M>,
+        r: &SMatrix<f32, M, M>,
+    ) -> Result<(), KalmanError> {
+        if !finite(y.iter().chain(r.iter())) {
+            return Err(KalmanError::NotFinite);
+        }
+        let hp = h * self.cov;
+        let s = hp * h.transpose() + r;
+        let chol = Cholesky::new(s).ok_or(KalmanError::Singular)?;
+        //归一化新息平方超过门限认为是野值
+        let gate = self.config.gate;
+        if gate > 0.0 && y.dot(&chol.solve(y)) > gate * gate * M as f32 {
+            return Err(KalmanError::Outlier);
+        }
+        let k = chol.solve(&hp).transpose();
+        let dx = k * y;
+        let ikh = Covariance::identity() - k * h;
+        let cov = ikh * self.cov * ikh.transpose() + k * r * k.transpose();
+        if !finite(dx.iter().chain(cov.iter())) {
+            return Err(KalmanError::NotFinite);
+        }
+        self.cov = (cov + cov.transpose()) * 0.5;
+        //注入
+        self.p += dx.fixed_rows::<3>(P);
+        self.v += dx.fixed_rows::<3>(V);
+        self.q *= UnitQuaternion::from_scaled_axis(dx.fixed_rows::<3>(TH).into_owned());
+        self.bg += dx.fixed_rows::<3>(BG);
+        self.ba += dx.fixed_rows::<3>(BA);
+        Ok(())
+    }
+}
+
+/// 静止时由加速度计比力算横滚俯仰，有磁力计时算航向，否则航向为0
+pub fn initial_attitude(
+    accel: &Vector3<f32>,
+    mag: Option<&Vector3<f32>>,
+    declination: f32,
+) -> UnitQuaternion<f32> {
+    let roll = libm::atan2f(-accel.y, -accel.z);
+    let pitch = libm::atan2f(accel.x, libm::sqrtf(accel.y * accel.y + accel.z * accel.z));
+    let tilt = UnitQuaternion::from_euler_angles(roll, pitch, 0.0);
+    let yaw = mag
+        .and_then(|m| heading(&tilt, m, declination).ok())
+        .unwrap_or(0.0);
+    UnitQuaternion::from_euler_angles(roll, pitch, yaw)
+}
+
+//tilt为去掉航向的姿态，返回真北航向
+fn heading(
+    tilt: &UnitQuaternion<f32>,
+    mag: &Vector3<f32>,
+    declination: f32,
+) -> Result<f32, KalmanError> {
+    let m = tilt * mag;
+    if !finite(m.iter()) {
+        return Err(KalmanError::NotFinite);
+    }
+    if m.x * m.x + m.y * m.y < 1e-12 {
+        return Err(KalmanError::Singular);
+    }
+    Ok(wrap_pi(libm::atan2f(-m.y, m.x) + declination))
+}
+
+//反对称矩阵，skew(a)·b = a×b
+fn skew(v: &Vector3<f32>) -> Matrix3<f32> {
+    Matrix3::new(0.0, -v.z, v.y, v.z, 0.0, -v.x, -v.y, v.x, 0.0)
+}
+
+//角度归一化到-π~π
+fn wrap_pi(a: f32) -> f32 {
+    libm::atan2f(libm::sinf(a), libm::cosf(a))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMU_HZ: usize = 100;
+    const GPS_DIV: usize = 20; //5Hz
+    const BARO_DIV: usize = 5; //20Hz
+    const MAG_DIV: usize = 10; //10Hz
+
+    //固定种子的高斯噪声，Box-Muller变换
+    struct Noise(u32);
+
+    impl Noise {
+        fn uniform(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1u32 << 24) as f32
+        }
+
+        fn gaussian(&mut self, sd: f32) -> f32 {
+            let u1 = self.uniform().max(1e-7);
+            let u2 = self.uniform();
+            sd * libm::sqrtf(-2.0 * libm::logf(u1)) * libm::cosf(2.0 * core::f32::consts::PI * u2)
+        }
+
+        fn vector(&mut self, sd: f32) -> Vector3<f32> {
+            Vector3::new(self.gaussian(sd), self.gaussian(sd), self.gaussian(sd))
+        }
+    }
+
+    //水平飞行的真实轨迹：初始速度v0，航向yaw0，偏航角速度rate，速度方向随航向转动
+    struct Trajectory {
+        v0: f32,
+        yaw0: f32,
+        rate: f32,
+        p0: Vector3<f32>,
+    }
+
+    impl Trajectory {
+        fn yaw(&self, t: f32) -> f32 {
+            self.yaw0 + self.rate * t
+        }
+
+        fn attitude(&self, t: f32) -> UnitQuaternion<f32> {
+            UnitQuaternion::from_euler_angles(0.0, 0.0, self.yaw(t))
+        }
+
+        fn velocity(&self, t: f32) -> Vector3<f32> {
+            let yaw = self.yaw(t);
+            Vector3::new(libm::cosf(yaw), libm::sinf(yaw), 0.0) * self.v0
+        }
+
+        fn position(&self, t: f32) -> Vector3<f32> {
+            if self.rate == 0.0 {
+                return self.p0 + self.velocity(0.0) * t;
+            }
+            let k = self.v0 / self.rate;
+            let (y0, y) = (self.yaw0, self.yaw(t));
+            self.p0
+                + Vector3::new(
+                    k * (libm::sinf(y) - libm::sinf(y0)),
+                    k * (libm::cosf(y0) - libm::cosf(y)),
+                    0.0,
+                )
+        }
+
+        //机体系比力
+        fn specific_force(&self, t: f32) -> Vector3<f32> {
+            let yaw = self.yaw(t);
+            let a = Vector3::new(-libm::sinf(yaw), libm::cosf(yaw), 0.0) * (self.v0 * self.rate);
+            self.attitude(t).inverse() * (a - Vector3::new(0.0, 0.0, GRAVITY))
+        }
+    }
+
+    //北、下方向的地磁场，单位高斯
+    fn earth_field() -> Vector3<f32> {
+        Vector3::new(0.2, 0.0, 0.4)
+    }
+
+    //返回后一半时间的位置、速度均方根误差和最大姿态误差
+    fn fly(traj: &Trajectory, seconds: usize) -> (f32, f32, f32) {
+        let dt = 1.0 / IMU_HZ as f32;
+        let mut noise = Noise(0x1234_5678);
+        let (accel_sd, gyro_sd) = (0.05, 0.003);
+        let (gps_sd, vel_sd, baro_sd, mag_sd) = (1.0, 0.2, 0.3, 0.005);
+        let gyro_bias = Vector3::new(0.01, -0.005, 0.003);
+
+        let mag =
+            |t: f32, n: &mut Noise| traj.attitude(t).inverse() * earth_field() + n.vector(mag_sd);
+        let config = EskfConfig::new();
+        let attitude =
+            initial_attitude(&traj.specific_force(0.0), Some(&mag(0.0, &mut noise)), 0.0);
+        let mut eskf = Eskf::new(config, attitude);
+
+        let steps = seconds * IMU_HZ;
+        let (mut pos_sum, mut vel_sum, mut att_max, mut count) = (0.0, 0.0, 0.0f32, 0);
+        for i in 1..=steps {
+            //IMU取这一步中点的值
+            let t = (i as f32 - 0.5) * dt;
+            let accel = traj.specific_force(t) + noise.vector(accel_sd);
+            let gyro = Vector3::new(0.0, 0.0, traj.rate) + gyro_bias + noise.vector(gyro_sd);
+            eskf.predict(&accel, &gyro, dt).unwrap();
+            let t = i as f32 * dt;
+            if i % MAG_DIV == 0 {
+                eskf.update_heading(&mag(t, &mut noise), config.heading_sd)
+                    .ok();
+            }
+            if i % BARO_DIV == 0 {
+                let altitude = -traj.position(t).z + noise.gaussian(baro_sd);
+                eskf.update_altitude(altitude, baro_sd).ok();
+            }
+            if i % GPS_DIV == 0 {
+                let p = traj.position(t) + noise.vector(gps_sd);
+                eskf.update_position(&p, gps_sd).ok();
+                let v = traj.velocity(t) + noise.vector(vel_sd);
+                eskf.update_velocity(&v, vel_sd).ok();
+            }
+            if i > steps / 2 {
+                pos_sum += (eskf.position() - traj.position(t)).norm_squared();
+                vel_sum += (eskf.velocity() - traj.velocity(t)).norm_squared();
+                att_max = att_max.max(eskf.attitude().angle_to(&traj.attitude(t)));
+                count += 1;
+            }
+        }
+        //陀螺仪零偏收敛
+        assert!((eskf.gyro_bias() - gyro_bias).norm() < 0.003);
+        (
+            libm::sqrtf(pos_sum / count as f32),
+            libm::sqrtf(vel_sum / count as f32),
+            att_max.to_degrees(),
+        )
+    }
+
+    #[test]
+    fn static_level() {
+        let traj = Trajectory {
+            v0: 0.0,
+            yaw0: 0.5,
+            rate: 0.0,
+            p0: Vector3::zeros(),
+        };
+        let (pos, vel, att) = fly(&traj, 60);
+        assert!(pos < 0.6, "position rms {}", pos);
+        assert!(vel < 0.15, "velocity rms {}", vel);
+        assert!(att < 1.0, "attitude error {}", att);
+    }
+
+    #[test]
+    fn constant_velocity() {
+        let traj = Trajectory {
+            v0: 5.0,
+            yaw0: -1.0,
+            rate: 0.0,
+            p0: Vector3::new(0.0, 0.0, -10.0),
+        };
+        let (pos, vel, att) = fly(&traj, 60);
+        assert!(pos < 0.6, "position rms {}", pos);
+        assert!(vel < 0.15, "velocity rms {}", vel);
+        assert!(att < 1.0, "attitude error {}", att);
+    }
+
+    #[test]
+    fn turning() {
+        //半径20米，一圈约25秒
+        let traj = Trajectory {
+            v0: 5.0,
+            yaw0: 0.0,
+            rate: 0.25,
+            p0: Vector3::new(0.0, 0.0, -10.0),
+        };
+        let (pos, vel, att) = fly(&traj, 60);
+        assert!(pos < 0.6, "position rms {}", pos);
+        assert!(vel < 0.2, "velocity rms {}", vel);
+        assert!(att < 2.0, "attitude error {}", att);
+    }
+
+    #[test]
+    fn initial_attitude_from_accel_and_mag() {
+        let q = UnitQuaternion::from_euler_angles(0.2, -0.1, 1.0);
+        let accel = q.inverse() * Vector3::new(0.0, 0.0, -GRAVITY);
+        let mag = q.inverse() * earth_field();
+        let estimated = initial_attitude(&accel, Some(&mag), 0.0);
+        assert!(estimated.angle_to(&q) < 1e-4);
+    }
+
+    #[test]
+    fn rejects_outlier() {
+        let mut eskf = Eskf::new(EskfConfig::new(), UnitQuaternion::identity());
+        eskf.set_position(Vector3::zeros(), 0.5);
+        let p = eskf.position();
+        assert_eq!(
+            eskf.update_position(&Vector3::new(100.0, 0.0, 0.0), 0.5),
+            Err(KalmanError::Outlier)
+        );
+        assert_eq!(eskf.position(), p);
+        assert_eq!(
+            eskf.predict(&Vector3::zeros(), &Vector3::zeros(), -0.01),
+            Err(KalmanError::InvalidDt)
+        );
     }
 }
@@ -3,44 +3,258 @@
 //! 考了上次滤波数据，因此滤波阶数越高，滤掉毛刺噪声的能力越强，
 //! 但对应的数据实时性会变差
 //!
+//! 每一节是直接II型转置的二阶节(biquad)，系数按截止频率和采样频率设计：
+//! 二阶巴特沃斯低通、陷波、带通，以及一到三个一阶低通串联的PT1/PT2/PT3。
+//! PT3是三阶，需要两节，其余都只用一节。PT1系列用k=dt/(rc+dt)离散化，截止频率远低于采样频率时才准确。
+//!
 use super::Filter;
+use core::f32::consts::PI;
 use nalgebra::Vector3;
 
+/// 二阶节系数，a0已归一化为1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoeffs {
+    pub b0: f32,
+    pub b1: f32,
+    pub b2: f32,
+    pub a1: f32,
+    pub a2: f32,
+}
+
+impl BiquadCoeffs {
+    /// 直通
+    pub const fn passthrough() -> Self {
+        Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        }
+    }
+
+    //RBJ音频滤波器设计，返回w0的cos和alpha
+    fn rbj(hz: f32, q: f32, sample_hz: f32) -> (f32, f32) {
+        let w0 = 2.0 * PI * hz / sample_hz;
+        (libm::cosf(w0), libm::sinf(w0) / (2.0 * q))
+    }
+
+    //按a0归一化
+    fn normalize(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// 二阶巴特沃斯低通
+    pub fn lowpass(cutoff_hz: f32, sample_hz: f32) -> Self {
+        let (cos, alpha) = Self::rbj(cutoff_hz, core::f32::consts::FRAC_1_SQRT_2, sample_hz);
+        let b = (1.0 - cos) / 2.0;
+        Self::normalize(b, 1.0 - cos, b, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// 陷波，center_hz为中心频率，q为品质因数，越大陷波越窄
+    pub fn notch(center_hz: f32, q: f32, sample_hz: f32) -> Self {
+        let (cos, alpha) = Self::rbj(center_hz, q, sample_hz);
+        Self::normalize(1.0, -2.0 * cos, 1.0, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// 带通，中心频率增益为1
+    pub fn bandpass(center_hz: f32, q: f32, sample_hz: f32) -> Self {
+        let (cos, alpha) = Self::rbj(center_hz, q, sample_hz);
+        Self::normalize(alpha, 0.0, -alpha, 1.0 + alpha, -2.0 * cos, 1.0 - alpha)
+    }
+
+    /// 一阶低通，放在二阶节里，b2、a2为0
+    pub fn pt1(cutoff_hz: f32, sample_hz: f32) -> Self {
+        let k = pt1_gain(cutoff_hz, sample_hz);
+        Self {
+            b0: k,
+            b1: 0.0,
+            b2: 0.0,
+            a1: k - 1.0,
+            a2: 0.0,
+        }
+    }
+
+    /// 两个相同的一阶低通串联
+    fn pt1_squared(k: f32) -> Self {
+        let p = 1.0 - k;
+        Self {
+            b0: k * k,
+            b1: 0.0,
+            b2: 0.0,
+            a1: -2.0 * p,
+            a2: p * p,
+        }
+    }
+
+    /// 0Hz的增益
+    pub fn dc_gain(&self) -> f32 {
+        (self.b0 + self.b1 + self.b2) / (1.0 + self.a1 + self.a2)
+    }
+}
+
+//一阶低通系数，k=dt/(rc+dt)
+fn pt1_gain(cutoff_hz: f32, sample_hz: f32) -> f32 {
+    let rc = 1.0 / (2.0 * PI * cutoff_hz);
+    let dt = 1.0 / sample_hz;
+    dt / (rc + dt)
+}
+
+/// 直接II型转置的二阶节
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    coeffs: BiquadCoeffs,
+    z1: f32,
+    z2: f32,
+}
+
+impl Biquad {
+    pub const fn new(coeffs: BiquadCoeffs) -> Self {
+        Self {
+            coeffs,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    pub fn coeffs(&self) -> &BiquadCoeffs {
+        &self.coeffs
+    }
+
+    /// 修改系数，保留状态，用于动态调整频率
+    pub fn set_coeffs(&mut self, coeffs: BiquadCoeffs) {
+        self.coeffs = coeffs;
+    }
+
+    pub fn reset(&mut self) {
+        self.z1 = 0.0;
+        self.z2 = 0.0;
+    }
+
+    pub fn apply(&mut self, x: f32) -> f32 {
+        let c = &self.coeffs;
+        let y = c.b0 * x + self.z1;
+        self.z1 = c.b1 * x - c.a1 * y + self.z2;
+        self.z2 = c.b2 * x - c.a2 * y;
+        y
+    }
+}
+
+/// 最多二阶节数
+pub const MAX_STAGES: usize = 2;
+
+/// 由一到两节二阶节串联的IIR滤波器
 #[derive(Debug, Clone, Copy)]
 pub struct IIRFilter {
-    last_in: f32,  //上一次输入
-    last_out: f32, //上一次输出
-    n: f32,        //阶数
+    stages: [Biquad; MAX_STAGES],
+    count: usize,
 }
 
 impl IIRFilter {
-    pub const fn new(n: f32) -> Self {
+    /// 由一节系数组成
+    pub const fn new(coeffs: BiquadCoeffs) -> Self {
         Self {
-            last_in: 0.0,
-            last_out: 0.0,
-            n,
+            stages: [
+                Biquad::new(coeffs),
+                Biquad::new(BiquadCoeffs::passthrough()),
+            ],
+            count: 1,
         }
     }
+
+    /// 二阶巴特沃斯低通
+    pub fn lowpass(cutoff_hz: f32, sample_hz: f32) -> Self {
+        Self::new(BiquadCoeffs::lowpass(cutoff_hz, sample_hz))
+    }
+
+    /// 陷波
+    pub fn notch(center_hz: f32, q: f32, sample_hz: f32) -> Self {
+        Self::new(BiquadCoeffs::notch(center_hz, q, sample_hz))
+    }
+
+    /// 带通
+    pub fn bandpass(center_hz: f32, q: f32, sample_hz: f32) -> Self {
+        Self::new(BiquadCoeffs::bandpass(center_hz, q, sample_hz))
+    }
+
+    /// 一阶低通
+    pub fn pt1(cutoff_hz: f32, sample_hz: f32) -> Self {
+        Self::new(BiquadCoeffs::pt1(cutoff_hz, sample_hz))
+    }
+
+    /// 两个一阶低通串联，每节截止频率放大，使整体-3dB点接近cutoff_hz
+    pub fn pt2(cutoff_hz: f32, sample_hz: f32) -> Self {
+        let k = pt1_gain(cutoff_hz * 1.553774, sample_hz);
+        Self::new(BiquadCoeffs::pt1_squared(k))
+    }
+
+    /// 三个一阶低通串联，每节截止频率放大，使整体-3dB点接近cutoff_hz
+    pub fn pt3(cutoff_hz: f32, sample_hz: f32) -> Self {
+        let hz = cutoff_hz * 1.961459;
+        let k = pt1_gain(hz, sample_hz);
+        let mut filter = Self::new(BiquadCoeffs::pt1_squared(k));
+        filter.stages[1] = Biquad::new(BiquadCoeffs::pt1(hz, sample_hz));
+        filter.count = 2;
+        filter
+    }
+
+    /// 只有一节时修改系数，保留状态，用于动态调整频率
+    pub fn set_coeffs(&mut self, coeffs: BiquadCoeffs) {
+        self.stages[0].set_coeffs(coeffs);
+    }
+
+    /// 0Hz的增益
+    pub fn dc_gain(&self) -> f32 {
+        self.stages[..self.count]
+            .iter()
+            .map(|s| s.coeffs().dc_gain())
+            .product()
+    }
+
+    pub fn reset(&mut self) {
+        self.stages.iter_mut().for_each(|s| s.reset());
+    }
+
+    pub fn apply(&mut self, x: f32) -> f32 {
+        self.stages[..self.count]
+            .iter_mut()
+            .fold(x, |x, s| s.apply(x))
+    }
 }
 
 impl Filter<f32, f32> for IIRFilter {
     fn do_filter(&mut self, input: f32, output: &mut f32) {
-        //TODO
-        self.last_in = input;
-        self.last_out = *output;
+        *output = self.apply(input);
     }
 }
 
+/// 三轴各一个相同设计的滤波器
 pub struct IIRFilter3 {
     filters: [IIRFilter; 3],
 }
 
 impl IIRFilter3 {
-    pub fn new(n: f32) -> Self {
-        let filters = [IIRFilter::new(n); 3];
+    pub fn new(filter: IIRFilter) -> Self {
+        let filters = [filter; 3];
         Self { filters }
     }
+
+    /// 三轴同时修改系数，保留状态
+    pub fn set_coeffs(&mut self, coeffs: BiquadCoeffs) {
+        self.filters.iter_mut().for_each(|f| f.set_coeffs(coeffs));
+    }
+
+    pub fn reset(&mut self) {
+        self.filters.iter_mut().for_each(|f| f.reset());
+    }
 }
+
 impl Filter<Vector3<f32>, Vector3<f32>> for IIRFilter3 {
     fn do_filter(&mut self, input: Vector3<f32>, output: &mut Vector3<f32>) {
         self.filters
@@ -49,3 +263,69 @@ impl Filter<Vector3<f32>, Vector3<f32>> for IIRFilter3 {
             .for_each(|(i, f)| f.do_filter(input[i], &mut output[i]))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FS: f32 = 1000.0;
+
+    //正弦输入稳定后的输出幅值，前一秒丢掉，后一秒与正弦、余弦求相关，不受采样点相位影响
+    fn amplitude(filter: &mut IIRFilter, hz: f32) -> f32 {
+        let n = FS as usize;
+        let (mut i_sum, mut q_sum) = (0.0, 0.0);
+        for i in 0..n * 2 {
+            let phase = 2.0 * PI * hz * i as f32 / FS;
+            let y = filter.apply(libm::sinf(phase));
+            if i >= n {
+                i_sum += y * libm::sinf(phase);
+                q_sum += y * libm::cosf(phase);
+            }
+        }
+        2.0 * libm::sqrtf(i_sum * i_sum + q_sum * q_sum) / n as f32
+    }
+
+    #[test]
+    fn lowpass_unity_dc_gain() {
+        for mut filter in [
+            IIRFilter::lowpass(80.0, FS),
+            IIRFilter::pt1(80.0, FS),
+            IIRFilter::pt2(80.0, FS),
+            IIRFilter::pt3(80.0, FS),
+        ] {
+            assert!((filter.dc_gain() - 1.0).abs() < 1e-4);
+            //阶跃响应稳定在1
+            let y = (0..1000).fold(0.0, |_, _| filter.apply(1.0));
+            assert!((y - 1.0).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn lowpass_cutoff() {
+        let half_power = core::f32::consts::FRAC_1_SQRT_2;
+        assert!((amplitude(&mut IIRFilter::lowpass(50.0, FS), 50.0) - half_power).abs() < 0.02);
+        //PT2、PT3每节截止频率放大后整体-3dB点接近设定值，截止频率远低于采样频率时才准确
+        assert!((amplitude(&mut IIRFilter::pt2(10.0, FS), 10.0) - half_power).abs() < 0.03);
+        assert!((amplitude(&mut IIRFilter::pt3(10.0, FS), 10.0) - half_power).abs() < 0.03);
+        assert!(amplitude(&mut IIRFilter::lowpass(50.0, FS), 400.0) < 0.02);
+    }
+
+    #[test]
+    fn notch_attenuates_center() {
+        let mut filter = IIRFilter::notch(200.0, 5.0, FS);
+        assert!((filter.dc_gain() - 1.0).abs() < 1e-4);
+        //中心频率衰减超过40dB
+        assert!(amplitude(&mut filter, 200.0) < 0.01);
+        filter.reset();
+        assert!(amplitude(&mut filter, 50.0) > 0.95);
+    }
+
+    #[test]
+    fn bandpass_passes_center() {
+        let mut filter = IIRFilter::bandpass(200.0, 5.0, FS);
+        assert!(filter.dc_gain().abs() < 1e-4);
+        assert!((amplitude(&mut filter, 200.0) - 1.0).abs() < 0.02);
+        filter.reset();
+        assert!(amplitude(&mut filter, 50.0) < 0.1);
+    }
+}
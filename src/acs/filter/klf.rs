@@ -1,7 +1,6 @@
 use super::Filter;
-use alloc::vec;
 
-use nalgebra::{DMatrix, DVector, Dynamic, Matrix, VecStorage};
+use nalgebra::{Cholesky, SMatrix, SVector};
 // 卡尔曼滤波的本质是通过k系数来表示更相信哪个值；预估值=值1 + k * (值2 - 值1);
 // 所以核心就是怎么计算k值
 
@@ -96,79 +95,221 @@ impl Filter<(f32, f32), f32> for FusionKalmanFilter {
     }
 }
 
-/// 卡尔曼滤波
+/// 卡尔曼滤波出错，出错时状态和协方差保持不变
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KalmanError {
+    /// 新息协方差H·P·Hᵀ+R不正定，无法求增益
+    Singular,
+    /// 时间间隔为负数或者不是有限值
+    InvalidDt,
+    /// 输入或计算结果中有NaN或无穷大
+    NotFinite,
+    /// 新息超出门限，测量值被当作野值丢弃
+    Outlier,
+}
+
+/// 矩阵卡尔曼滤波，N为状态维数，M为测量维数，U为控制量维数，全部定长，不分配堆内存
+///
+/// 系统模型为连续时间的 x' = A·x + B·u + w，z = H·x + v，每次预测按dt一阶离散化：
+/// F = I + A·dt，G = B·dt，Qd = Q·dt。匀速模型等A幂零的情况离散化是精确的。
+/// 预测和校正分开调用，测量没有到达时可以只预测。
 #[derive(Debug, Clone)]
-pub struct MatrixKalmanFilter {
-    /// 过程激励噪声协方差矩阵（系统过程的协方差）。该参数被用来表示状
-    /// 态转换矩阵与实际过程之间的误差。因为我们无法直接观测到过程信号，
-    ///  所以 Q 的取值是很难确定的。是卡尔曼滤波器用于估计离散时间过程
-    /// 的状态变量，也叫预测模型本身带来的噪声。状态转移协方差矩阵
-    q: DMatrix<f32>,
-
-    /// 测量噪声协方差矩阵。滤波器实际实现时，测量噪声协方差 R一般可以观测得到，是滤波器的已知条件。
-    r: DMatrix<f32>,
-    /// 是将输入转换为状态的矩阵，因为没有输入控制量所以不需要了
-    //b: DMatrix<f32>,
-    /// 状态转移矩阵，实际上是对目标状态转换的一种猜想模型。
-    a: DMatrix<f32>,
-
-    /// 观测器矩阵，是状态变量到测量（观测）的转换矩阵，表示将状态
-    /// 和观测连接起来的关系，卡尔曼滤波里为线性关系，它负责将 m 维
-    /// 的测量值转换到 n 维，使之符合状态变量的数学形式，是滤波的前提条件之一。
-    h: DMatrix<f32>,
-
-    // k: DMatrix<f32>,  //滤波增益矩阵，是滤波的中间计算结果，卡尔曼增益，或卡尔曼系数。
-    // p_: DMatrix<f32>, //先验估计误差协方差矩阵
-    p: DMatrix<f32>, //后验估计误差协方差矩阵
-    // x_: DMatrix<f32>, //先验估计
-    x: DVector<f32>, //后验估计
-                     // qe_sd: DMatrix<f32>, //过程误差标准方差
-                     // re_sd: DMatrix<f32>, //测量误差标准方差
-                     // qe_w: DMatrix<f32>, //状态过程误差
-                     // re_v: DMatrix<f32>, //测量过程误差
+pub struct MatrixKalmanFilter<const N: usize, const M: usize, const U: usize> {
+    /// 状态矩阵，描述状态随时间的变化
+    a: SMatrix<f32, N, N>,
+    /// 控制矩阵，把控制量转换为状态的变化
+    b: SMatrix<f32, N, U>,
+    /// 观测矩阵，把状态转换为测量值
+    h: SMatrix<f32, M, N>,
+    /// 过程噪声功率谱密度，每秒的协方差增量，预测模型本身带来的误差
+    q: SMatrix<f32, N, N>,
+    /// 测量噪声协方差，一般可以由传感器数据测出
+    r: SMatrix<f32, M, M>,
+    x: SVector<f32, N>,    //状态估计
+    p: SMatrix<f32, N, N>, //估计误差协方差
 }
 
-impl MatrixKalmanFilter {
-    pub fn new(q: DMatrix<f32>, r: DMatrix<f32>, a: DMatrix<f32>, h: DMatrix<f32>) -> Self {
+impl<const N: usize, const M: usize, const U: usize> MatrixKalmanFilter<N, M, U> {
+    /// 初始状态为0，初始协方差为单位阵
+    pub fn new(
+        a: SMatrix<f32, N, N>,
+        b: SMatrix<f32, N, U>,
+        h: SMatrix<f32, M, N>,
+        q: SMatrix<f32, N, N>,
+        r: SMatrix<f32, M, M>,
+    ) -> Self {
         Self {
-            q,
-            r,
             a,
+            b,
             h,
-            p: DMatrix::from_vec_storage(VecStorage::new(Dynamic::new(0), Dynamic::new(0), vec![])),
-            x: DVector::from_row_slice(&[]),
+            q,
+            r,
+            x: SVector::zeros(),
+            p: SMatrix::identity(),
+        }
+    }
+
+    pub fn state(&self) -> &SVector<f32, N> {
+        &self.x
+    }
+
+    pub fn set_state(&mut self, x: SVector<f32, N>) {
+        self.x = x;
+    }
+
+    pub fn covariance(&self) -> &SMatrix<f32, N, N> {
+        &self.p
+    }
+
+    pub fn set_covariance(&mut self, p: SMatrix<f32, N, N>) {
+        self.p = p;
+    }
+
+    pub fn set_process_noise(&mut self, q: SMatrix<f32, N, N>) {
+        self.q = q;
+    }
+
+    pub fn set_measurement_noise(&mut self, r: SMatrix<f32, M, M>) {
+        self.r = r;
+    }
+
+    /// 预测，u为控制量，dt单位秒
+    pub fn predict(&mut self, u: &SVector<f32, U>, dt: f32) -> Result<(), KalmanError> {
+        if !dt.is_finite() || dt < 0.0 {
+            return Err(KalmanError::InvalidDt);
+        }
+        let f = SMatrix::<f32, N, N>::identity() + self.a * dt;
+        let x = f * self.x + self.b * (u * dt);
+        let p = f * self.p * f.transpose() + self.q * dt;
+        if !finite(x.iter()) || !finite(p.iter()) {
+            return Err(KalmanError::NotFinite);
         }
+        self.x = x;
+        self.p = p;
+        Ok(())
     }
+
+    /// 校正，z为测量值，协方差用Joseph形式更新，舍入误差下也保持对称半正定
+    pub fn update(&mut self, z: &SVector<f32, M>) -> Result<(), KalmanError> {
+        if !finite(z.iter()) {
+            return Err(KalmanError::NotFinite);
+        }
+        let hp = self.h * self.p;
+        let s = hp * self.h.transpose() + self.r;
+        //K = P·Hᵀ·S⁻¹，S对称，解S·Kᵀ = H·P，不直接求逆
+        let k = Cholesky::new(s)
+            .ok_or(KalmanError::Singular)?
+            .solve(&hp)
+            .transpose();
+        let x = self.x + k * (z - self.h * self.x);
+        let ikh = SMatrix::<f32, N, N>::identity() - k * self.h;
+        let p = ikh * self.p * ikh.transpose() + k * self.r * k.transpose();
+        if !finite(x.iter()) || !finite(p.iter()) {
+            return Err(KalmanError::NotFinite);
+        }
+        self.x = x;
+        self.p = (p + p.transpose()) * 0.5;
+        Ok(())
+    }
+}
+
+pub(super) fn finite<'a>(mut v: impl Iterator<Item = &'a f32>) -> bool {
+    v.all(|x| x.is_finite())
 }
 
-impl Filter<DVector<f32>, DVector<f32>> for MatrixKalmanFilter {
-    fn do_filter(&mut self, z: DVector<f32>, out: &mut DVector<f32>) {
-        // 预测，时间更新方程
-        // 1. 计算先验估计
-        let x_ = self.a.clone() * self.x.clone();
-        // 2. 计算先验估计误差协方差
-        let p_ = self.a.clone() * self.p.clone() * self.a.clone().transpose() + self.q.clone();
-        // 校正，状态更新方差
-        // 3. 计算卡尔曼增益
-        let p_ht = p_.clone() * self.h.clone().transpose();
-        let k = p_ht.clone()
-            * (self.h.clone() * p_ht.clone() + self.r.clone())
-                .try_inverse()
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix1, Matrix2, RowVector2, Vector1, Vector2};
+
+    //固定种子的高斯噪声，Box-Muller变换
+    struct Noise(u32);
+
+    impl Noise {
+        fn uniform(&mut self) -> f32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 17;
+            self.0 ^= self.0 << 5;
+            (self.0 >> 8) as f32 / (1u32 << 24) as f32
+        }
+
+        fn gaussian(&mut self, sd: f32) -> f32 {
+            let u1 = self.uniform().max(1e-7);
+            let u2 = self.uniform();
+            sd * libm::sqrtf(-2.0 * libm::logf(u1)) * libm::cosf(2.0 * core::f32::consts::PI * u2)
+        }
+    }
+
+    //匀速模型，状态为位置和速度，只测量位置
+    fn constant_velocity(q: f32, r: f32) -> MatrixKalmanFilter<2, 1, 1> {
+        MatrixKalmanFilter::new(
+            Matrix2::new(0.0, 1.0, 0.0, 0.0),
+            Vector2::zeros(),
+            RowVector2::new(1.0, 0.0),
+            Matrix2::new(0.0, 0.0, 0.0, q),
+            Matrix1::new(r * r),
+        )
+    }
+
+    #[test]
+    fn tracks_constant_velocity() {
+        let (dt, sd, velocity) = (0.01, 0.5, 2.0);
+        let mut kf = constant_velocity(0.01, sd);
+        let mut noise = Noise(12345);
+        let mut sum = 0.0;
+        let steps = 2000;
+        for i in 1..=steps {
+            kf.predict(&Vector1::zeros(), dt).unwrap();
+            let truth = velocity * i as f32 * dt;
+            kf.update(&Vector1::new(truth + noise.gaussian(sd)))
                 .unwrap();
-        // 4. 计算后验估计
-        let x = x_.clone() + k.clone() * (z.clone() - self.h.clone() * x_.clone());
-
-        // 5. 计算后验误差协方差
-        let mut i = DMatrix::from_vec_storage(VecStorage::new(
-            Dynamic::new(x.row_iter().len()),
-            Dynamic::new(x.column_iter().len()),
-            vec![],
-        ));
-        i.fill_with_identity();
-        let p = (i - k.clone() * self.h.clone()) * p_.clone();
-
-        self.x = x.clone();
-        self.p = p.clone();
-        *out = x.clone();
+            if i > steps / 2 {
+                let e = kf.state()[0] - truth;
+                sum += e * e;
+            }
+        }
+        let rms = libm::sqrtf(sum / (steps / 2) as f32);
+        assert!(rms < 0.15, "position rms {}", rms);
+        assert!((kf.state()[1] - velocity).abs() < 0.1);
+    }
+
+    #[test]
+    fn joseph_covariance_symmetric() {
+        let mut kf = constant_velocity(0.5, 0.1);
+        kf.set_covariance(Matrix2::new(4.0, 1.0, 1.0, 2.0));
+        for i in 0..500 {
+            kf.predict(&Vector1::zeros(), 0.01).unwrap();
+            kf.update(&Vector1::new(i as f32 * 0.01)).unwrap();
+            let p = kf.covariance();
+            assert_eq!(p, &p.transpose());
+            //正定
+            assert!(Cholesky::new(*p).is_some());
+        }
+        //校正后位置方差不大于测量方差
+        assert!(kf.covariance()[(0, 0)] <= 0.01);
+    }
+
+    #[test]
+    fn rejects_invalid_input() {
+        let mut kf = constant_velocity(0.01, 0.5);
+        kf.set_state(Vector2::new(1.0, 2.0));
+        let u = Vector1::zeros();
+        assert_eq!(kf.predict(&u, -0.01), Err(KalmanError::InvalidDt));
+        assert_eq!(kf.predict(&u, f32::NAN), Err(KalmanError::InvalidDt));
+        assert_eq!(kf.predict(&u, f32::INFINITY), Err(KalmanError::InvalidDt));
+        assert_eq!(
+            kf.update(&Vector1::new(f32::NAN)),
+            Err(KalmanError::NotFinite)
+        );
+        //测量噪声和位置方差都为0时新息协方差奇异
+        kf.set_measurement_noise(Matrix1::zeros());
+        kf.set_covariance(Matrix2::new(0.0, 0.0, 0.0, 1.0));
+        assert_eq!(kf.update(&Vector1::new(3.0)), Err(KalmanError::Singular));
+        //出错时状态和协方差不变
+        assert_eq!(kf.state(), &Vector2::new(1.0, 2.0));
+        assert_eq!(kf.covariance(), &Matrix2::new(0.0, 0.0, 0.0, 1.0));
+        //dt为0只是不前进
+        assert_eq!(kf.predict(&u, 0.0), Ok(()));
+        assert_eq!(kf.state(), &Vector2::new(1.0, 2.0));
     }
 }
@@ -3,7 +3,8 @@
 
 pub mod ahrs;
 pub mod dither;
-// pub mod eskf;
+pub mod dyn_notch;
+pub mod eskf;
 pub mod first_order;
 pub mod iir_filter;
 pub mod jitter_filter;
@@ -16,6 +17,7 @@ pub mod mean;
 pub mod mean_mean;
 pub mod mean_value;
 pub mod moving_average;
+pub mod rpm_filter;
 pub mod weighted_moving_average;
 
 pub trait Filter<In, Out>: Send + Sync {
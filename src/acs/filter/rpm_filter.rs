@@ -0,0 +1,224 @@
+//! ### RPM陷波滤波
+//!
+//! 按电调回传的每个电机转速，在转速频率及其谐波处各放一个陷波器，滤除陀螺仪上的电机噪声。
+//! 频率接近下限时逐渐减弱陷波，避免低转速时陷波落到飞行控制频段；超过奈奎斯特频率的谐波不处理。
+//! 采样率的一半不高于最低陷波频率时没有可用的频段，整个滤波器不启用。
+use super::iir_filter::BiquadCoeffs;
+use super::Filter;
+use crate::driver::MAX_MOTORS;
+use nalgebra::Vector3;
+
+/// 最多谐波数
+pub const MAX_HARMONICS: usize = 3;
+
+/// 二阶陷波器，直接I型，频繁改变中心频率时输出比直接II型转置连续
+#[derive(Debug, Clone, Copy)]
+pub struct Notch {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    x1: f32,
+    x2: f32,
+    y1: f32,
+    y2: f32,
+}
+
+impl Notch {
+    /// 直通
+    pub const fn new() -> Self {
+        Self {
+            b0: 1.0,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+            x1: 0.0,
+            x2: 0.0,
+            y1: 0.0,
+            y2: 0.0,
+        }
+    }
+
+    /// 设置中心频率和品质因数，sample_hz为采样频率
+    pub fn set(&mut self, center_hz: f32, q: f32, sample_hz: f32) {
+        let c = BiquadCoeffs::notch(center_hz, q, sample_hz);
+        self.b0 = c.b0;
+        self.b1 = c.b1;
+        self.b2 = c.b2;
+        self.a1 = c.a1;
+        self.a2 = c.a2;
+    }
+
+    pub fn apply(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1
+            - self.a2 * self.y2;
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// RPM陷波参数
+#[derive(Debug, Clone, Copy)]
+pub struct RpmFilterConfig {
+    pub harmonics: u8, //谐波数，1-3
+    pub q: f32,        //品质因数
+    pub min_hz: f32,   //最低陷波频率
+    pub fade_hz: f32,  //从最低频率往上这一段逐渐加强陷波
+}
+
+impl Default for RpmFilterConfig {
+    fn default() -> Self {
+        Self {
+            harmonics: 3,
+            q: 5.0,
+            min_hz: 100.0,
+            fade_hz: 50.0,
+        }
+    }
+}
+
+/// 每个电机、每个谐波、每个轴一个陷波器
+pub struct RpmFilter {
+    config: RpmFilterConfig,
+    sample_hz: f32,
+    enabled: bool,
+    notches: [[[Notch; 3]; MAX_HARMONICS]; MAX_MOTORS],
+    weights: [[f32; MAX_HARMONICS]; MAX_MOTORS], //0为不滤波
+    rpm: [f32; MAX_MOTORS],
+}
+
+impl RpmFilter {
+    pub fn new(config: RpmFilterConfig, sample_hz: f32) -> Self {
+        let enabled = config.min_hz < sample_hz * 0.48;
+        if !enabled {
+            log::warn!(
+                "RPM filter disabled, sample rate {}Hz too low for {}Hz",
+                sample_hz,
+                config.min_hz
+            );
+        }
+        Self {
+            config,
+            sample_hz,
+            enabled,
+            notches: [[[Notch::new(); 3]; MAX_HARMONICS]; MAX_MOTORS],
+            weights: [[0.0; MAX_HARMONICS]; MAX_MOTORS],
+            rpm: [0.0; MAX_MOTORS],
+        }
+    }
+
+    /// 更新电机转速，重新调整该电机的陷波频率
+    pub fn set_rpm(&mut self, motor: usize, rpm: f32) {
+        if !self.enabled || motor >= MAX_MOTORS || self.rpm[motor] == rpm {
+            return;
+        }
+        self.rpm[motor] = rpm;
+        let harmonics = (self.config.harmonics as usize).min(MAX_HARMONICS);
+        let nyquist = self.sample_hz * 0.48;
+        for h in 0..MAX_HARMONICS {
+            let hz = rpm / 60.0 * (h + 1) as f32;
+            let weight = if h >= harmonics || hz >= nyquist {
+                0.0
+            } else if self.config.fade_hz > 0.0 {
+                ((hz - self.config.min_hz) / self.config.fade_hz)
+                    .max(0.0)
+                    .min(1.0)
+            } else if hz >= self.config.min_hz {
+                1.0
+            } else {
+                0.0
+            };
+            self.weights[motor][h] = weight;
+            if weight > 0.0 {
+                for notch in self.notches[motor][h].iter_mut() {
+                    notch.set(hz, self.config.q, self.sample_hz);
+                }
+            }
+        }
+    }
+}
+
+impl Filter<Vector3<f32>, Vector3<f32>> for RpmFilter {
+    fn do_filter(&mut self, input: Vector3<f32>, output: &mut Vector3<f32>) {
+        let mut v = input;
+        for (notches, weights) in self.notches.iter_mut().zip(self.weights.iter()) {
+            for (axes, &weight) in notches.iter_mut().zip(weights.iter()) {
+                if weight <= 0.0 {
+                    continue;
+                }
+                for (i, notch) in axes.iter_mut().enumerate() {
+                    let y = notch.apply(v[i]);
+                    v[i] = weight * y + (1.0 - weight) * v[i];
+                }
+            }
+        }
+        *output = v;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+
+    //正弦输入稳定后的输出幅值
+    fn amplitude(filter: &mut RpmFilter, hz: f32, sample_hz: f32) -> f32 {
+        let mut peak = 0.0f32;
+        let mut output = Vector3::zeros();
+        for i in 0..(sample_hz as usize * 2) {
+            let x = libm::sinf(2.0 * PI * hz * i as f32 / sample_hz);
+            filter.do_filter(Vector3::repeat(x), &mut output);
+            if i > sample_hz as usize {
+                peak = peak.max(output.x.abs());
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn notch_placement() {
+        let mut filter = RpmFilter::new(RpmFilterConfig::default(), 1000.0);
+        //12000rpm基频200Hz，二次谐波400Hz，三次谐波600Hz超过奈奎斯特频率
+        filter.set_rpm(0, 12000.0);
+        assert_eq!(filter.weights[0], [1.0, 1.0, 0.0]);
+        assert!(filter.weights[1..]
+            .iter()
+            .all(|w| *w == [0.0; MAX_HARMONICS]));
+        assert!(amplitude(&mut filter, 200.0, 1000.0) < 0.01);
+        let mut filter = RpmFilter::new(RpmFilterConfig::default(), 1000.0);
+        filter.set_rpm(0, 12000.0);
+        assert!(amplitude(&mut filter, 400.0, 1000.0) < 0.01);
+        //远离陷波频率的信号基本不受影响
+        let mut filter = RpmFilter::new(RpmFilterConfig::default(), 1000.0);
+        filter.set_rpm(0, 12000.0);
+        assert!(amplitude(&mut filter, 20.0, 1000.0) > 0.95);
+    }
+
+    #[test]
+    fn fade_near_min_hz() {
+        let mut filter = RpmFilter::new(RpmFilterConfig::default(), 1000.0);
+        //125Hz在最低频率往上的渐变段中间
+        filter.set_rpm(2, 7500.0);
+        assert!((filter.weights[2][0] - 0.5).abs() < 1e-6);
+        assert_eq!(filter.weights[2][1], 1.0);
+        filter.set_rpm(2, 3000.0);
+        assert_eq!(filter.weights[2][0], 0.0);
+    }
+
+    #[test]
+    fn disabled_when_sample_rate_too_low() {
+        let mut filter = RpmFilter::new(RpmFilterConfig::default(), 100.0);
+        assert!(!filter.enabled);
+        filter.set_rpm(0, 3000.0);
+        assert_eq!(filter.weights[0], [0.0; MAX_HARMONICS]);
+        let mut output = Vector3::zeros();
+        filter.do_filter(Vector3::new(1.0, -2.0, 3.0), &mut output);
+        assert_eq!(output, Vector3::new(1.0, -2.0, 3.0));
+    }
+}
@@ -0,0 +1,84 @@
+//! 增益调度
+//!
+//! TPA(油门PID衰减)：油门超过拐点后，比例、微分增益随油门线性减小，满油门时减小rate。
+//! 电压补偿：电池电压下降后同样的输出推力变小，按标称电压/实测电压放大电机输出。
+//!
+
+/// 衰减哪些增益
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TpaMode {
+    /// 比例和微分
+    PD,
+    /// 只衰减微分
+    D,
+}
+
+/// TPA参数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TpaConfig {
+    pub mode: TpaMode,
+    pub breakpoint: f32, //拐点油门，0.0-1.0
+    pub rate: f32,       //满油门时的衰减量，0.0-1.0，0.0为不衰减
+}
+
+impl TpaConfig {
+    pub const fn new() -> Self {
+        Self {
+            mode: TpaMode::PD,
+            breakpoint: 0.5,
+            rate: 0.0,
+        }
+    }
+
+    /// 增益系数，拐点以下为1.0，满油门时为1.0-rate
+    pub fn factor(&self, throttle: f32) -> f32 {
+        let throttle = throttle.max(0.0).min(1.0);
+        let rate = self.rate.max(0.0).min(1.0);
+        if throttle <= self.breakpoint || self.breakpoint >= 1.0 {
+            return 1.0;
+        }
+        1.0 - rate * (throttle - self.breakpoint) / (1.0 - self.breakpoint)
+    }
+
+    /// 比例、积分、微分的增益系数
+    pub fn scale(&self, throttle: f32) -> (f32, f32, f32) {
+        let f = self.factor(throttle);
+        match self.mode {
+            TpaMode::PD => (f, 1.0, f),
+            TpaMode::D => (1.0, 1.0, f),
+        }
+    }
+}
+
+impl Default for TpaConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 电压补偿参数，电压单位V
+#[derive(Debug, Clone, Copy)]
+pub struct VbatCompConfig {
+    pub nominal: f32,  //标称电压，满电到空电之间的常用电压
+    pub min: f32,      //低于该电压认为没有读数，不补偿
+    pub max_gain: f32, //最大放大倍数
+}
+
+impl VbatCompConfig {
+    /// cells为电池串数，标称3.8V/节
+    pub const fn new(cells: u8) -> Self {
+        Self {
+            nominal: 3.8 * cells as f32,
+            min: 3.0 * cells as f32,
+            max_gain: 1.3,
+        }
+    }
+
+    /// 电机输出系数，没有读数时为1.0，电压高于标称时小于1.0
+    pub fn factor(&self, voltage: Option<f32>) -> f32 {
+        match voltage {
+            Some(v) if v >= self.min && v > 0.0 => (self.nominal / v).min(self.max_gain),
+            _ => 1.0,
+        }
+    }
+}
@@ -0,0 +1,99 @@
+//! 固定翼舵面混控
+//!
+//! 输出为舵面偏转指令-1.0~1.0，水平舵面后缘向下为正，方向舵后缘向右为正。
+//! 横滚右倾为正，俯仰推杆(低头)为正，偏航右转为正，襟翼放下为正。
+//! 安装方向与约定相反的舵机用ServoConfig的reversed修正。
+//!
+
+/// 舵面布局，每种布局四路舵机的用途不同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WingType {
+    /// 常规布局: 左副翼、升降舵、方向舵、右副翼
+    Conventional,
+    /// 飞翼: 左升降副翼、右升降副翼、方向舵(可选)、不用
+    Elevon,
+    /// V尾: 左副翼、左V尾、右V尾、右副翼
+    VTail,
+    /// 襟副翼: 左襟副翼、升降舵、方向舵、右襟副翼
+    Flaperon,
+}
+
+impl WingType {
+    /// MSP_MIXER_CONFIG里的混控编号，飞翼为FLYING_WING，其余为AIRPLANE
+    pub fn msp_mixer_mode(&self) -> u8 {
+        match self {
+            WingType::Elevon => 8,
+            _ => 14,
+        }
+    }
+}
+
+/// 混控参数
+#[derive(Debug, Clone, Copy)]
+pub struct WingConfig {
+    pub wing_type: WingType,
+    pub roll_rate: f32,     //横滚舵量比例，0.0-1.0
+    pub pitch_rate: f32,    //俯仰舵量比例，0.0-1.0
+    pub yaw_rate: f32,      //偏航舵量比例，0.0-1.0
+    pub flap_rate: f32,     //襟翼舵量比例，0.0-1.0，只对襟副翼有效
+    pub dual_aileron: bool, //常规和V尾布局左右副翼各用一个舵机，否则两侧用Y线并联在左副翼上
+}
+
+impl WingConfig {
+    pub const fn new(wing_type: WingType) -> Self {
+        Self {
+            wing_type,
+            roll_rate: 1.0,
+            pitch_rate: 1.0,
+            yaw_rate: 1.0,
+            flap_rate: 0.5,
+            dual_aileron: false,
+        }
+    }
+
+    /// 布局需要的舵机数，飞翼的方向舵可选
+    pub fn servo_count(&self) -> usize {
+        match self.wing_type {
+            WingType::Conventional | WingType::VTail if self.dual_aileron => 4,
+            WingType::Conventional | WingType::VTail => 3,
+            WingType::Elevon => 2,
+            WingType::Flaperon => 4,
+        }
+    }
+}
+
+/// 两个舵面共用一路行程时，合成量超出-1.0~1.0就按比例缩小，保持两个控制量的比例不变
+fn pair(a: f32, b: f32) -> (f32, f32) {
+    let (x, y) = (a + b, a - b);
+    let m = x.abs().max(y.abs());
+    if m > 1.0 {
+        (x / m, y / m)
+    } else {
+        (x, y)
+    }
+}
+
+/// 横滚、俯仰、偏航(-1.0~1.0)和襟翼(0.0~1.0)混控为四路舵机指令(-1.0~1.0)
+pub fn mix(config: &WingConfig, roll: f32, pitch: f32, yaw: f32, flap: f32) -> [f32; 4] {
+    let roll = roll.max(-1.0).min(1.0) * config.roll_rate;
+    let pitch = pitch.max(-1.0).min(1.0) * config.pitch_rate;
+    let yaw = yaw.max(-1.0).min(1.0) * config.yaw_rate;
+    let flap = flap.max(0.0).min(1.0) * config.flap_rate;
+    match config.wing_type {
+        WingType::Conventional => [roll, pitch, yaw, -roll],
+        WingType::Elevon => {
+            //右倾时左翼后缘向下
+            let (left, right) = pair(pitch, roll);
+            [left, right, yaw, 0.0]
+        }
+        WingType::VTail => {
+            //右转时左V尾后缘向下
+            let (left, right) = pair(pitch, yaw);
+            [roll, left, right, -roll]
+        }
+        WingType::Flaperon => {
+            let (left, right) = pair(flap, roll);
+            [left, pitch, yaw, right]
+        }
+    }
+}
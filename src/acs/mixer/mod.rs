@@ -0,0 +1,5 @@
+//! 混控，把姿态控制量分配到各个舵机和电机
+
+pub mod fixed_wing;
+pub mod multirotor;
+pub mod swashplate;
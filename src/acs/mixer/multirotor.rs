@@ -0,0 +1,177 @@
+//! 多旋翼电机混控
+//!
+//! 每个电机一条混控规则，给出油门、横滚、俯仰、偏航对该电机的系数，系数表与Betaflight一致。
+//! 姿态控制量的范围超过1时整体缩小，再平移油门保证所有电机输出都在0.0~1.0内，优先保证姿态。
+//!
+use crate::driver::MAX_MOTORS;
+
+/// 混控规则
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MotorMixRule {
+    pub throttle: f32,
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl MotorMixRule {
+    pub const fn new(throttle: f32, roll: f32, pitch: f32, yaw: f32) -> Self {
+        Self {
+            throttle,
+            roll,
+            pitch,
+            yaw,
+        }
+    }
+}
+
+const fn r(throttle: f32, roll: f32, pitch: f32, yaw: f32) -> MotorMixRule {
+    MotorMixRule::new(throttle, roll, pitch, yaw)
+}
+
+const QUAD_X: [MotorMixRule; 4] = [
+    r(1.0, -1.0, 1.0, -1.0), //右后
+    r(1.0, -1.0, -1.0, 1.0), //右前
+    r(1.0, 1.0, 1.0, 1.0),   //左后
+    r(1.0, 1.0, -1.0, -1.0), //左前
+];
+
+const QUAD_PLUS: [MotorMixRule; 4] = [
+    r(1.0, 0.0, 1.0, -1.0),  //后
+    r(1.0, -1.0, 0.0, 1.0),  //右
+    r(1.0, 1.0, 0.0, 1.0),   //左
+    r(1.0, 0.0, -1.0, -1.0), //前
+];
+
+const HEX_X: [MotorMixRule; 6] = [
+    r(1.0, -0.5, 0.866025, 1.0),  //右后
+    r(1.0, -0.5, -0.866025, 1.0), //右前
+    r(1.0, 0.5, 0.866025, -1.0),  //左后
+    r(1.0, 0.5, -0.866025, -1.0), //左前
+    r(1.0, -1.0, 0.0, -1.0),      //右
+    r(1.0, 1.0, 0.0, 1.0),        //左
+];
+
+const OCTO_FLAT_X: [MotorMixRule; 8] = [
+    r(1.0, 1.0, -0.414178, 1.0),   //左中前
+    r(1.0, -0.414178, -1.0, 1.0),  //右前
+    r(1.0, -1.0, 0.414178, 1.0),   //右中后
+    r(1.0, 0.414178, 1.0, 1.0),    //左后
+    r(1.0, 0.414178, -1.0, -1.0),  //左前
+    r(1.0, -1.0, -0.414178, -1.0), //右中前
+    r(1.0, -0.414178, 1.0, -1.0),  //右后
+    r(1.0, 1.0, 0.414178, -1.0),   //左中后
+];
+
+const Y6: [MotorMixRule; 6] = [
+    r(1.0, 0.0, 1.333333, 1.0),    //后上
+    r(1.0, -1.0, -0.666667, -1.0), //右上
+    r(1.0, 1.0, -0.666667, -1.0),  //左上
+    r(1.0, 0.0, 1.333333, -1.0),   //后下
+    r(1.0, -1.0, -0.666667, 1.0),  //右下
+    r(1.0, 1.0, -0.666667, 1.0),   //左下
+];
+
+/// 机架类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MixerType {
+    QuadX,
+    QuadPlus,
+    HexX,
+    OctoFlatX,
+    Y6,
+    /// 自定义规则
+    Custom,
+}
+
+impl MixerType {
+    /// 内置的混控表，自定义返回空
+    pub fn rules(&self) -> &'static [MotorMixRule] {
+        match self {
+            MixerType::QuadX => &QUAD_X,
+            MixerType::QuadPlus => &QUAD_PLUS,
+            MixerType::HexX => &HEX_X,
+            MixerType::OctoFlatX => &OCTO_FLAT_X,
+            MixerType::Y6 => &Y6,
+            MixerType::Custom => &[],
+        }
+    }
+
+    /// MSP_MIXER_CONFIG里的混控编号
+    pub fn msp_mixer_mode(&self) -> u8 {
+        match self {
+            MixerType::QuadPlus => 2,
+            MixerType::QuadX => 3,
+            MixerType::Y6 => 6,
+            MixerType::HexX => 10,
+            MixerType::OctoFlatX => 13,
+            MixerType::Custom => 23,
+        }
+    }
+}
+
+/// 电机混控器
+#[derive(Debug, Clone, Copy)]
+pub struct Mixer {
+    mixer_type: MixerType,
+    rules: [MotorMixRule; MAX_MOTORS],
+    count: usize,
+}
+
+impl Mixer {
+    pub fn new(mixer_type: MixerType) -> Self {
+        let mut mixer = Self::custom(mixer_type.rules());
+        mixer.mixer_type = mixer_type;
+        mixer
+    }
+
+    /// 自定义混控规则，超过MAX_MOTORS的部分忽略
+    pub fn custom(rules: &[MotorMixRule]) -> Self {
+        let count = rules.len().min(MAX_MOTORS);
+        let mut r = [MotorMixRule::default(); MAX_MOTORS];
+        r[..count].copy_from_slice(&rules[..count]);
+        Self {
+            mixer_type: MixerType::Custom,
+            rules: r,
+            count,
+        }
+    }
+
+    pub fn mixer_type(&self) -> MixerType {
+        self.mixer_type
+    }
+
+    pub fn motor_count(&self) -> usize {
+        self.count
+    }
+
+    pub fn rules(&self) -> &[MotorMixRule] {
+        &self.rules[..self.count]
+    }
+
+    /// 油门0.0~1.0，横滚、俯仰、偏航-1.0~1.0，输出每个电机0.0~1.0，只有前motor_count个有效
+    pub fn mix(&self, throttle: f32, roll: f32, pitch: f32, yaw: f32) -> [f32; MAX_MOTORS] {
+        let mut out = [0.0; MAX_MOTORS];
+        if self.count == 0 {
+            return out;
+        }
+        let mut min = f32::MAX;
+        let mut max = f32::MIN;
+        for (o, rule) in out.iter_mut().zip(self.rules()) {
+            *o = rule.roll * roll + rule.pitch * pitch + rule.yaw * yaw;
+            min = min.min(*o);
+            max = max.max(*o);
+        }
+        //姿态量超出电机行程时整体缩小
+        let range = max - min;
+        let scale = if range > 1.0 { 1.0 / range } else { 1.0 };
+        let (min, max) = (min * scale, max * scale);
+        //油门平移到所有电机都不越界
+        let throttle = throttle.max(0.0).min(1.0);
+        let throttle = throttle.max(-min).min(1.0 - max);
+        for (o, rule) in out.iter_mut().zip(self.rules()) {
+            *o = (*o * scale + throttle * rule.throttle).max(0.0).min(1.0);
+        }
+        out
+    }
+}
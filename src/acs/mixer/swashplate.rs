@@ -0,0 +1,186 @@
+//! CCPM斜盘混控
+//!
+//! 三个舵机按安装角度分布在斜盘周围，角度从机头方向起算，顺时针(俯视)为正。
+//! 每个舵机的行程 = 总距 - (俯仰·cosθ + 横滚·sinθ)，即正俯仰使斜盘前倾，正横滚使斜盘右倾。
+//! H1为无机械混控的斜盘，三个舵机分别直接控制横滚、俯仰、总距。
+//!
+use core::f32::consts::PI;
+use nalgebra::{Matrix3, Vector3};
+
+/// 斜盘类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwashType {
+    /// 无混控，舵机依次为横滚、俯仰、总距
+    H1,
+    /// 120°CCPM
+    H120,
+    /// 135°CCPM
+    H135,
+    /// 140°CCPM
+    H140,
+    /// 90°CCPM
+    H90,
+}
+
+impl SwashType {
+    /// 三个舵机的安装角度，单位度
+    pub fn servo_angles(&self) -> [f32; 3] {
+        match self {
+            SwashType::H1 => [0.0; 3],
+            SwashType::H120 => [0.0, 120.0, 240.0],
+            SwashType::H135 => [0.0, 135.0, 225.0],
+            SwashType::H140 => [0.0, 140.0, 220.0],
+            SwashType::H90 => [0.0, 90.0, 270.0],
+        }
+    }
+
+    /// MSP_MIXER_CONFIG里的混控编号，90°为HELI_90_DEG，其余按HELI_120_CCPM上报
+    pub fn msp_mixer_mode(&self) -> u8 {
+        match self {
+            SwashType::H90 => 16,
+            _ => 15,
+        }
+    }
+}
+
+/// 斜盘参数
+#[derive(Debug, Clone, Copy)]
+pub struct SwashConfig {
+    pub swash_type: SwashType,
+    pub servo_angles: [f32; 3], //每个舵机的安装角度，单位度，默认由斜盘类型决定
+    pub phase: f32,             //整体相位修正，单位度
+    pub collective_range: f32,  //总距行程比例，0.0-1.0
+    pub cyclic_range: f32,      //周期变距行程比例，0.0-1.0
+    pub ring: f32,              //斜盘环，横滚和俯仰合成后的最大倾斜量
+}
+
+impl SwashConfig {
+    pub fn new(swash_type: SwashType) -> Self {
+        Self {
+            swash_type,
+            servo_angles: swash_type.servo_angles(),
+            phase: 0.0,
+            collective_range: 0.5,
+            cyclic_range: 0.5,
+            ring: 0.5,
+        }
+    }
+
+    //每个舵机一行: [总距, 俯仰, 横滚]系数
+    fn matrix(&self) -> Matrix3<f32> {
+        if self.swash_type == SwashType::H1 {
+            return Matrix3::new(0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0);
+        }
+        let mut m = Matrix3::zeros();
+        for (i, angle) in self.servo_angles.iter().enumerate() {
+            let theta = (angle + self.phase) * PI / 180.0;
+            m[(i, 0)] = 1.0;
+            m[(i, 1)] = -libm::cosf(theta);
+            m[(i, 2)] = -libm::sinf(theta);
+        }
+        m
+    }
+}
+
+/// 斜盘环，横滚和俯仰合成的倾斜量超过ring时按比例缩小
+pub fn swash_ring(roll: f32, pitch: f32, ring: f32) -> (f32, f32) {
+    let tilt = libm::sqrtf(roll * roll + pitch * pitch);
+    if tilt > ring && tilt > 0.0 {
+        let k = ring / tilt;
+        (roll * k, pitch * k)
+    } else {
+        (roll, pitch)
+    }
+}
+
+/// 横滚、俯仰、总距(-1.0~1.0)混控为三个舵机的指令(-1.0~1.0)
+pub fn mix(config: &SwashConfig, roll: f32, pitch: f32, collective: f32) -> [f32; 3] {
+    let roll = roll.max(-1.0).min(1.0) * config.cyclic_range;
+    let pitch = pitch.max(-1.0).min(1.0) * config.cyclic_range;
+    let collective = collective.max(-1.0).min(1.0) * config.collective_range;
+    let (roll, pitch) = swash_ring(roll, pitch, config.ring);
+    let servos = config.matrix() * Vector3::new(collective, pitch, roll);
+    [
+        servos[0].max(-1.0).min(1.0),
+        servos[1].max(-1.0).min(1.0),
+        servos[2].max(-1.0).min(1.0),
+    ]
+}
+
+/// 由舵机指令反算横滚、俯仰、总距，斜盘环和限幅没有生效时与mix互逆，矩阵奇异时返回None
+pub fn unmix(config: &SwashConfig, servos: [f32; 3]) -> Option<(f32, f32, f32)> {
+    let inv = config.matrix().try_inverse()?;
+    let v = inv * Vector3::from(servos);
+    let (collective, pitch, roll) = (v[0], v[1], v[2]);
+    let scale = |x: f32, range: f32| if range > 0.0 { x / range } else { 0.0 };
+    Some((
+        scale(roll, config.cyclic_range),
+        scale(pitch, config.cyclic_range),
+        scale(collective, config.collective_range),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TYPES: [SwashType; 5] = [
+        SwashType::H1,
+        SwashType::H120,
+        SwashType::H135,
+        SwashType::H140,
+        SwashType::H90,
+    ];
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn unmix_roundtrip() {
+        for t in TYPES {
+            let mut config = SwashConfig::new(t);
+            config.phase = 10.0;
+            config.ring = 1.0;
+            for (roll, pitch, collective) in [(0.3, -0.2, 0.5), (-0.5, 0.4, -0.1), (0.0, 0.0, 1.0)]
+            {
+                let servos = mix(&config, roll, pitch, collective);
+                let (r, p, c) = unmix(&config, servos).unwrap();
+                assert!(
+                    close(r, roll) && close(p, pitch) && close(c, collective),
+                    "{:?} {:?}",
+                    t,
+                    (r, p, c)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn h120_pitch_moves_front_servo_opposite() {
+        let config = SwashConfig::new(SwashType::H120);
+        let servos = mix(&config, 0.0, 1.0, 0.0);
+        assert!(close(servos[0], -0.5));
+        assert!(close(servos[1], 0.25));
+        assert!(close(servos[2], 0.25));
+        let servos = mix(&config, 0.0, 0.0, 1.0);
+        assert!(servos.iter().all(|s| close(*s, 0.5)));
+    }
+
+    #[test]
+    fn ring_limits_combined_tilt() {
+        let config = SwashConfig::new(SwashType::H120);
+        let (r, p, _) = unmix(&config, mix(&config, 1.0, 1.0, 0.0)).unwrap();
+        //斜盘环生效后不再互逆，合成倾斜量等于ring
+        let tilt = libm::sqrtf(r * r + p * p) * config.cyclic_range;
+        assert!(close(tilt, config.ring));
+        assert!(close(r, p));
+    }
+
+    #[test]
+    fn unmix_singular() {
+        let mut config = SwashConfig::new(SwashType::H120);
+        config.servo_angles = [0.0; 3];
+        assert!(unmix(&config, [0.1, 0.2, 0.3]).is_none());
+    }
+}
@@ -1,4 +1,10 @@
 //! 姿态控制系统 Attitude Control System
 
+pub mod attitude;
+pub mod attitude_error;
+pub mod autotune;
 pub mod filter;
+pub mod gain_schedule;
+pub mod mixer;
 pub mod pid;
+pub mod rates;
@@ -7,6 +7,12 @@ pub struct Limit<T> {
     max: T,
 }
 
+impl<T: FloatCore> Limit<T> {
+    pub fn new(min: T, max: T) -> Self {
+        Self { min, max }
+    }
+}
+
 impl<T: FloatCore> Default for Limit<T> {
     fn default() -> Self {
         Limit {
@@ -41,22 +47,79 @@ pub enum Mode {
     Increasing, //增量式PID
 }
 
+/// 微分项的输入
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Derivative {
+    #[default]
+    Error, //对误差求微分，目标值突变时会产生微分冲击
+    Measurement, //对测量值求微分，目标值突变时没有冲击
+}
+
+/// 积分抗饱和，只对位置式PID有效
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum AntiWindup<T> {
+    #[default]
+    None, //只靠积分限幅
+    Clamping,           //条件积分，输出饱和且误差使其更饱和时停止积分
+    BackCalculation(T), //反算，按(饱和输出-未饱和输出)*系数回退积分
+}
+
+/// 前馈，由目标值的变化率计算，摇杆动作时不必等误差出现就给出控制量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FeedForward<T> {
+    pub gain: T,              //目标值变化率的系数
+    pub smoothing: Option<T>, //目标值变化率低通截止频率，单位Hz，遥控信号有台阶时使用
+    pub boost: T,             //目标值变化加速度的系数，单位秒，加快打杆初期的响应
+}
+
+impl<T: FloatCore> FeedForward<T> {
+    pub fn new(gain: T) -> Self {
+        Self {
+            gain,
+            smoothing: None,
+            boost: T::zero(),
+        }
+    }
+}
+
+/// 积分松弛，目标值快速变化时按比例减小积分输入，避免打杆结束后积分过冲
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ItermRelax<T> {
+    pub cutoff: T,    //目标值低通截止频率，单位Hz，目标值减去低通结果为快速变化的部分
+    pub threshold: T, //快速变化部分达到该值时积分输入为0
+}
+
 #[derive(Debug)]
 pub struct Pid<T: FloatCore> {
     kp: T,
     ki: T,
     kd: T,
+    p_scale: T, //增益调度的比例系数，运行时修改，不改变kp、ki、kd
+    i_scale: T,
+    d_scale: T,
     mode: Mode,
-    setpoint: T,                 //目标值
-    limit_out: Option<Limit<T>>, //输出限幅
-    limit_p: Option<Limit<T>>,   //比例输出限幅
-    limit_i: Option<Limit<T>>,   //积分输出限幅
-    limit_d: Option<Limit<T>>,   //微分输出限幅
-    error: Error<T>,             //误差
-    derror: Error<T>,            //微分误差
+    derivative: Derivative,               //微分项输入
+    d_cutoff: Option<T>,                  //微分项低通截止频率，单位Hz
+    anti_windup: AntiWindup<T>,           //积分抗饱和
+    feed_forward: Option<FeedForward<T>>, //前馈
+    iterm_relax: Option<ItermRelax<T>>,   //积分松弛
+    sp_last: T,                           //上次目标值
+    sp_rate: T,                           //平滑后的目标值变化率
+    sp_lowpass: T,                        //低通后的目标值，用于积分松弛
+    setpoint: T,                          //目标值
+    limit_out: Option<Limit<T>>,          //输出限幅
+    limit_p: Option<Limit<T>>,            //比例输出限幅
+    limit_i: Option<Limit<T>>,            //积分输出限幅
+    limit_d: Option<Limit<T>>,            //微分输出限幅
+    error: Error<T>,                      //误差
+    measurement: Error<T>,                //测量值
+    derror: Error<T>,                     //微分误差
+    samples: u8,                          //已计算的次数，不足时不算微分
     p_out: T,
     i_out: T, //积分项，误差对时间的积分
     d_out: T,
+    f_out: T, //前馈项
+    relax: T, //积分松弛系数，1.0为不松弛
     out: T,
 }
 
@@ -66,17 +129,32 @@ impl<T: FloatCore> Pid<T> {
             kp,
             ki,
             kd,
+            p_scale: T::one(),
+            i_scale: T::one(),
+            d_scale: T::one(),
             mode: Default::default(),
+            derivative: Default::default(),
+            d_cutoff: None,
+            anti_windup: AntiWindup::None,
+            feed_forward: None,
+            iterm_relax: None,
+            sp_last: T::zero(),
+            sp_rate: T::zero(),
+            sp_lowpass: T::zero(),
             setpoint: T::zero(),
             limit_out: Default::default(),
             limit_p: Default::default(),
             limit_i: Default::default(),
             limit_d: Default::default(),
             error: Error::default(),
+            measurement: Error::default(),
             derror: Default::default(),
+            samples: 0,
             p_out: T::zero(),
             i_out: T::zero(),
             d_out: T::zero(),
+            f_out: T::zero(),
+            relax: T::one(),
             out: T::zero(),
         }
     }
@@ -84,12 +162,35 @@ impl<T: FloatCore> Pid<T> {
         self.mode = mode;
         self
     }
+    /// 微分项输入，默认对误差求微分
+    pub fn with_derivative(mut self, derivative: Derivative) -> Self {
+        self.derivative = derivative;
+        self
+    }
+    /// 微分项一阶低通，cutoff为截止频率，单位Hz
+    pub fn with_d_lowpass(mut self, cutoff: T) -> Self {
+        self.d_cutoff = Some(cutoff);
+        self
+    }
+    pub fn with_anti_windup(mut self, anti_windup: AntiWindup<T>) -> Self {
+        self.anti_windup = anti_windup;
+        self
+    }
+    pub fn with_feed_forward(mut self, feed_forward: FeedForward<T>) -> Self {
+        self.feed_forward = Some(feed_forward);
+        self
+    }
+    pub fn with_iterm_relax(mut self, relax: ItermRelax<T>) -> Self {
+        self.iterm_relax = Some(relax);
+        self
+    }
     pub fn with_limit_out(mut self, limit: Limit<T>) -> Self {
         self.limit_out = Some(limit);
         self
     }
+    /// 比例项限幅。原来误写成了微分项限幅，比例项实际没有限幅
     pub fn with_limit_proportion(mut self, limit: Limit<T>) -> Self {
-        self.limit_d = Some(limit);
+        self.limit_p = Some(limit);
         self
     }
     pub fn with_limit_integral(mut self, limit: Limit<T>) -> Self {
@@ -108,39 +209,128 @@ impl<T: FloatCore> Pid<T> {
         self.setpoint = setpoint;
     }
 
+    /// 修改增益，积分和微分的历史保留
+    pub fn set_gains(&mut self, kp: T, ki: T, kd: T) {
+        self.kp = kp;
+        self.ki = ki;
+        self.kd = kd;
+    }
+
+    pub fn gains(&self) -> (T, T, T) {
+        (self.kp, self.ki, self.kd)
+    }
+
+    /// 增益调度，实际使用的增益为kp*p、ki*i、kd*d，每次计算前都可以修改
+    pub fn set_gain_scale(&mut self, p: T, i: T, d: T) {
+        self.p_scale = p;
+        self.i_scale = i;
+        self.d_scale = d;
+    }
+
+    pub fn gain_scale(&self) -> (T, T, T) {
+        (self.p_scale, self.i_scale, self.d_scale)
+    }
+
+    /// 修改前馈参数，None关闭前馈
+    pub fn set_feed_forward(&mut self, feed_forward: Option<FeedForward<T>>) {
+        self.feed_forward = feed_forward;
+    }
+
+    /// 修改积分松弛参数，None关闭积分松弛
+    pub fn set_iterm_relax(&mut self, relax: Option<ItermRelax<T>>) {
+        self.iterm_relax = relax;
+    }
+
+    //各项输出，用于记录日志
+    pub fn p_out(&self) -> T {
+        self.p_out
+    }
+    pub fn i_out(&self) -> T {
+        self.i_out
+    }
+    pub fn d_out(&self) -> T {
+        self.d_out
+    }
+    pub fn f_out(&self) -> T {
+        self.f_out
+    }
+    pub fn relax(&self) -> T {
+        self.relax
+    }
+    pub fn out(&self) -> T {
+        self.out
+    }
+
     //求控制量
-    pub fn next1(&mut self, value: T, setpoint: T) -> T {
+    pub fn next1(&mut self, value: T, setpoint: T, dt: T) -> T {
         self.setpoint = setpoint;
-        self.next(value)
+        self.next(value, dt)
     }
 
-    //求控制量
-    pub fn next(&mut self, value: T) -> T {
-        //存放过去两次计算的误差值
+    /// 求控制量，dt为距上次计算的时间，单位秒，dt不大于0时返回上次的输出
+    pub fn next(&mut self, value: T, dt: T) -> T {
+        if dt <= T::zero() {
+            return self.out;
+        }
+        //存放过去两次计算的误差值和测量值
         self.error.prev = self.error.last;
         self.error.last = self.error.current;
+        self.measurement.prev = self.measurement.last;
+        self.measurement.last = self.measurement.current;
         //当前误差
         self.error.current = self.setpoint - value;
+        self.measurement.current = value;
+        let two = T::one() + T::one();
+        let f_last = self.f_out;
+        self.setpoint_terms(dt);
         match self.mode {
             //位置式PID
             Mode::Position => {
                 //比例项输出
-                self.p_out = limit(self.kp * self.error.current, self.limit_p.as_ref());
-                //积分项限幅输出
-                self.i_out = limit(
-                    self.i_out + self.ki * self.error.current,
-                    self.limit_i.as_ref(),
+                self.p_out = limit(
+                    self.kp * self.p_scale * self.error.current,
+                    self.limit_p.as_ref(),
                 );
+                //微分项，对测量值求微分时符号与误差相反
+                let d = if self.samples < 1 {
+                    T::zero()
+                } else {
+                    match self.derivative {
+                        Derivative::Error => (self.error.current - self.error.last) / dt,
+                        Derivative::Measurement => {
+                            (self.measurement.last - self.measurement.current) / dt
+                        }
+                    }
+                };
                 //存放过去两次计算的微分误差值
                 self.derror.prev = self.derror.last;
                 self.derror.last = self.derror.current;
-                //当前误差的微分用本次误差减去上一次误差来计算
-                self.derror.current = self.error.current - self.error.last;
+                self.derror.current = self.d_lowpass(d, dt);
                 //微分项限幅输出
-                self.d_out = limit(self.kd * self.derror.current, self.limit_d.as_ref());
-                //叠加三个输出到总输出
+                self.d_out = limit(
+                    self.kd * self.d_scale * self.derror.current,
+                    self.limit_d.as_ref(),
+                );
+                //积分项，按抗饱和方式累加后限幅
+                let i = self.i_out + self.ki * self.i_scale * self.error.current * self.relax * dt;
+                let unsat = self.p_out + i + self.d_out + self.f_out;
+                let sat = limit(unsat, self.limit_out.as_ref());
+                let i = match self.anti_windup {
+                    AntiWindup::None => i,
+                    //饱和且误差与饱和方向相同时保持积分不变
+                    AntiWindup::Clamping => {
+                        if sat != unsat && (unsat > sat) == (self.error.current > T::zero()) {
+                            self.i_out
+                        } else {
+                            i
+                        }
+                    }
+                    AntiWindup::BackCalculation(kt) => i + kt * (sat - unsat) * dt,
+                };
+                self.i_out = limit(i, self.limit_i.as_ref());
+                //叠加四个输出到总输出
                 self.out = limit(
-                    self.p_out + self.i_out + self.d_out,
+                    self.p_out + self.i_out + self.d_out + self.f_out,
                     self.limit_out.as_ref(),
                 );
             }
@@ -148,39 +338,121 @@ impl<T: FloatCore> Pid<T> {
             Mode::Increasing => {
                 //以本次误差与上次误差的差值作为比例项的输入带入计算
                 self.p_out = limit(
-                    self.kp * (self.error.current - self.error.last),
+                    self.kp * self.p_scale * (self.error.current - self.error.last),
                     self.limit_p.as_ref(),
                 );
                 //以本次误差作为积分项带入计算
-                self.i_out = limit(self.ki * self.error.current, self.limit_i.as_ref());
+                self.i_out = limit(
+                    self.ki * self.i_scale * self.error.current * self.relax * dt,
+                    self.limit_i.as_ref(),
+                );
+                //以本次误差与上次误差的差值减去上次误差与上上次误差的差值作为微分项的输入带入计算
+                let d = if self.samples < 2 {
+                    T::zero()
+                } else {
+                    match self.derivative {
+                        Derivative::Error => {
+                            (self.error.current - two * self.error.last + self.error.prev) / dt
+                        }
+                        Derivative::Measurement => {
+                            (two * self.measurement.last
+                                - self.measurement.current
+                                - self.measurement.prev)
+                                / dt
+                        }
+                    }
+                };
                 //迭代微分项的数组
                 self.derror.prev = self.derror.last;
                 self.derror.last = self.derror.current;
-                //以本次误差与上次误差的差值减去上次误差与上上次误差的差值作为微分项的输入带入计算
-                self.derror.current =
-                    self.error.current - T::from(2.0).unwrap() * self.error.last + self.error.prev;
-                self.d_out = limit(self.kd * self.derror.current, self.limit_d.as_ref());
-                //叠加三个项的输出作为总输出
-                self.out = self.out + self.p_out + self.i_out + self.d_out;
+                self.derror.current = self.d_lowpass(d, dt);
+                self.d_out = limit(
+                    self.kd * self.d_scale * self.derror.current,
+                    self.limit_d.as_ref(),
+                );
+                //叠加三个项的输出和前馈的增量作为总输出
+                self.out = self.out + self.p_out + self.i_out + self.d_out + self.f_out - f_last;
                 //总输出限幅
                 self.out = limit(self.out, self.limit_out.as_ref());
             }
         }
+        if self.samples < 2 {
+            self.samples += 1;
+        }
         self.out
     }
 
+    //微分项一阶低通，没有设置截止频率时直通
+    fn d_lowpass(&self, d: T, dt: T) -> T {
+        match self.d_cutoff {
+            Some(cutoff) if self.samples > 0 => lowpass(self.derror.last, d, cutoff, dt),
+            _ => d,
+        }
+    }
+
+    //由目标值计算前馈项和积分松弛系数，第一次计算时目标值没有历史，两者都不生效
+    fn setpoint_terms(&mut self, dt: T) {
+        if self.samples < 1 {
+            self.sp_last = self.setpoint;
+            self.sp_lowpass = self.setpoint;
+        }
+        let rate = (self.setpoint - self.sp_last) / dt;
+        self.sp_last = self.setpoint;
+        self.f_out = match self.feed_forward {
+            Some(ff) => {
+                let last = self.sp_rate;
+                self.sp_rate = match ff.smoothing {
+                    Some(cutoff) => lowpass(last, rate, cutoff, dt),
+                    None => rate,
+                };
+                let accel = (self.sp_rate - last) / dt;
+                ff.gain * (self.sp_rate + ff.boost * accel)
+            }
+            None => T::zero(),
+        };
+        self.relax = match self.iterm_relax {
+            Some(relax) if relax.threshold > T::zero() => {
+                self.sp_lowpass = lowpass(self.sp_lowpass, self.setpoint, relax.cutoff, dt);
+                let hp = (self.setpoint - self.sp_lowpass).abs();
+                (T::one() - hp / relax.threshold).max(T::zero())
+            }
+            _ => T::one(),
+        };
+    }
+
+    /// 清掉历史和积分。原来不清积分项，解锁后会带着上次飞行的积分
     pub fn reset(&mut self) {
         self.error.current = T::zero();
         self.error.last = T::zero();
         self.error.prev = T::zero();
 
+        self.measurement.current = T::zero();
+        self.measurement.last = T::zero();
+        self.measurement.prev = T::zero();
+
         self.derror.current = T::zero();
         self.derror.last = T::zero();
         self.derror.prev = T::zero();
+        self.samples = 0;
+        self.sp_rate = T::zero();
+        self.f_out = T::zero();
+        self.relax = T::one();
+        self.i_out = T::zero();
         self.out = T::zero();
     }
 }
 
+//一阶低通，cutoff为截止频率，单位Hz，不大于0时直通
+fn lowpass<T: FloatCore>(last: T, value: T, cutoff: T, dt: T) -> T {
+    if cutoff <= T::zero() {
+        return value;
+    }
+    let pi = T::from(core::f32::consts::PI).unwrap();
+    let rc = T::one() / (T::from(2.0).unwrap() * pi * cutoff);
+    let alpha = dt / (rc + dt);
+    last + alpha * (value - last)
+}
+
 #[inline]
 fn limit<T: FloatCore>(val: T, limit: Option<&Limit<T>>) -> T {
     if let Some(limit) = limit {
@@ -195,3 +467,205 @@ fn limit<T: FloatCore>(val: T, limit: Option<&Limit<T>>) -> T {
         val
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 0.001;
+
+    //目标值从0阶跃到setpoint，测量值保持为0，返回最后一次的输出
+    fn step(pid: &mut Pid<f32>, setpoint: f32, n: usize) -> f32 {
+        pid.next1(0.0, 0.0, DT);
+        let mut out = 0.0;
+        for _ in 0..n {
+            out = pid.next1(0.0, setpoint, DT);
+        }
+        out
+    }
+
+    #[test]
+    fn derivative_on_measurement_has_no_kick() {
+        let mut pid = Pid::new(0.0, 0.0, 0.01);
+        step(&mut pid, 1.0, 1);
+        //对误差求微分，阶跃时微分项为kd*1/dt
+        assert!((pid.d_out() - 10.0).abs() < 1e-3);
+
+        let mut pid = Pid::new(0.0, 0.0, 0.01).with_derivative(Derivative::Measurement);
+        step(&mut pid, 1.0, 1);
+        assert_eq!(pid.d_out(), 0.0);
+        //测量值变化时微分项与测量值变化方向相反
+        pid.next1(0.1, 1.0, DT);
+        assert!((pid.d_out() + 1.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn d_lowpass_smooths_derivative() {
+        //阶跃时不滤波的微分项只有一次kd/dt，滤波后峰值变小，之后逐渐衰减
+        let mut plain = Pid::new(0.0, 0.0, 0.01);
+        let mut filtered = Pid::new(0.0, 0.0, 0.01).with_d_lowpass(50.0);
+        step(&mut plain, 1.0, 1);
+        step(&mut filtered, 1.0, 1);
+        assert!((plain.d_out() - 10.0).abs() < 1e-3);
+        let first = filtered.d_out();
+        assert!(first > 0.0 && first < 0.5 * plain.d_out());
+        plain.next1(0.0, 1.0, DT);
+        filtered.next1(0.0, 1.0, DT);
+        assert_eq!(plain.d_out(), 0.0);
+        assert!(filtered.d_out() > 0.0 && filtered.d_out() < first);
+
+        //测量值在奈奎斯特频率上抖动，50Hz低通在1kHz时衰减到约14%
+        let noise = |pid: &mut Pid<f32>| {
+            let mut peak = 0.0f32;
+            for i in 0..200 {
+                let m = if i % 2 == 0 { 0.001 } else { -0.001 };
+                pid.next1(m, 0.0, DT);
+                if i >= 100 {
+                    peak = peak.max(pid.d_out().abs());
+                }
+            }
+            peak
+        };
+        let measurement = || Pid::new(0.0, 0.0, 0.01).with_derivative(Derivative::Measurement);
+        let plain = noise(&mut measurement());
+        let filtered = noise(&mut measurement().with_d_lowpass(50.0));
+        assert!((plain - 0.02).abs() < 1e-4);
+        assert!(filtered < 0.2 * plain);
+    }
+
+    #[test]
+    fn clamping_stops_integral_while_saturated() {
+        let mut pid = Pid::new(1.0, 10.0, 0.0)
+            .with_limit_out(Limit::new(-1.0, 1.0))
+            .with_anti_windup(AntiWindup::Clamping);
+        assert_eq!(step(&mut pid, 2.0, 1000), 1.0);
+        assert_eq!(pid.i_out(), 0.0);
+        //误差反向使输出退出饱和时恢复积分
+        pid.next1(2.5, 2.0, DT);
+        assert!((pid.i_out() + 0.005).abs() < 1e-6);
+
+        //不抗饱和时积分一直累加
+        let mut pid = Pid::new(1.0, 10.0, 0.0).with_limit_out(Limit::new(-1.0, 1.0));
+        assert_eq!(step(&mut pid, 2.0, 1000), 1.0);
+        assert!((pid.i_out() - 20.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn back_calculation_bounds_integral() {
+        //稳态时ki*e+kt*(sat-unsat)=0，积分收敛到max-kp*e+ki*e/kt
+        let (kp, ki, kt, e) = (0.5, 10.0, 50.0, 1.0);
+        let mut pid = Pid::new(kp, ki, 0.0)
+            .with_limit_out(Limit::new(-1.0, 1.0))
+            .with_anti_windup(AntiWindup::BackCalculation(kt));
+        assert_eq!(step(&mut pid, e, 2000), 1.0);
+        let expected = 1.0 - kp * e + ki * e / kt;
+        //离散计算比连续时间的稳态值小ki*e*dt
+        assert!((pid.i_out() - expected).abs() < 0.02);
+        //误差归零后很快退出饱和
+        let out = pid.next1(e, e, DT);
+        assert!(out < 1.0);
+    }
+
+    #[test]
+    fn reset_clears_integral() {
+        let mut pid = Pid::new(0.0, 1.0, 0.0);
+        step(&mut pid, 1.0, 100);
+        assert!(pid.i_out() > 0.0);
+        pid.reset();
+        assert_eq!(pid.i_out(), 0.0);
+        assert_eq!(pid.out(), 0.0);
+    }
+
+    //目标值以rate的速度斜坡上升，测量值保持为0，返回最后一次的输出
+    fn ramp(pid: &mut Pid<f32>, rate: f32, n: usize) -> f32 {
+        pid.next1(0.0, 0.0, DT);
+        let mut out = 0.0;
+        for i in 1..=n {
+            out = pid.next1(0.0, rate * i as f32 * DT, DT);
+        }
+        out
+    }
+
+    #[test]
+    fn feed_forward_follows_setpoint_rate() {
+        let mut pid = Pid::new(0.0, 0.0, 0.0).with_feed_forward(FeedForward::new(0.1));
+        //斜坡时前馈为gain*变化率
+        assert!((ramp(&mut pid, 2.0, 100) - 0.2).abs() < 1e-3);
+        assert_eq!(pid.out(), pid.f_out());
+        //阶跃只在跳变的一次有前馈
+        let mut pid = Pid::new(0.0, 0.0, 0.0).with_feed_forward(FeedForward::new(0.1));
+        step(&mut pid, 1.0, 1);
+        assert!((pid.f_out() - 0.1 / DT).abs() < 1e-2);
+        pid.next1(0.0, 1.0, DT);
+        assert_eq!(pid.f_out(), 0.0);
+    }
+
+    #[test]
+    fn feed_forward_boost() {
+        let ff = FeedForward {
+            boost: 0.01,
+            ..FeedForward::new(0.1)
+        };
+        let mut pid = Pid::new(0.0, 0.0, 0.0).with_feed_forward(ff);
+        //斜坡开始时变化率从0跳到rate，加速度项额外给出gain*boost*rate/dt
+        ramp(&mut pid, 2.0, 1);
+        assert!((pid.f_out() - 0.1 * (2.0 + 0.01 * 2.0 / DT)).abs() < 1e-2);
+        //之后变化率不变，只剩gain*rate
+        pid.next1(0.0, 2.0 * 2.0 * DT, DT);
+        assert!((pid.f_out() - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn feed_forward_smoothing() {
+        let ff = FeedForward {
+            smoothing: Some(20.0),
+            ..FeedForward::new(0.1)
+        };
+        let mut pid = Pid::new(0.0, 0.0, 0.0).with_feed_forward(ff);
+        pid.next1(0.0, 0.0, DT);
+        //阶跃的前馈被摊开，峰值变小，总量仍为gain*阶跃量
+        let mut sum = 0.0;
+        let mut peak = 0.0f32;
+        for _ in 0..500 {
+            let f = pid.next1(0.0, 1.0, DT);
+            peak = peak.max(f);
+            sum += f * DT;
+        }
+        //20Hz低通在1kHz时第一次只通过约11%
+        assert!(peak < 0.15 * 0.1 / DT);
+        assert!((sum - 0.1).abs() < 1e-3);
+        //斜坡稳定后与不平滑时一致
+        let mut pid = Pid::new(0.0, 0.0, 0.0).with_feed_forward(ff);
+        assert!((ramp(&mut pid, 2.0, 500) - 0.2).abs() < 1e-3);
+    }
+
+    #[test]
+    fn iterm_relax_suppresses_integral() {
+        let relax = ItermRelax {
+            cutoff: 10.0,
+            threshold: 0.5,
+        };
+        //阶跃后目标值与低通结果相差超过门限，积分停止；低通跟上后恢复
+        let mut pid = Pid::new(0.0, 10.0, 0.0).with_iterm_relax(relax);
+        step(&mut pid, 1.0, 10);
+        assert_eq!(pid.relax(), 0.0);
+        assert_eq!(pid.i_out(), 0.0);
+        step(&mut pid, 1.0, 500);
+        assert!((pid.relax() - 1.0).abs() < 1e-3);
+        let mut plain = Pid::new(0.0, 10.0, 0.0);
+        step(&mut plain, 1.0, 510);
+        assert!(pid.i_out() > 0.0 && pid.i_out() < plain.i_out());
+
+        //斜坡时低通滞后rate/(2π·cutoff)，积分按比例减小
+        let mut pid = Pid::new(0.0, 10.0, 0.0).with_iterm_relax(relax);
+        ramp(&mut pid, 2.0, 500);
+        let lag = 2.0 / (2.0 * core::f32::consts::PI * 10.0);
+        assert!((pid.relax() - (1.0 - lag / 0.5)).abs() < 0.01);
+    }
+
+    #[test]
+    fn limit_proportion() {
+        let mut pid = Pid::new(10.0, 0.0, 0.0).with_limit_proportion(Limit::new(-1.0, 1.0));
+        assert_eq!(step(&mut pid, 1.0, 1), 1.0);
+    }
+}
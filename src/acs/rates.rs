@@ -0,0 +1,301 @@
+//! 摇杆速率曲线
+//!
+//! 把-1.0~1.0的摇杆量转成目标角速度，单位度每秒，曲线和参数与Betaflight一致：
+//! - Betaflight: RC rate、super rate、expo
+//! - Actual: 中位灵敏度、最大角速度、expo
+//! - KISS: RC rate、rate、RC curve
+//!
+//! 参数按Betaflight的整数格式保存，MSP_RC_TUNING可以直接读写。
+//!
+use crate::acs::gain_schedule::TpaConfig;
+use spin::Mutex;
+
+/// 角速度上限，单位度每秒
+pub const RATE_LIMIT_MAX: u16 = 1998;
+/// MSP_RC_TUNING的长度
+pub const RC_TUNING_SIZE: usize = 23;
+
+static PROFILE: Mutex<RateProfile> = Mutex::new(RateProfile::new());
+
+/// 曲线类型，编号与Betaflight的rates_type一致，没有实现Raceflight(1)和Quick(4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatesType {
+    Betaflight = 0,
+    Kiss = 2,
+    Actual = 3,
+}
+
+impl RatesType {
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(RatesType::Betaflight),
+            2 => Some(RatesType::Kiss),
+            3 => Some(RatesType::Actual),
+            _ => None,
+        }
+    }
+}
+
+/// 单轴参数，含义随曲线类型变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AxisRate {
+    pub rc_rate: u8, //Betaflight: RC rate*100；KISS: RC rate*1000；Actual: 中位灵敏度/10
+    pub rate: u8,    //Betaflight: super rate*100；KISS: rate*100；Actual: 最大角速度/10
+    pub expo: u8,    //expo*100
+    pub rate_limit: u16, //角速度上限，单位度每秒
+}
+
+impl AxisRate {
+    pub const fn new(rc_rate: u8, rate: u8, expo: u8) -> Self {
+        Self {
+            rc_rate,
+            rate,
+            expo,
+            rate_limit: RATE_LIMIT_MAX,
+        }
+    }
+}
+
+/// 速率配置，下标0、1、2依次为横滚、俯仰、偏航
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateProfile {
+    pub rates_type: RatesType,
+    pub axes: [AxisRate; 3],
+    pub tpa: TpaConfig, //油门PID衰减，和Betaflight一样放在速率配置里
+}
+
+impl RateProfile {
+    /// Betaflight 4.x的默认值
+    pub const fn new() -> Self {
+        Self {
+            rates_type: RatesType::Betaflight,
+            axes: [
+                AxisRate::new(100, 70, 0),
+                AxisRate::new(100, 70, 0),
+                AxisRate::new(100, 70, 0),
+            ],
+            tpa: TpaConfig::new(),
+        }
+    }
+
+    /// 摇杆量转目标角速度，单位度每秒
+    pub fn rate(&self, axis: usize, stick: f32) -> f32 {
+        let ax = &self.axes[axis];
+        let stick = stick.max(-1.0).min(1.0);
+        let rate = match self.rates_type {
+            RatesType::Actual => actual(ax, stick),
+            RatesType::Kiss => kiss(ax, stick),
+            RatesType::Betaflight => betaflight(ax, stick),
+        };
+        let limit = ax.rate_limit.min(RATE_LIMIT_MAX) as f32;
+        rate.max(-limit).min(limit)
+    }
+
+    /// MSP_RC_TUNING格式，油门中点和油门曲线没有实现，固定为50和0
+    pub fn to_bytes(&self) -> [u8; RC_TUNING_SIZE] {
+        let [roll, pitch, yaw] = self.axes;
+        //四舍五入，0.59*100在f32下是58.999996
+        let tpa_rate = libm::roundf(self.tpa.rate * 100.0) as u8;
+        let breakpoint = 1000 + libm::roundf(self.tpa.breakpoint * 1000.0) as u16;
+        let mut b = [0u8; RC_TUNING_SIZE];
+        b[..10].copy_from_slice(&[
+            roll.rc_rate,
+            roll.expo,
+            roll.rate,
+            pitch.rate,
+            yaw.rate,
+            tpa_rate,
+            50,
+            0,
+            breakpoint as u8,
+            (breakpoint >> 8) as u8,
+        ]);
+        b[10..16].copy_from_slice(&[yaw.expo, yaw.rc_rate, pitch.rc_rate, pitch.expo, 0, 100]);
+        for (i, ax) in self.axes.iter().enumerate() {
+            b[16 + i * 2..18 + i * 2].copy_from_slice(&ax.rate_limit.to_le_bytes());
+        }
+        b[22] = self.rates_type as u8;
+        b
+    }
+
+    /// 按MSP_SET_RC_TUNING更新，老版本地面站发来的短数据只更新已有字段，长度不足10字节或曲线类型无效时返回None
+    pub fn from_bytes(&self, b: &[u8]) -> Option<Self> {
+        if b.len() < 10 {
+            return None;
+        }
+        let mut p = *self;
+        p.axes[0].rc_rate = b[0];
+        p.axes[0].expo = b[1];
+        p.axes[0].rate = b[2];
+        p.axes[1].rate = b[3];
+        p.axes[2].rate = b[4];
+        p.tpa.rate = b[5].min(100) as f32 / 100.0;
+        //b[6]、b[7]为油门中点和油门曲线
+        let breakpoint = u16::from_le_bytes([b[8], b[9]]).max(1000).min(2000);
+        p.tpa.breakpoint = (breakpoint - 1000) as f32 / 1000.0;
+        if b.len() >= 11 {
+            p.axes[2].expo = b[10];
+        }
+        if b.len() >= 12 {
+            p.axes[2].rc_rate = b[11];
+        }
+        if b.len() >= 13 {
+            p.axes[1].rc_rate = b[12];
+        }
+        if b.len() >= 14 {
+            p.axes[1].expo = b[13];
+        }
+        //b[14]、b[15]为油门限制
+        if b.len() >= 22 {
+            for (i, ax) in p.axes.iter_mut().enumerate() {
+                let limit = u16::from_le_bytes([b[16 + i * 2], b[17 + i * 2]]);
+                ax.rate_limit = limit.min(RATE_LIMIT_MAX);
+            }
+        }
+        if b.len() >= 23 {
+            p.rates_type = RatesType::from_u8(b[22])?;
+        }
+        Some(p)
+    }
+}
+
+impl Default for RateProfile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Betaflight曲线
+pub fn betaflight(ax: &AxisRate, stick: f32) -> f32 {
+    let abs = stick.abs();
+    let expo = ax.expo as f32 / 100.0;
+    let stick = if expo > 0.0 {
+        stick * abs * abs * abs * expo + stick * (1.0 - expo)
+    } else {
+        stick
+    };
+    let mut rc_rate = ax.rc_rate as f32 / 100.0;
+    if rc_rate > 2.0 {
+        rc_rate += 14.54 * (rc_rate - 2.0);
+    }
+    let mut rate = 200.0 * rc_rate * stick;
+    if ax.rate > 0 {
+        let factor = 1.0 / (1.0 - abs * ax.rate as f32 / 100.0).max(0.01).min(1.0);
+        rate *= factor;
+    }
+    rate
+}
+
+/// Actual曲线
+pub fn actual(ax: &AxisRate, stick: f32) -> f32 {
+    let abs = stick.abs();
+    let expo = ax.expo as f32 / 100.0;
+    let s5 = stick * abs * abs * abs * abs;
+    let expof = abs * (s5 * expo + stick * (1.0 - expo));
+    let center = ax.rc_rate as f32 * 10.0;
+    let movement = (ax.rate as f32 * 10.0 - center).max(0.0);
+    stick * center + movement * expof
+}
+
+/// KISS曲线
+pub fn kiss(ax: &AxisRate, stick: f32) -> f32 {
+    let abs = stick.abs();
+    let factor = 1.0 / (1.0 - abs * ax.rate as f32 / 100.0).max(0.01).min(1.0);
+    let curve = ax.expo as f32 / 100.0;
+    let command =
+        (stick * stick * stick * curve + stick * (1.0 - curve)) * ax.rc_rate as f32 / 1000.0;
+    2000.0 * factor * command
+}
+
+/// 当前速率配置
+pub fn profile() -> RateProfile {
+    *PROFILE.lock()
+}
+
+pub fn set_profile(profile: RateProfile) {
+    *PROFILE.lock() = profile;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 0.01
+    }
+
+    //参考值按Betaflight的applyBetaflightRates、applyActualRates、applyKissRates计算，
+    //默认参数满杆667°/s、Actual默认670°/s与Betaflight地面站显示一致
+    #[test]
+    fn betaflight_rates() {
+        let ax = AxisRate::new(100, 70, 0);
+        assert_eq!(betaflight(&ax, 0.0), 0.0);
+        assert!(close(betaflight(&ax, 0.5), 153.85));
+        assert!(close(betaflight(&ax, 1.0), 666.67));
+        assert!(close(betaflight(&ax, -1.0), -666.67));
+        let ax = AxisRate::new(100, 70, 30);
+        assert!(close(betaflight(&ax, 0.5), 113.46));
+        assert!(close(betaflight(&ax, 1.0), 666.67));
+        //RC rate超过2.0后按14.54倍增长
+        let ax = AxisRate::new(220, 0, 0);
+        assert!(close(betaflight(&ax, 1.0), 1021.6));
+    }
+
+    #[test]
+    fn actual_rates() {
+        let ax = AxisRate::new(7, 67, 54);
+        assert_eq!(actual(&ax, 0.0), 0.0);
+        assert!(close(actual(&ax, 0.5), 109.06));
+        assert!(close(actual(&ax, 1.0), 670.0));
+        assert!(close(actual(&ax, -0.5), -109.06));
+    }
+
+    #[test]
+    fn kiss_rates() {
+        let ax = AxisRate::new(100, 70, 0);
+        assert_eq!(kiss(&ax, 0.0), 0.0);
+        assert!(close(kiss(&ax, 0.5), 153.85));
+        assert!(close(kiss(&ax, 1.0), 666.67));
+        let ax = AxisRate::new(100, 70, 40);
+        assert!(close(kiss(&ax, 0.5), 107.69));
+        assert!(close(kiss(&ax, 1.0), 666.67));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut profile = RateProfile::new();
+        profile.axes[0] = AxisRate::new(255, 80, 0);
+        assert_eq!(profile.rate(0, 1.0), RATE_LIMIT_MAX as f32);
+        profile.axes[0].rate_limit = 800;
+        assert_eq!(profile.rate(0, -1.0), -800.0);
+        //摇杆量超出范围按满杆计算
+        assert_eq!(profile.rate(1, 2.0), profile.rate(1, 1.0));
+    }
+
+    #[test]
+    fn rc_tuning_round_trip() {
+        let mut profile = RateProfile::new();
+        profile.rates_type = RatesType::Actual;
+        profile.axes = [
+            AxisRate::new(7, 67, 54),
+            AxisRate::new(8, 60, 50),
+            AxisRate::new(6, 40, 20),
+        ];
+        profile.axes[2].rate_limit = 500;
+        profile.tpa.rate = 0.59;
+        profile.tpa.breakpoint = 0.35;
+        let bytes = profile.to_bytes();
+        assert_eq!(bytes[5], 59);
+        assert_eq!(u16::from_le_bytes([bytes[8], bytes[9]]), 1350);
+        assert_eq!(RateProfile::new().from_bytes(&bytes), Some(profile));
+        //老版本地面站的短数据只更新前面的字段
+        let p = RateProfile::new().from_bytes(&bytes[..10]).unwrap();
+        assert_eq!(p.axes[0], profile.axes[0]);
+        assert_eq!(p.axes[1].rc_rate, 100);
+        assert_eq!(p.rates_type, RatesType::Betaflight);
+        assert_eq!(RateProfile::new().from_bytes(&bytes[..9]), None);
+        let mut bad = bytes;
+        bad[22] = 1;
+        assert_eq!(RateProfile::new().from_bytes(&bad), None);
+    }
+}
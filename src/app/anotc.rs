@@ -1,6 +1,6 @@
 //! 匿名上位机通信协议
 
-use crate::driver::{Accel, Gyro, Quaternion};
+use crate::driver::{Accel, Gyro, Quaternion, IMU_SAMPLE_HZ};
 use crate::filter::first_order::FirstOrderFilter;
 use crate::filter::limiting::LimitingFilter;
 use crate::filter::moving_average::MovingAverageFilter;
@@ -56,10 +56,8 @@ fn sync() {
     buf.push(sum);
     buf.push(check);
     mbus::bus().call("/telem/tx", Message::Telem(Telem::Multiwii(buf)));
-    #[cfg(feature = "mpu9250")]
-    let m = 1;
-    #[cfg(any(feature = "mpu6050", feature = "icm20602"))]
-    let m = 10;
+    //姿态100Hz，指示灯10Hz翻转
+    let m = IMU_SAMPLE_HZ as u64 / 100;
 
     loop {
         if let Some(msg) = recv.pop_front() {
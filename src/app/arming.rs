@@ -0,0 +1,379 @@
+//! 解锁/上锁
+//!
+//! 支持摇杆手势和辅助通道开关两种方式，解锁前逐项做预检，任何一项不通过都拒绝解锁。
+//! 未通过的检查项通过MSP_STATUS的arming disable标志上报，红灯闪烁次数表示第一个未通过的检查项，
+//! 解锁状态变化发布到/arming。
+//!
+use crate::app::failsafe::{FailsafeAction, FailsafeStage};
+use crate::mbus;
+use crate::message::*;
+use bitflags::bitflags;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crossbeam::atomic::AtomicCell;
+use nalgebra::UnitQuaternion;
+use xtask::TaskBuilder;
+
+/// 检查周期，单位毫秒
+const PERIOD_MS: u32 = 10;
+/// 超过这个时间没有姿态数据认为IMU异常
+const IMU_TIMEOUT_MS: u32 = 100;
+/// 红灯闪烁节拍
+const LED_SLOT_MS: u32 = 200;
+
+static IMU_COUNT: AtomicU32 = AtomicU32::new(0);
+static ATTITUDE: AtomicCell<Option<UnitQuaternion<f32>>> = AtomicCell::new(None);
+static RC: AtomicCell<Option<RemoteControl>> = AtomicCell::new(None);
+static FAILSAFE: AtomicCell<FailsafeStage> = AtomicCell::new(FailsafeStage::Idle);
+static ARMED: AtomicBool = AtomicBool::new(false);
+static DISABLE_FLAGS: AtomicU32 = AtomicU32::new(0);
+static MSP_DISABLED: AtomicBool = AtomicBool::new(false);
+static MODE: AtomicCell<Option<ArmingMode>> = AtomicCell::new(None);
+static mut CONFIG: ArmingConfig = ArmingConfig::new();
+
+bitflags! {
+    /// 禁止解锁的原因，位定义与Betaflight一致，地面站可直接显示
+    pub struct ArmingDisableFlags: u32 {
+        const NO_GYRO = 1 << 0;
+        const RX_FAILSAFE = 1 << 2;
+        const THROTTLE = 1 << 7;
+        const ANGLE = 1 << 8;
+        const CALIBRATING = 1 << 12;
+        const MSP = 1 << 16;
+        const ARM_SWITCH = 1 << 25;
+    }
+}
+
+/// Betaflight定义的禁止解锁标志个数
+pub const ARMING_DISABLE_FLAGS_COUNT: u8 = 26;
+
+/// 预检顺序，决定红灯闪几下
+const CHECKS: [ArmingDisableFlags; 7] = [
+    ArmingDisableFlags::NO_GYRO,
+    ArmingDisableFlags::CALIBRATING,
+    ArmingDisableFlags::RX_FAILSAFE,
+    ArmingDisableFlags::THROTTLE,
+    ArmingDisableFlags::ANGLE,
+    ArmingDisableFlags::ARM_SWITCH,
+    ArmingDisableFlags::MSP,
+];
+
+/// 解锁状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmingState {
+    Disarmed,
+    Armed,
+}
+
+pub fn start() {
+    mbus::bus().subscribe("/imu", |_, msg| match msg {
+        Message::ImuData(data) => {
+            ATTITUDE.store(data.quaternion);
+            IMU_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        _ => {}
+    });
+    mbus::bus().subscribe("/rc", |_, msg| match msg {
+        Message::RemoteControl(rc) => RC.store(Some(rc)),
+        _ => {}
+    });
+    mbus::bus().subscribe("/failsafe", |_, msg| match msg {
+        Message::Failsafe(stage) => FAILSAFE.store(stage),
+        _ => {}
+    });
+    TaskBuilder::new()
+        .name("arming")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(|| unsafe {
+            let mut arming = Arming::new(CONFIG);
+            let mut now_ms = 0u32;
+            let mut imu_count = IMU_COUNT.load(Ordering::Relaxed);
+            let mut imu_ms = None;
+            let mut led = None;
+            loop {
+                if arming.state() == ArmingState::Disarmed {
+                    if let Some(mode) = MODE.take() {
+                        log::info!("Arming mode {:?}", mode);
+                        arming.set_mode(mode);
+                    }
+                }
+                let n = IMU_COUNT.load(Ordering::Relaxed);
+                if n != imu_count {
+                    imu_count = n;
+                    imu_ms = Some(now_ms);
+                }
+                let failsafe = FAILSAFE.load();
+                let input = Input {
+                    imu: imu_ms
+                        .map(|t: u32| now_ms.wrapping_sub(t) <= IMU_TIMEOUT_MS)
+                        .unwrap_or(false),
+                    gyro_calibrated: super::imu::gyro_calibrated(),
+                    attitude: ATTITUDE.load(),
+                    rc: RC.load(),
+                    rc_healthy: failsafe == FailsafeStage::Idle,
+                    msp_disabled: MSP_DISABLED.load(Ordering::Relaxed),
+                };
+                let flags = arming.check(&input);
+                DISABLE_FLAGS.store(flags.bits(), Ordering::Relaxed);
+                let transition = if failsafe == FailsafeStage::Active(FailsafeAction::Disarm) {
+                    arming.disarm()
+                } else {
+                    arming.update(now_ms, &input, flags)
+                };
+                if let Some(state) = transition {
+                    log::info!("{:?}", state);
+                    ARMED.store(state == ArmingState::Armed, Ordering::Relaxed);
+                    mbus::bus().publish("/arming", Message::Arming(state));
+                }
+                let on = (led_pattern(arming.state(), flags, now_ms), arming.state());
+                if led != Some(on) {
+                    led = Some(on);
+                    let (r, g) = match on {
+                        (_, ArmingState::Armed) => ("/led/r/off", "/led/g/on"),
+                        (true, _) => ("/led/r/on", "/led/g/off"),
+                        (false, _) => ("/led/r/off", "/led/g/off"),
+                    };
+                    mbus::bus().call(r, Message::Control(Signal::Led));
+                    mbus::bus().call(g, Message::Control(Signal::Led));
+                }
+                xtask::delay_us(PERIOD_MS * 1000);
+                now_ms = now_ms.wrapping_add(PERIOD_MS);
+            }
+        });
+}
+
+/// 修改解锁参数，需在start之前调用
+pub fn configure(config: ArmingConfig) {
+    unsafe {
+        CONFIG = config;
+    }
+}
+
+/// 运行中修改解锁方式，地面站设置ARM模式范围时调用，上锁状态下才生效
+pub fn set_mode(mode: ArmingMode) {
+    MODE.store(Some(mode));
+}
+
+/// 是否已解锁
+pub fn armed() -> bool {
+    ARMED.load(Ordering::Relaxed)
+}
+
+/// 当前禁止解锁的原因
+pub fn disable_flags() -> ArmingDisableFlags {
+    ArmingDisableFlags::from_bits_truncate(DISABLE_FLAGS.load(Ordering::Relaxed))
+}
+
+/// 地面站连接时禁止解锁
+pub fn set_msp_disabled(disabled: bool) {
+    MSP_DISABLED.store(disabled, Ordering::Relaxed);
+}
+
+//已解锁时绿灯常亮；未解锁时预检通过红灯常亮，否则红灯按第一个未通过的检查项闪烁
+fn led_pattern(state: ArmingState, flags: ArmingDisableFlags, now_ms: u32) -> bool {
+    if state == ArmingState::Armed {
+        return true;
+    }
+    match CHECKS.iter().position(|f| flags.contains(*f)) {
+        Some(i) => {
+            let blinks = i as u32 + 1;
+            let slot = (now_ms / LED_SLOT_MS) % (blinks * 2 + 4);
+            slot < blinks * 2 && slot % 2 == 0
+        }
+        None => true,
+    }
+}
+
+/// 解锁方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArmingMode {
+    /// 油门最低，偏航打到最右保持解锁；偏航打到最左保持上锁
+    Stick,
+    /// 辅助通道在[min, max]微秒范围内解锁，否则上锁
+    Switch { aux: u8, min: u16, max: u16 },
+}
+
+/// 解锁参数
+#[derive(Debug, Clone, Copy)]
+pub struct ArmingConfig {
+    pub mode: ArmingMode,
+    pub low_throttle: f32,  //油门低于这个值认为在最低位
+    pub max_tilt: f32,      //允许解锁的最大倾角，单位弧度
+    pub stick_hold_ms: u32, //手势保持时间
+}
+
+impl ArmingConfig {
+    pub const fn new() -> Self {
+        Self {
+            mode: ArmingMode::Stick,
+            low_throttle: 0.05,
+            max_tilt: 25.0 * core::f32::consts::PI / 180.0,
+            stick_hold_ms: 1000,
+        }
+    }
+}
+
+impl Default for ArmingConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 预检输入
+#[derive(Debug, Clone, Copy)]
+pub struct Input {
+    pub imu: bool,                             //IMU数据正常
+    pub gyro_calibrated: bool,                 //陀螺仪校准完成
+    pub attitude: Option<UnitQuaternion<f32>>, //当前姿态
+    pub rc: Option<RemoteControl>,             //最近一次遥控指令
+    pub rc_healthy: bool,                      //遥控链路正常
+    pub msp_disabled: bool,                    //地面站禁止解锁
+}
+
+/// 解锁状态机
+#[derive(Debug, Clone, Copy)]
+pub struct Arming {
+    config: ArmingConfig,
+    state: ArmingState,
+    gesture_ms: Option<u32>, //手势开始时刻
+    switch_off_seen: bool,   //开关解锁时要求先看到开关处于上锁位置
+}
+
+impl Arming {
+    pub const fn new(config: ArmingConfig) -> Self {
+        Self {
+            config,
+            state: ArmingState::Disarmed,
+            gesture_ms: None,
+            switch_off_seen: false,
+        }
+    }
+
+    pub fn state(&self) -> ArmingState {
+        self.state
+    }
+
+    /// 预检，返回所有未通过的检查项
+    pub fn check(&self, input: &Input) -> ArmingDisableFlags {
+        let mut flags = ArmingDisableFlags::empty();
+        flags.set(ArmingDisableFlags::NO_GYRO, !input.imu);
+        flags.set(ArmingDisableFlags::CALIBRATING, !input.gyro_calibrated);
+        flags.set(
+            ArmingDisableFlags::RX_FAILSAFE,
+            !input.rc_healthy || input.rc.is_none(),
+        );
+        if let Some(rc) = input.rc {
+            flags.set(
+                ArmingDisableFlags::THROTTLE,
+                rc.throttle > self.config.low_throttle,
+            );
+        }
+        flags.set(
+            ArmingDisableFlags::ANGLE,
+            input
+                .attitude
+                .map(|q| tilt(&q) > self.config.max_tilt)
+                .unwrap_or(true),
+        );
+        if let ArmingMode::Switch { .. } = self.config.mode {
+            flags.set(ArmingDisableFlags::ARM_SWITCH, !self.switch_off_seen);
+        }
+        flags.set(ArmingDisableFlags::MSP, input.msp_disabled);
+        flags
+    }
+
+    /// 修改解锁方式，换成开关方式后要先看到开关处于上锁位置
+    pub fn set_mode(&mut self, mode: ArmingMode) {
+        if self.config.mode != mode {
+            self.config.mode = mode;
+            self.gesture_ms = None;
+            self.switch_off_seen = false;
+        }
+    }
+
+    /// 强制上锁
+    pub fn disarm(&mut self) -> Option<ArmingState> {
+        self.gesture_ms = None;
+        self.transition(ArmingState::Disarmed)
+    }
+
+    /// 处理解锁/上锁请求，状态变化时返回新状态
+    pub fn update(
+        &mut self,
+        now_ms: u32,
+        input: &Input,
+        flags: ArmingDisableFlags,
+    ) -> Option<ArmingState> {
+        let rc = input.rc?;
+        let request = match self.config.mode {
+            ArmingMode::Stick => self.gesture(now_ms, &rc),
+            ArmingMode::Switch { aux, min, max } => {
+                let on = (aux as usize) < rc.aux_count as usize
+                    && (min..=max).contains(&rc.aux[aux as usize]);
+                if !on {
+                    self.switch_off_seen = true;
+                }
+                Some(on)
+            }
+        };
+        match request {
+            Some(true) if self.state == ArmingState::Disarmed => {
+                if flags.is_empty() {
+                    self.transition(ArmingState::Armed)
+                } else {
+                    //开关在解锁位置时被拒绝，要等开关拨回上锁位置才能再次解锁，防止预检通过的瞬间突然解锁
+                    if let ArmingMode::Switch { .. } = self.config.mode {
+                        self.switch_off_seen = false;
+                    }
+                    None
+                }
+            }
+            Some(false) => self.transition(ArmingState::Disarmed),
+            _ => None,
+        }
+    }
+
+    //手势保持够时间后返回解锁(true)或上锁(false)请求
+    fn gesture(&mut self, now_ms: u32, rc: &RemoteControl) -> Option<bool> {
+        let low = rc.throttle <= self.config.low_throttle;
+        let request = if low && rc.yaw > 0.9 {
+            Some(true)
+        } else if low && rc.yaw < -0.9 {
+            Some(false)
+        } else {
+            None
+        };
+        match request {
+            Some(arm) => {
+                let since = *self.gesture_ms.get_or_insert(now_ms);
+                if now_ms.wrapping_sub(since) >= self.config.stick_hold_ms {
+                    Some(arm)
+                } else {
+                    None
+                }
+            }
+            None => {
+                self.gesture_ms = None;
+                None
+            }
+        }
+    }
+
+    fn transition(&mut self, state: ArmingState) -> Option<ArmingState> {
+        if state == self.state {
+            return None;
+        }
+        self.state = state;
+        if state == ArmingState::Disarmed {
+            //开关解锁的，上锁后要先拨回上锁位置才能再次解锁
+            self.switch_off_seen = false;
+        }
+        Some(state)
+    }
+}
+
+/// 机体倾角，机体z轴与竖直方向的夹角
+pub fn tilt(q: &UnitQuaternion<f32>) -> f32 {
+    let cos = 1.0 - 2.0 * (q.i * q.i + q.j * q.j);
+    libm::acosf(cos.max(-1.0).min(1.0))
+}
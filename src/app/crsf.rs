@@ -0,0 +1,86 @@
+//! CRSF遥测回传，把姿态、电池和飞行模式发给接收机
+//!
+use crate::app::failsafe::{self, FailsafeStage};
+use crate::app::{arming, modes};
+use crate::driver::crsf;
+use crate::driver::{Battery, IMU_SAMPLE_HZ};
+use crate::mbus;
+use crate::message::*;
+use alloc::string::String;
+use xtask::{Queue, TaskBuilder};
+
+static mut Q: Option<Queue<Message>> = None;
+
+pub fn start() {
+    let q = Queue::with_capacity(100);
+    unsafe {
+        Q.replace(q);
+    }
+    TaskBuilder::new()
+        .name("crsf")
+        .stack_size(1024)
+        .spawn(telemetry);
+    mbus::bus().subscribe("/imu", move |_, msg| push(msg));
+    mbus::bus().subscribe("/battery", move |_, msg| push(msg));
+}
+
+fn push(msg: Message) {
+    match msg {
+        Message::ImuData(_) | Message::Battery(_) => {
+            let q: &'static Queue<Message> = unsafe { Q.as_ref().unwrap() };
+            if let Err(err) = q.push_back_isr(msg) {
+                log::error!("error {:?}", err);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn telemetry() {
+    let recv: &'static Queue<Message> = unsafe { Q.as_ref().unwrap() };
+    let mut imu_count = 0u64;
+    let mut battery = Battery::default();
+    //姿态10Hz，电池和飞行模式1Hz
+    let m = IMU_SAMPLE_HZ as u64 / 10;
+    loop {
+        if let Some(msg) = recv.pop_front() {
+            match msg {
+                Message::ImuData(data) => {
+                    if imu_count % m == 0 {
+                        if let Some(euler) = data.euler {
+                            send(crsf::attitude(euler.pitch, euler.roll, euler.yaw));
+                        }
+                    }
+                    if imu_count % (m * 10) == 0 {
+                        send(crsf::battery(
+                            battery.voltage,
+                            battery.current,
+                            battery.capacity,
+                            battery.remaining,
+                        ));
+                        send(crsf::flight_mode(&flight_mode()));
+                    }
+                    imu_count += 1;
+                }
+                Message::Battery(b) => battery = b,
+                _ => {}
+            }
+        }
+    }
+}
+
+//和Betaflight一样，失控保护时显示!FS!，未解锁时在模式后加*
+fn flight_mode() -> String {
+    if let FailsafeStage::Active(_) = failsafe::stage() {
+        return String::from("!FS!");
+    }
+    let mut name = String::from(modes::active().short_name());
+    if !arming::armed() {
+        name.push('*');
+    }
+    name
+}
+
+fn send(frame: alloc::vec::Vec<u8>) {
+    mbus::bus().call("/crsf/tx", Message::Telem(Telem::Raw(frame)));
+}
@@ -0,0 +1,380 @@
+//! 失控保护
+//!
+//! 监测遥控帧的新鲜度和接收机的失控标志，链路中断后分阶段处理:
+//! 1. 保持: 宽限期内保持最后一次指令，期间恢复则回到正常
+//! 2. 动作: 按配置改平下降、缓降着陆或直接上锁，链路持续恢复一段时间后退出
+//!
+//! 每次阶段切换都发布到/failsafe，动作阶段由本模块在/rc上发布替代指令。
+//!
+use crate::driver;
+use crate::mbus;
+use crate::message::*;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use crossbeam::atomic::AtomicCell;
+use xtask::TaskBuilder;
+
+/// 监测周期，单位毫秒，各阶段的时间按系统时钟计算，与实际周期无关
+const PERIOD_MS: u32 = 10;
+
+static FRAMES: AtomicU32 = AtomicU32::new(0);
+static RX_FAILSAFE: AtomicBool = AtomicBool::new(false);
+static STAGE: AtomicCell<FailsafeStage> = AtomicCell::new(FailsafeStage::Idle);
+static LAST: AtomicCell<Option<RemoteControl>> = AtomicCell::new(None);
+static mut CONFIG: FailsafeConfig = FailsafeConfig::new();
+
+pub fn start() {
+    mbus::bus().subscribe("/rc/raw", |_, msg| match msg {
+        Message::RcChannels(rc) => {
+            RX_FAILSAFE.store(rc.failsafe, Ordering::Relaxed);
+            FRAMES.fetch_add(1, Ordering::Relaxed);
+        }
+        _ => {}
+    });
+    mbus::bus().subscribe("/rc", |_, msg| match msg {
+        Message::RemoteControl(rc) if !rc.failsafe => LAST.store(Some(rc)),
+        _ => {}
+    });
+    TaskBuilder::new()
+        .name("failsafe")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(|| unsafe {
+            let mut monitor = FailsafeMonitor::new(CONFIG);
+            let mut frames = FRAMES.load(Ordering::Relaxed);
+            loop {
+                let now_ms = driver::now_ms();
+                let n = FRAMES.load(Ordering::Relaxed);
+                if n != frames {
+                    frames = n;
+                    monitor.on_frame(now_ms, RX_FAILSAFE.load(Ordering::Relaxed));
+                }
+                if let Some(stage) = monitor.update(now_ms) {
+                    log::warn!("failsafe {:?}", stage);
+                    STAGE.store(stage);
+                    mbus::bus().publish("/failsafe", Message::Failsafe(stage));
+                }
+                if let FailsafeStage::Active(_) = monitor.stage() {
+                    let last = LAST.load().unwrap_or_default();
+                    mbus::bus().publish(
+                        "/rc",
+                        Message::RemoteControl(monitor.command(&last, now_ms)),
+                    );
+                }
+                xtask::delay_us(PERIOD_MS * 1000);
+            }
+        });
+}
+
+/// 当前失控保护阶段
+pub fn stage() -> FailsafeStage {
+    STAGE.load()
+}
+
+/// 修改失控保护参数，需在start之前调用
+pub fn configure(config: FailsafeConfig) {
+    unsafe {
+        CONFIG = config;
+    }
+}
+
+/// 失控动作
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailsafeAction {
+    /// 改平并以固定油门下降，持续一段时间后上锁
+    LevelDescend,
+    /// 改平并从当前油门逐渐收到零，然后上锁
+    Land,
+    /// 直接上锁
+    Disarm,
+}
+
+/// 失控保护阶段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailsafeStage {
+    /// 链路正常
+    Idle,
+    /// 链路中断，宽限期内保持最后一次指令
+    Hold,
+    /// 执行失控动作
+    Active(FailsafeAction),
+}
+
+/// 失控保护参数，时间单位毫秒
+#[derive(Debug, Clone, Copy)]
+pub struct FailsafeConfig {
+    pub timeout_ms: u32,        //超过这个时间没有有效帧认为链路中断
+    pub grace_ms: u32,          //保持阶段时长
+    pub action: FailsafeAction, //失控动作
+    pub descent_throttle: f32,  //改平下降的油门
+    pub descent_ms: u32,        //下降或着陆时长，到时上锁
+    pub recovery_ms: u32,       //动作阶段中链路需持续正常这么久才退出
+}
+
+impl FailsafeConfig {
+    pub const fn new() -> Self {
+        Self {
+            timeout_ms: 300,
+            grace_ms: 1000,
+            action: FailsafeAction::LevelDescend,
+            descent_throttle: 0.3,
+            descent_ms: 10_000,
+            recovery_ms: 1000,
+        }
+    }
+}
+
+impl Default for FailsafeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 失控保护状态机
+#[derive(Debug, Clone, Copy)]
+pub struct FailsafeMonitor {
+    config: FailsafeConfig,
+    stage: FailsafeStage,
+    last_good_ms: Option<u32>, //最后一次有效帧
+    lost_ms: u32,              //链路中断时刻
+    active_ms: u32,            //进入动作阶段时刻
+    good_ms: Option<u32>,      //动作阶段中链路恢复时刻
+}
+
+impl FailsafeMonitor {
+    pub const fn new(config: FailsafeConfig) -> Self {
+        Self {
+            config,
+            stage: FailsafeStage::Idle,
+            last_good_ms: None,
+            lost_ms: 0,
+            active_ms: 0,
+            good_ms: None,
+        }
+    }
+
+    pub fn stage(&self) -> FailsafeStage {
+        self.stage
+    }
+
+    /// 收到一帧，rx_failsafe为接收机自带的失控标志
+    pub fn on_frame(&mut self, now_ms: u32, rx_failsafe: bool) {
+        if !rx_failsafe {
+            self.last_good_ms = Some(now_ms);
+        }
+    }
+
+    /// 链路是否正常
+    pub fn healthy(&self, now_ms: u32) -> bool {
+        self.last_good_ms
+            .map(|t| now_ms.wrapping_sub(t) <= self.config.timeout_ms)
+            .unwrap_or(false)
+    }
+
+    /// 推进状态机，阶段发生变化时返回新阶段
+    pub fn update(&mut self, now_ms: u32) -> Option<FailsafeStage> {
+        let healthy = self.healthy(now_ms);
+        let next = match self.stage {
+            //从未收到过有效帧时不触发
+            FailsafeStage::Idle if !healthy && self.last_good_ms.is_some() => {
+                self.lost_ms = now_ms;
+                FailsafeStage::Hold
+            }
+            FailsafeStage::Hold if healthy => FailsafeStage::Idle,
+            FailsafeStage::Hold if now_ms.wrapping_sub(self.lost_ms) >= self.config.grace_ms => {
+                self.enter(now_ms, self.config.action)
+            }
+            FailsafeStage::Active(action) => {
+                if healthy {
+                    let since = *self.good_ms.get_or_insert(now_ms);
+                    if now_ms.wrapping_sub(since) >= self.config.recovery_ms {
+                        FailsafeStage::Idle
+                    } else {
+                        self.stage
+                    }
+                } else {
+                    self.good_ms = None;
+                    if action != FailsafeAction::Disarm
+                        && now_ms.wrapping_sub(self.active_ms) >= self.config.descent_ms
+                    {
+                        self.enter(now_ms, FailsafeAction::Disarm)
+                    } else {
+                        self.stage
+                    }
+                }
+            }
+            stage => stage,
+        };
+        if next != self.stage {
+            self.stage = next;
+            Some(next)
+        } else {
+            None
+        }
+    }
+
+    fn enter(&mut self, now_ms: u32, action: FailsafeAction) -> FailsafeStage {
+        self.active_ms = now_ms;
+        self.good_ms = None;
+        FailsafeStage::Active(action)
+    }
+
+    /// 动作阶段的替代指令，last为链路中断前最后一次指令
+    pub fn command(&self, last: &RemoteControl, now_ms: u32) -> RemoteControl {
+        let throttle = match self.stage {
+            FailsafeStage::Active(FailsafeAction::LevelDescend) => {
+                self.config.descent_throttle.min(last.throttle)
+            }
+            FailsafeStage::Active(FailsafeAction::Land) => {
+                let t = now_ms.wrapping_sub(self.active_ms) as f32 / self.config.descent_ms as f32;
+                last.throttle * (1.0 - t).max(0.0)
+            }
+            _ => 0.0,
+        };
+        //姿态摇杆回中即改平，辅助通道保持不变
+        RemoteControl {
+            throttle,
+            aux: last.aux,
+            aux_count: last.aux_count,
+            failsafe: true,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(action: FailsafeAction) -> FailsafeConfig {
+        FailsafeConfig {
+            action,
+            ..FailsafeConfig::new()
+        }
+    }
+
+    //t=0收到最后一帧，t=301判定中断，t=1301进入动作阶段
+    fn activate(action: FailsafeAction) -> FailsafeMonitor {
+        let mut m = FailsafeMonitor::new(config(action));
+        m.on_frame(0, false);
+        assert_eq!(m.update(301), Some(FailsafeStage::Hold));
+        assert_eq!(m.update(1301), Some(FailsafeStage::Active(action)));
+        m
+    }
+
+    #[test]
+    fn frame_timeout() {
+        let mut m = FailsafeMonitor::new(FailsafeConfig::new());
+        //从未收到过有效帧时不触发
+        assert_eq!(m.update(5000), None);
+        m.on_frame(5000, false);
+        assert_eq!(m.update(5300), None);
+        assert!(m.healthy(5300));
+        assert_eq!(m.update(5301), Some(FailsafeStage::Hold));
+        assert!(!m.healthy(5301));
+    }
+
+    #[test]
+    fn rx_failsafe_flag() {
+        //接收机进入失控保护后帧仍在到达，但不算有效帧
+        let mut m = FailsafeMonitor::new(FailsafeConfig::new());
+        m.on_frame(0, false);
+        for t in (10..400).step_by(10) {
+            m.on_frame(t, true);
+        }
+        assert_eq!(m.update(301), Some(FailsafeStage::Hold));
+    }
+
+    #[test]
+    fn grace_period() {
+        //保持阶段内恢复回到正常
+        let mut m = FailsafeMonitor::new(FailsafeConfig::new());
+        m.on_frame(0, false);
+        assert_eq!(m.update(301), Some(FailsafeStage::Hold));
+        assert_eq!(m.update(1300), None);
+        m.on_frame(1300, false);
+        assert_eq!(m.update(1300), Some(FailsafeStage::Idle));
+
+        //宽限期满进入动作阶段
+        let mut m = FailsafeMonitor::new(FailsafeConfig::new());
+        m.on_frame(0, false);
+        m.update(301);
+        assert_eq!(m.update(1300), None);
+        assert_eq!(
+            m.update(1301),
+            Some(FailsafeStage::Active(FailsafeAction::LevelDescend))
+        );
+    }
+
+    #[test]
+    fn level_descend() {
+        let mut m = activate(FailsafeAction::LevelDescend);
+        let last = RemoteControl {
+            roll: 0.5,
+            pitch: -0.3,
+            yaw: 0.2,
+            throttle: 0.6,
+            aux_count: 2,
+            ..Default::default()
+        };
+        let cmd = m.command(&last, 2000);
+        assert_eq!(cmd.throttle, 0.3);
+        assert_eq!((cmd.roll, cmd.pitch, cmd.yaw), (0.0, 0.0, 0.0));
+        assert_eq!(cmd.aux_count, 2);
+        assert!(cmd.failsafe);
+        //油门本来就低时不加油门
+        let low = RemoteControl {
+            throttle: 0.1,
+            ..last
+        };
+        assert_eq!(m.command(&low, 2000).throttle, 0.1);
+        //下降时间到后上锁
+        assert_eq!(m.update(11_300), None);
+        assert_eq!(
+            m.update(11_301),
+            Some(FailsafeStage::Active(FailsafeAction::Disarm))
+        );
+    }
+
+    #[test]
+    fn land() {
+        let mut m = activate(FailsafeAction::Land);
+        let last = RemoteControl {
+            throttle: 0.6,
+            ..Default::default()
+        };
+        assert!((m.command(&last, 1301).throttle - 0.6).abs() < 1e-6);
+        assert!((m.command(&last, 6301).throttle - 0.3).abs() < 1e-6);
+        assert_eq!(m.command(&last, 20_000).throttle, 0.0);
+        assert_eq!(
+            m.update(11_301),
+            Some(FailsafeStage::Active(FailsafeAction::Disarm))
+        );
+    }
+
+    #[test]
+    fn disarm() {
+        let mut m = activate(FailsafeAction::Disarm);
+        let last = RemoteControl {
+            throttle: 0.6,
+            ..Default::default()
+        };
+        assert_eq!(m.command(&last, 1301).throttle, 0.0);
+        //已经上锁，不再切换
+        assert_eq!(m.update(60_000), None);
+    }
+
+    #[test]
+    fn recovery() {
+        let mut m = activate(FailsafeAction::LevelDescend);
+        //链路需要持续正常recovery_ms才退出，中间再中断则重新计时
+        m.on_frame(2000, false);
+        assert_eq!(m.update(2000), None);
+        assert_eq!(m.update(2301), None);
+        m.on_frame(2400, false);
+        assert_eq!(m.update(2400), None);
+        m.on_frame(3300, false);
+        assert_eq!(m.update(3399), None);
+        m.on_frame(3400, false);
+        assert_eq!(m.update(3400), Some(FailsafeStage::Idle));
+    }
+}
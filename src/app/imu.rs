@@ -1,15 +1,40 @@
 //! 惯性测量单元，接收陀螺仪、加速度计、磁力计数据，融合计算输出欧拉角
+//! 陀螺仪数据先按电调回传的电机转速做RPM陷波，再经过FFT动态陷波，然后进入Madgwick融合
 //!
+//! "/imu/raw"是IMU芯片的轴(前左上，静止水平时加速度计为(0,0,1))，Madgwick在这个坐标系下融合。
+//! 发布到"/imu"之前加速度计、陀螺仪、磁力计和姿态都转为机体前右下，姿态为机体到北东地，
+//! 右倾、抬头、机头右转为正，控制和遥测都按这个坐标系处理。
+//!
+use crate::acs::filter::dyn_notch::{DynNotch, DynNotchConfig};
 use crate::acs::filter::first_order::FirstOrderFilter3;
 use crate::acs::filter::jitter_filter::JitterFilter3;
+use crate::acs::filter::rpm_filter::{RpmFilter, RpmFilterConfig};
 use crate::acs::filter::Filter;
-use crate::driver::Euler;
+use crate::driver::{Euler, IMU_SAMPLE_HZ, MAX_MOTORS};
 use crate::{driver::ImuData, mbus, message::Message};
 use ahrs::{Ahrs, Madgwick};
-use nalgebra::Vector3;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use nalgebra::{Quaternion, UnitQuaternion, Vector3};
 
 use xtask::{Queue, TaskBuilder};
 static mut IMU_FILTER: Option<ImuFilter> = None;
+static GYRO_CALIBRATED: AtomicBool = AtomicBool::new(false);
+const ZERO: AtomicU32 = AtomicU32::new(0);
+/// 各电机转速，f32按位存储
+static MOTOR_RPM: [AtomicU32; MAX_MOTORS] = [ZERO; MAX_MOTORS];
+
+/// IMU采样频率，单位Hz
+pub(crate) const SAMPLE_HZ: f32 = IMU_SAMPLE_HZ as f32;
+
+/// 陀螺仪零偏校准采样数，2秒
+const GYRO_CALIBRATION_SAMPLES: u32 = IMU_SAMPLE_HZ * 2;
+/// 校准期间角速度最大波动，超过说明没有静止，单位rad/s
+const GYRO_CALIBRATION_MAX_SPREAD: f32 = 0.05;
+
+/// 陀螺仪零偏校准是否完成
+pub fn gyro_calibrated() -> bool {
+    GYRO_CALIBRATED.load(Ordering::Relaxed)
+}
 
 static mut Q: Option<Queue<ImuData>> = None;
 pub fn start() {
@@ -25,6 +50,14 @@ pub fn start() {
             }
             _ => {}
         });
+        mbus::bus().subscribe("/esc/rpm", |_, msg| match msg {
+            Message::MotorRpm(m) => {
+                if let Some(rpm) = MOTOR_RPM.get(m.index as usize) {
+                    rpm.store(m.rpm.to_bits(), Ordering::Relaxed);
+                }
+            }
+            _ => {}
+        });
     }
     TaskBuilder::new()
         .name("imu_raw_filter")
@@ -59,18 +92,46 @@ pub fn start() {
 }
 pub struct ImuFilter {
     ahrs: Madgwick<f32>,
+    calibration: GyroCalibration,
+    gyro_bias: Vector3<f32>, //陀螺仪零偏
+    rpm_filter: RpmFilter,
+    dyn_notch: DynNotch,
 }
 
 impl ImuFilter {
     fn new() -> Self {
         Self {
-            ahrs: Madgwick::new(1.0 / 100.0, 0.1),
+            ahrs: Madgwick::new(1.0 / SAMPLE_HZ, 0.1),
+            calibration: GyroCalibration::new(),
+            gyro_bias: Vector3::zeros(),
+            rpm_filter: RpmFilter::new(RpmFilterConfig::default(), SAMPLE_HZ),
+            //采样率低于最低陷波频率的2倍时不启用，直接输出；1kHz时陷波频段为150~450Hz
+            dyn_notch: DynNotch::new(DynNotchConfig::default(), SAMPLE_HZ),
         }
     }
 }
 
 impl ImuFilter {
     pub fn update(&mut self, data: &mut ImuData) {
+        if let Some(gyro) = data.gyro {
+            if !gyro_calibrated() {
+                if let Some(bias) = self.calibration.sample(gyro) {
+                    self.gyro_bias = bias;
+                    GYRO_CALIBRATED.store(true, Ordering::Relaxed);
+                    log::info!("Gyro calibration ok, bias {:?}", bias);
+                }
+            }
+            for (i, rpm) in MOTOR_RPM.iter().enumerate() {
+                self.rpm_filter
+                    .set_rpm(i, f32::from_bits(rpm.load(Ordering::Relaxed)));
+            }
+            let mut filtered = Vector3::zeros();
+            self.rpm_filter
+                .do_filter(gyro - self.gyro_bias, &mut filtered);
+            let mut notched = Vector3::zeros();
+            self.dyn_notch.do_filter(filtered, &mut notched);
+            data.gyro = Some(notched);
+        }
         if let Some(acc) = data.accel {
             if let Some(gyro) = data.gyro {
                 if let Some(mag) = data.compass {
@@ -84,5 +145,112 @@ impl ImuFilter {
                 }
             }
         }
+        data.accel = data.accel.map(|v| frd(&v));
+        data.gyro = data.gyro.map(|v| frd(&v));
+        data.compass = data.compass.map(|v| frd(&v));
+        data.quaternion = data.quaternion.map(|q| frd_attitude(&q));
+    }
+}
+
+/// IMU芯片的前左上转为机体前右下
+pub(crate) fn frd(v: &Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(v.x, -v.y, -v.z)
+}
+
+/// 芯片坐标系到北西天的姿态转为机体前右下到北东地。
+/// 两边都绕x轴转180°，即q' = Rx(π)·q·Rx(π)⁻¹，虚部的y、z分量取反
+pub(crate) fn frd_attitude(q: &UnitQuaternion<f32>) -> UnitQuaternion<f32> {
+    let q = q.quaternion();
+    UnitQuaternion::new_unchecked(Quaternion::new(q.w, q.i, -q.j, -q.k))
+}
+
+/// 静止状态下的陀螺仪零偏校准，采样期间有晃动则重新开始
+struct GyroCalibration {
+    sum: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    count: u32,
+}
+
+impl GyroCalibration {
+    fn new() -> Self {
+        Self {
+            sum: Vector3::zeros(),
+            min: Vector3::repeat(f32::MAX),
+            max: Vector3::repeat(f32::MIN),
+            count: 0,
+        }
+    }
+
+    //采样完成返回零偏
+    fn sample(&mut self, gyro: Vector3<f32>) -> Option<Vector3<f32>> {
+        self.sum += gyro;
+        self.min = self.min.inf(&gyro);
+        self.max = self.max.sup(&gyro);
+        self.count += 1;
+        if self.count < GYRO_CALIBRATION_SAMPLES {
+            return None;
+        }
+        let spread = (self.max - self.min).max();
+        let bias = self.sum / self.count as f32;
+        *self = Self::new();
+        if spread > GYRO_CALIBRATION_MAX_SPREAD {
+            None
+        } else {
+            Some(bias)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gyro_axes() {
+        //芯片坐标系下右滚为+x，抬头为-y，机头右转为-z；机体前右下三者都为正
+        assert_eq!(
+            frd(&Vector3::new(1.0, 0.0, 0.0)),
+            Vector3::new(1.0, 0.0, 0.0)
+        );
+        assert_eq!(
+            frd(&Vector3::new(0.0, -1.0, 0.0)),
+            Vector3::new(0.0, 1.0, 0.0)
+        );
+        assert_eq!(
+            frd(&Vector3::new(0.0, 0.0, -1.0)),
+            Vector3::new(0.0, 0.0, 1.0)
+        );
+        //静止水平时加速度计测到向上的比力
+        assert_eq!(
+            frd(&Vector3::new(0.0, 0.0, 1.0)),
+            Vector3::new(0.0, 0.0, -1.0)
+        );
+    }
+
+    #[test]
+    fn attitude_axes() {
+        //芯片坐标系下的姿态为北西天，右滚绕+x，抬头绕-y，机头右转(向东)绕-z
+        let cases = [
+            (Vector3::x_axis(), 0.3, (0.3, 0.0, 0.0)),
+            (Vector3::y_axis(), -0.3, (0.0, 0.3, 0.0)),
+            (Vector3::z_axis(), -0.3, (0.0, 0.0, 0.3)),
+        ];
+        for &(axis, angle, (roll, pitch, yaw)) in cases.iter() {
+            let q = frd_attitude(&UnitQuaternion::from_axis_angle(&axis, angle));
+            let (r, p, y) = q.euler_angles();
+            assert!((r - roll).abs() < 1e-5, "{:?} roll {}", axis, r);
+            assert!((p - pitch).abs() < 1e-5, "{:?} pitch {}", axis, p);
+            assert!((y - yaw).abs() < 1e-5, "{:?} yaw {}", axis, y);
+        }
+    }
+
+    #[test]
+    fn attitude_matches_vectors() {
+        //转换后的姿态把机体前右下的向量转到北东地，与分别转换向量一致
+        let q = UnitQuaternion::from_euler_angles(0.2, -0.4, 1.3);
+        let v = Vector3::new(0.3, -0.5, 0.8);
+        let ned = frd(&(q * v));
+        assert!((frd_attitude(&q) * frd(&v) - ned).norm() < 1e-5);
     }
 }
@@ -1,18 +1,34 @@
 #[cfg(feature = "anotc")]
 mod anotc;
+pub mod arming;
+#[cfg(feature = "crsf")]
+mod crsf;
+pub mod failsafe;
 mod imu;
 #[cfg(feature = "mavlink")]
 mod mavlink;
+pub mod modes;
 #[cfg(feature = "msp")]
 mod msp;
+#[cfg(feature = "nav")]
+mod nav;
+pub mod rc;
 
 pub fn start() {
     imu::start();
+    rc::start();
+    failsafe::start();
+    arming::start();
+    modes::start();
+    #[cfg(feature = "nav")]
+    nav::start();
     #[cfg(feature = "anotc")]
     anotc::start();
     #[cfg(feature = "mavlink")]
     mavlink::start();
     #[cfg(feature = "msp")]
     msp::start();
+    #[cfg(feature = "crsf")]
+    crsf::start();
     log::info!("Start xpilot application ok");
 }
@@ -0,0 +1,244 @@
+//! 飞行模式选择
+//!
+//! 和Betaflight的模式页一样，每条模式范围指定一个辅助通道和一段通道值，通道值落在范围内时
+//! 激活对应模式。同时激活多个模式时取优先级最高的，都没有激活时为手动模式。
+//! 当前模式变化时发布到/mode。
+//!
+use crate::app::arming::{self, ArmingMode};
+use crate::mbus;
+use crate::message::*;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crossbeam::atomic::AtomicCell;
+use spin::Mutex;
+use xtask::TaskBuilder;
+
+/// 最多模式范围条数
+pub const MAX_MODE_RANGES: usize = 20;
+/// 刷新周期，单位毫秒
+const PERIOD_MS: u32 = 20;
+/// 解锁在模式列表里的编号，地面站设置的解锁范围转给arming模块按开关方式解锁
+pub const BOX_ARM: u8 = 0;
+/// 上报给地面站的模式名，顺序与编号一致
+pub const BOX_NAMES: &str = "ARM;HOVER;MANUAL;AUTO;FOLLOW;TRICK;AUTOTUNE;";
+
+static MODE_RANGES: Mutex<[Option<ModeRange>; MAX_MODE_RANGES]> =
+    Mutex::new([None; MAX_MODE_RANGES]);
+static RC: AtomicCell<Option<RemoteControl>> = AtomicCell::new(None);
+static RC_COUNT: AtomicU32 = AtomicU32::new(0);
+static ACTIVE: AtomicCell<FlightMode> = AtomicCell::new(FlightMode::Manual);
+//解锁范围所在的序号和MSP格式的内容
+static ARM_RANGE: AtomicCell<Option<(usize, [u8; 4])>> = AtomicCell::new(None);
+
+/// 飞行模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlightMode {
+    Manual,    //手动
+    Hover,     //悬停，自稳
+    Auto,      //自动
+    Following, //跟随
+    Trick,     //特技
+    Autotune,  //PID自整定
+}
+
+impl FlightMode {
+    pub const ALL: [FlightMode; 6] = [
+        FlightMode::Hover,
+        FlightMode::Manual,
+        FlightMode::Auto,
+        FlightMode::Following,
+        FlightMode::Trick,
+        FlightMode::Autotune,
+    ];
+
+    /// 模式编号，和BOX_NAMES对应
+    pub fn box_id(&self) -> u8 {
+        match self {
+            FlightMode::Hover => 1,
+            FlightMode::Manual => 2,
+            FlightMode::Auto => 3,
+            FlightMode::Following => 4,
+            FlightMode::Trick => 5,
+            FlightMode::Autotune => 6,
+        }
+    }
+
+    pub fn from_box_id(id: u8) -> Option<Self> {
+        Self::ALL.iter().find(|m| m.box_id() == id).copied()
+    }
+
+    /// 优先级，数值越大越优先
+    pub fn priority(&self) -> u8 {
+        match self {
+            FlightMode::Manual => 0,
+            FlightMode::Trick => 1,
+            FlightMode::Hover => 2,
+            FlightMode::Following => 3,
+            FlightMode::Auto => 4,
+            FlightMode::Autotune => 5,
+        }
+    }
+
+    /// 四个字符的简称，用于遥测显示
+    pub fn short_name(&self) -> &'static str {
+        match self {
+            FlightMode::Manual => "MANU",
+            FlightMode::Hover => "HOVR",
+            FlightMode::Auto => "AUTO",
+            FlightMode::Following => "FOLW",
+            FlightMode::Trick => "TRIK",
+            FlightMode::Autotune => "TUNE",
+        }
+    }
+}
+
+/// 模式范围，通道值=900+25*step，激活条件start<=通道值<end
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModeRange {
+    pub mode: FlightMode,
+    pub aux: u8,        //辅助通道，从0开始
+    pub start_step: u8, //0-48
+    pub end_step: u8,   //0-48
+}
+
+impl ModeRange {
+    pub fn new(mode: FlightMode, aux: u8, start_us: u16, end_us: u16) -> Self {
+        Self {
+            mode,
+            aux,
+            start_step: us_to_step(start_us),
+            end_step: us_to_step(end_us),
+        }
+    }
+
+    pub fn active(&self, rc: &RemoteControl) -> bool {
+        let aux = self.aux as usize;
+        if aux >= rc.aux_count as usize {
+            return false;
+        }
+        let value = rc.aux[aux];
+        value >= step_to_us(self.start_step) && value < step_to_us(self.end_step)
+    }
+
+    /// 按MSP格式打包: 模式编号、辅助通道、起始step、结束step
+    pub fn to_bytes(&self) -> [u8; 4] {
+        [self.mode.box_id(), self.aux, self.start_step, self.end_step]
+    }
+
+    /// 从MSP格式解包，编号不是飞行模式或范围为空时返回None
+    pub fn from_bytes(b: &[u8]) -> Option<Self> {
+        if b.len() < 4 || b[2] >= b[3] {
+            return None;
+        }
+        Some(Self {
+            mode: FlightMode::from_box_id(b[0])?,
+            aux: b[1],
+            start_step: b[2].min(48),
+            end_step: b[3].min(48),
+        })
+    }
+}
+
+#[inline]
+fn step_to_us(step: u8) -> u16 {
+    900 + 25 * step.min(48) as u16
+}
+
+#[inline]
+fn us_to_step(us: u16) -> u8 {
+    ((us.max(900).min(2100) - 900) / 25) as u8
+}
+
+/// 按范围和优先级选出当前模式
+pub fn select(ranges: &[Option<ModeRange>], rc: &RemoteControl) -> FlightMode {
+    ranges
+        .iter()
+        .flatten()
+        .filter(|r| r.active(rc))
+        .map(|r| r.mode)
+        .max_by_key(|m| m.priority())
+        .unwrap_or(FlightMode::Manual)
+}
+
+pub fn start() {
+    mbus::bus().subscribe("/rc", |_, msg| match msg {
+        Message::RemoteControl(rc) => {
+            RC.store(Some(rc));
+            RC_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        _ => {}
+    });
+    TaskBuilder::new()
+        .name("modes")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(|| {
+            let mut count = RC_COUNT.load(Ordering::Relaxed);
+            loop {
+                let n = RC_COUNT.load(Ordering::Relaxed);
+                if n != count {
+                    count = n;
+                    if let Some(rc) = RC.load() {
+                        let mode = select(&*MODE_RANGES.lock(), &rc);
+                        if ACTIVE.swap(mode) != mode {
+                            log::info!("Flight mode {:?}", mode);
+                            mbus::bus().publish("/mode", Message::FlightMode(mode));
+                        }
+                    }
+                }
+                xtask::delay_us(PERIOD_MS * 1000);
+            }
+        });
+}
+
+/// 当前模式
+pub fn active() -> FlightMode {
+    ACTIVE.load()
+}
+
+/// 设置第index条模式范围，None清除
+pub fn set_range(index: usize, range: Option<ModeRange>) -> bool {
+    if index >= MAX_MODE_RANGES {
+        return false;
+    }
+    MODE_RANGES.lock()[index] = range;
+    clear_arm_range(index);
+    true
+}
+
+/// 所有模式范围
+pub fn ranges() -> [Option<ModeRange>; MAX_MODE_RANGES] {
+    *MODE_RANGES.lock()
+}
+
+/// 把第index条设为解锁范围，通道值落在[start, end)内解锁，范围为空时清除
+pub fn set_arm_range(index: usize, aux: u8, start_step: u8, end_step: u8) -> bool {
+    if index >= MAX_MODE_RANGES {
+        return false;
+    }
+    let (start_step, end_step) = (start_step.min(48), end_step.min(48));
+    if start_step >= end_step {
+        return set_range(index, None);
+    }
+    MODE_RANGES.lock()[index] = None;
+    //只有一个解锁开关，新的范围替换旧的
+    ARM_RANGE.store(Some((index, [BOX_ARM, aux, start_step, end_step])));
+    arming::set_mode(ArmingMode::Switch {
+        aux,
+        min: step_to_us(start_step),
+        max: step_to_us(end_step) - 1,
+    });
+    true
+}
+
+/// 解锁范围所在的序号和MSP格式的内容
+pub fn arm_range() -> Option<(usize, [u8; 4])> {
+    ARM_RANGE.load()
+}
+
+//第index条被覆盖或清除时，如果它是解锁范围就恢复摇杆解锁
+fn clear_arm_range(index: usize) {
+    if matches!(ARM_RANGE.load(), Some((i, _)) if i == index) {
+        ARM_RANGE.store(None);
+        arming::set_mode(ArmingMode::Stick);
+    }
+}
@@ -2,11 +2,17 @@
 ///
 ///
 ///
-use crate::driver::ImuData;
+use crate::acs::attitude::{self, AttitudeConfig};
+use crate::acs::{autotune, rates};
+use crate::app::arming;
+use crate::app::modes;
+use crate::driver::{ImuData, RcChannels, MAX_RC_CHANNELS};
+use crate::drone;
 
 use crate::mbus;
 use crate::message::*;
 use alloc::vec;
+use alloc::vec::Vec;
 
 use crossbeam::atomic::AtomicCell;
 use multiwii_serial_protocol_v2::structs::*;
@@ -23,6 +29,13 @@ static IMU_DATA: AtomicCell<ImuData> = AtomicCell::new(ImuData {
     euler: None,
 });
 
+static RC_CHANNELS: AtomicCell<RcChannels> = AtomicCell::new(RcChannels {
+    channels: [0; MAX_RC_CHANNELS],
+    count: 0,
+    frame_lost: false,
+    failsafe: false,
+});
+
 static mut Q: Option<Queue<Message>> = None;
 
 pub fn start() {
@@ -42,6 +55,13 @@ pub fn start() {
         _ => {}
     });
 
+    mbus::bus().subscribe("/rc/raw", move |_, msg| match msg {
+        Message::RcChannels(rc) => {
+            RC_CHANNELS.store(rc);
+        }
+        _ => {}
+    });
+
     mbus::bus().subscribe("/telem/msp", move |_, msg| match msg {
         Message::Telem(_) => {
             let q: &'static Queue<Message> = unsafe { Q.as_ref().unwrap() };
@@ -103,14 +123,11 @@ fn process() {
                             }
                         }
                         Command::MSP_MIXER_CONFIG => {
-                            let conf = MspMixerConfig {
-                                mixer_mode: MixerMode::QuadX,
-                            };
-                            if let Ok(b) = conf.pack() {
-                                send_multiwii(
-                                    Packet::new(Command::MSP_MIXER_CONFIG).with_data(b.to_vec()),
-                                );
-                            }
+                            //混控编号，电机反转标志
+                            send_multiwii(
+                                Packet::new(Command::MSP_MIXER_CONFIG)
+                                    .with_data(vec![drone::msp_mixer_mode(), 0]),
+                            );
                         }
                         Command::MSP_ACC_TRIM => {
                             let acc = MspAccTrim { pitch: 0, roll: 0 };
@@ -133,14 +150,16 @@ fn process() {
                                     acc: true,
                                 },
                                 null1: 0,
-                                flight_mode: 6,
+                                flight_mode: flight_mode_flags(),
                                 profile: 2,
                                 system_load: 0,
                             };
                             if let Ok(b) = status.pack() {
-                                send_multiwii(
-                                    Packet::new(Command::MSP_STATUS).with_data(b.to_vec()),
-                                );
+                                let mut data = b.to_vec();
+                                //陀螺仪循环时间
+                                data.extend_from_slice(&0u16.to_le_bytes());
+                                status_tail(&mut data);
+                                send_multiwii(Packet::new(Command::MSP_STATUS).with_data(data));
                             }
                         }
                         Command::MSP_STATUS_EX => {
@@ -156,16 +175,16 @@ fn process() {
                                     acc: true,
                                 },
                                 null1: 0,
-                                flight_mode: 0,
+                                flight_mode: flight_mode_flags(),
                                 current_pid_profile_index: 0,
                                 average_system_load_percent: 0,
                                 max_profile_count: 10,
                                 current_control_rate_profile_index: 0,
                             };
                             if let Ok(b) = status.pack() {
-                                send_multiwii(
-                                    Packet::new(Command::MSP_STATUS_EX).with_data(b.to_vec()),
-                                );
+                                let mut data = b.to_vec();
+                                status_tail(&mut data);
+                                send_multiwii(Packet::new(Command::MSP_STATUS_EX).with_data(data));
                             }
                         }
                         Command::MSP_FC_VARIANT => {
@@ -229,6 +248,8 @@ fn process() {
                             send_multiwii(Packet::new(Command::MSP_NAME).with_data(name.to_vec()));
                         }
                         Command::MSP_SET_ARMING_DISABLED => {
+                            //第一个字节为1时禁止解锁
+                            arming::set_msp_disabled(msg.data.first() == Some(&1));
                             send_multiwii(Packet::new(Command::MSP_SET_ARMING_DISABLED));
                         }
                         Command::MSP_SET_RTC => {
@@ -255,12 +276,92 @@ fn process() {
                                 );
                             }
                         }
-                        Command::MSP_RC => {
-                            let rc = MspRcChannelValue { value: 16 };
-                            if let Ok(b) = rc.pack() {
-                                send_multiwii(Packet::new(Command::MSP_RC).with_data(b.to_vec()));
+                        Command::MSP_BOXNAMES => {
+                            send_multiwii(
+                                Packet::new(Command::MSP_BOXNAMES)
+                                    .with_data(modes::BOX_NAMES.as_bytes().to_vec()),
+                            );
+                        }
+                        Command::MSP_BOXIDS => {
+                            let mut data = vec![modes::BOX_ARM];
+                            data.extend(modes::FlightMode::ALL.iter().map(|m| m.box_id()));
+                            data.sort_unstable();
+                            send_multiwii(Packet::new(Command::MSP_BOXIDS).with_data(data));
+                        }
+                        Command::MSP_MODE_RANGES => {
+                            //每条4字节: 模式编号、辅助通道、起始step、结束step，空条目为0
+                            let arm = modes::arm_range();
+                            let data = modes::ranges()
+                                .iter()
+                                .enumerate()
+                                .flat_map(|(i, r)| match arm {
+                                    Some((index, bytes)) if index == i => bytes,
+                                    _ => r.map(|r| r.to_bytes()).unwrap_or_default(),
+                                })
+                                .collect();
+                            send_multiwii(Packet::new(Command::MSP_MODE_RANGES).with_data(data));
+                        }
+                        Command::MSP_SET_MODE_RANGE => {
+                            //序号后跟一条模式范围，起止相同表示清除
+                            let ok = match msg.data.split_first() {
+                                Some((&index, b)) if b.len() >= 4 => {
+                                    if b[0] == modes::BOX_ARM {
+                                        modes::set_arm_range(index as usize, b[1], b[2], b[3])
+                                    } else if b[2] >= b[3] {
+                                        modes::set_range(index as usize, None)
+                                    } else if let Some(range) = modes::ModeRange::from_bytes(b) {
+                                        modes::set_range(index as usize, Some(range))
+                                    } else {
+                                        false
+                                    }
+                                }
+                                _ => false,
+                            };
+                            if ok {
+                                send_multiwii(Packet::new(Command::MSP_SET_MODE_RANGE));
+                            } else {
+                                send_multiwii(Packet::new_code(msg.code));
+                            }
+                        }
+                        Command::MSP_RC_TUNING => {
+                            let data = rates::profile().to_bytes().to_vec();
+                            send_multiwii(Packet::new(Command::MSP_RC_TUNING).with_data(data));
+                        }
+                        Command::MSP_SET_RC_TUNING => {
+                            //老版本地面站发来的数据较短，只更新已有字段
+                            match rates::profile().from_bytes(&msg.data) {
+                                Some(profile) => {
+                                    rates::set_profile(profile);
+                                    send_multiwii(Packet::new(Command::MSP_SET_RC_TUNING));
+                                }
+                                None => send_multiwii(Packet::new_code(msg.code)),
                             }
                         }
+                        Command::MSP_PID => {
+                            let rate = AttitudeConfig::new().with_params().rate;
+                            let data = attitude::msp_pid(&rate).to_vec();
+                            send_multiwii(Packet::new(Command::MSP_PID).with_data(data));
+                        }
+                        Command::MSP_SET_PID => {
+                            //写入参数表，下次解锁时生效
+                            match attitude::from_msp_pid(&msg.data) {
+                                Some(rate) => {
+                                    for (axis, gains) in rate.iter().enumerate() {
+                                        autotune::store(axis, gains);
+                                    }
+                                    send_multiwii(Packet::new(Command::MSP_SET_PID));
+                                }
+                                None => send_multiwii(Packet::new_code(msg.code)),
+                            }
+                        }
+                        Command::MSP_RC => {
+                            let rc = RC_CHANNELS.load();
+                            let data = rc.channels[..rc.count as usize]
+                                .iter()
+                                .flat_map(|v| v.to_le_bytes())
+                                .collect();
+                            send_multiwii(Packet::new(Command::MSP_RC).with_data(data));
+                        }
                         _ => {
                             log::info!("Ignore {:?}", msg);
                             send_multiwii(Packet::new_code(msg.code));
@@ -273,6 +374,23 @@ fn process() {
     }
 }
 
+//激活模式的位图，第n位对应MSP_BOXIDS里的第n个模式，模式编号连续所以直接用编号
+fn flight_mode_flags() -> u32 {
+    let mut flags = 1 << modes::active().box_id();
+    if arming::armed() {
+        flags |= 1 << modes::BOX_ARM;
+    }
+    flags
+}
+
+//Betaflight在状态基本字段后追加: 飞行模式扩展字节数、禁止解锁标志、配置状态
+fn status_tail(data: &mut Vec<u8>) {
+    data.push(0);
+    data.push(arming::ARMING_DISABLE_FLAGS_COUNT);
+    data.extend_from_slice(&arming::disable_flags().bits().to_le_bytes());
+    data.push(0);
+}
+
 fn send_multiwii(msg: Packet) {
     let mut buf = vec![0u8; msg.packet_size_bytes_v2()];
     if let Ok(_) = msg.serialize_v2(&mut buf) {
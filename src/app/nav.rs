@@ -0,0 +1,202 @@
+//! 组合导航，IMU原始数据传播误差状态卡尔曼滤波，GPS、气压计、磁力计校正，结果发布到"/nav"
+//!
+//! 订阅"/imu/raw"、"/gps"、"/baro"。三种数据频率不同，按到达顺序放进同一个队列，
+//! 校正用的是最近一次传播后的状态。IMU数据没有时间戳，传播周期按IMU采样频率计算；
+//! 磁力计随IMU数据一起到达，每HEADING_DIV个数据做一次航向校正。
+//! 加速度计单位g。驱动发布的是IMU芯片的轴(前左上，静止水平时加速度计为(0,0,1))，
+//! 加速度计、陀螺仪、磁力计先按"/imu"的约定转到机体前右下，静止水平时加速度计为(0,0,-1)。
+//! 磁力计按驱动已经对齐到加速度计的轴处理。
+//!
+//! 第一次GPS定位的经纬度作为原点，原点海拔按当时的高度估计对齐，使GPS和气压计高度一致。
+//! 有原点之后才发布"/nav"，之前的位置没有参考点。
+//!
+use super::imu::{frd, SAMPLE_HZ};
+use crate::acs::filter::eskf::{initial_attitude, Eskf, EskfConfig, GRAVITY};
+use crate::acs::filter::klf::KalmanError;
+use crate::driver::{Barometer, Gps, ImuData};
+use crate::mbus;
+use crate::message::{Message, Navigation};
+use nalgebra::Vector3;
+use xtask::{Queue, TaskBuilder};
+
+/// 地球平均半径，单位米
+const EARTH_RADIUS: f64 = 6_371_000.0;
+/// 每隔多少个IMU数据做一次磁力计航向校正
+const HEADING_DIV: u32 = 10;
+
+enum Input {
+    Imu(ImuData),
+    Gps(Gps),
+    Baro(Barometer),
+}
+
+static mut Q: Option<Queue<Input>> = None;
+
+pub fn start() {
+    unsafe {
+        Q.replace(Queue::with_capacity(100));
+        mbus::bus().subscribe("/imu/raw", |_, msg| match msg {
+            Message::ImuData(data) => push(Input::Imu(data)),
+            _ => {}
+        });
+        mbus::bus().subscribe("/gps", |_, msg| match msg {
+            Message::Gps(gps) => push(Input::Gps(gps)),
+            _ => {}
+        });
+        mbus::bus().subscribe("/baro", |_, msg| match msg {
+            Message::Barometer(baro) => push(Input::Baro(baro)),
+            _ => {}
+        });
+    }
+    TaskBuilder::new()
+        .name("nav")
+        .priority(1)
+        //15维协方差运算的临时矩阵都在栈上
+        .stack_size(8 * 1024)
+        .spawn(|| unsafe {
+            let mut nav = Nav::new(EskfConfig::default());
+            loop {
+                if let Some(q) = Q.as_mut() {
+                    if let Some(input) = q.pop_front() {
+                        nav.input(input);
+                    }
+                }
+            }
+        });
+}
+
+unsafe fn push(input: Input) {
+    if let Some(q) = Q.as_mut() {
+        q.push_back_isr(input).ok();
+    }
+}
+
+struct Nav {
+    config: EskfConfig,
+    eskf: Option<Eskf>,              //收到第一个IMU数据后初始化
+    origin: Option<(f64, f64, f32)>, //原点纬度、经度、海拔
+    baro_ref: Option<f32>,           //原点的气压高度
+    count: u32,
+}
+
+impl Nav {
+    fn new(config: EskfConfig) -> Self {
+        Self {
+            config,
+            eskf: None,
+            origin: None,
+            baro_ref: None,
+            count: 0,
+        }
+    }
+
+    fn input(&mut self, input: Input) {
+        match input {
+            Input::Imu(data) => self.imu(data),
+            Input::Gps(gps) => self.gps(gps),
+            Input::Baro(baro) => self.baro(baro),
+        }
+    }
+
+    fn imu(&mut self, data: ImuData) {
+        let (acc, gyro) = match (data.accel, data.gyro) {
+            (Some(acc), Some(gyro)) => (frd(&acc) * GRAVITY, frd(&gyro)),
+            _ => return,
+        };
+        let mag = data.compass.map(|m| frd(&m));
+        let eskf = match self.eskf.as_mut() {
+            Some(eskf) => eskf,
+            None => {
+                let attitude = initial_attitude(&acc, mag.as_ref(), self.config.declination);
+                self.eskf = Some(Eskf::new(self.config, attitude));
+                log::info!(
+                    "Navigation initialized, attitude {:?}",
+                    attitude.euler_angles()
+                );
+                return;
+            }
+        };
+        if let Err(err) = eskf.predict(&acc, &gyro, 1.0 / SAMPLE_HZ) {
+            log::warn!("Navigation predict {:?}", err);
+            return;
+        }
+        self.count = self.count.wrapping_add(1);
+        if let Some(mag) = mag {
+            if self.count % HEADING_DIV == 0 {
+                check("heading", eskf.update_heading(&mag, self.config.heading_sd));
+            }
+        }
+        let origin = match self.origin {
+            Some(origin) => origin,
+            None => return,
+        };
+        mbus::bus().publish(
+            "/nav",
+            Message::Navigation(Navigation {
+                position: eskf.position(),
+                velocity: eskf.velocity(),
+                attitude: eskf.attitude(),
+                gyro_bias: eskf.gyro_bias(),
+                accel_bias: eskf.accel_bias(),
+                origin,
+            }),
+        );
+    }
+
+    fn gps(&mut self, gps: Gps) {
+        let eskf = match self.eskf.as_mut() {
+            Some(eskf) if gps.fix => eskf,
+            _ => return,
+        };
+        let sd = if gps.h_acc > 0.0 {
+            gps.h_acc
+        } else {
+            self.config.gps_pos_sd
+        };
+        match self.origin {
+            Some(origin) => check("gps position", eskf.update_position(&ned(origin, &gps), sd)),
+            None => {
+                let down = eskf.position().z;
+                let origin = (gps.latitude, gps.longitude, gps.altitude + down);
+                self.origin = Some(origin);
+                eskf.set_position(Vector3::new(0.0, 0.0, down), sd);
+                log::info!("Navigation origin {:?}", origin);
+            }
+        }
+        if let Some(velocity) = gps.velocity {
+            let sd = if gps.s_acc > 0.0 {
+                gps.s_acc
+            } else {
+                self.config.gps_vel_sd
+            };
+            check("gps velocity", eskf.update_velocity(&velocity, sd));
+        }
+    }
+
+    fn baro(&mut self, baro: Barometer) {
+        let eskf = match self.eskf.as_mut() {
+            Some(eskf) => eskf,
+            None => return,
+        };
+        //第一个数据对齐到当前的高度估计
+        let reference = *self.baro_ref.get_or_insert(baro.h + eskf.position().z);
+        check(
+            "baro",
+            eskf.update_altitude(baro.h - reference, self.config.baro_sd),
+        );
+    }
+}
+
+//校正失败时丢掉这个测量值
+fn check(name: &str, result: Result<(), KalmanError>) {
+    if let Err(err) = result {
+        log::debug!("Navigation {} rejected {:?}", name, err);
+    }
+}
+
+/// 经纬度海拔转为相对原点的北东地坐标，离原点几十公里以内用平面近似
+fn ned((lat0, lon0, alt0): (f64, f64, f32), gps: &Gps) -> Vector3<f32> {
+    let north = (gps.latitude - lat0).to_radians() * EARTH_RADIUS;
+    let east = (gps.longitude - lon0).to_radians() * EARTH_RADIUS * libm::cos(lat0.to_radians());
+    Vector3::new(north as f32, east as f32, alt0 - gps.altitude)
+}
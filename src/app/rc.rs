@@ -0,0 +1,215 @@
+//! 遥控映射，把接收机原始通道(/rc/raw)按通道顺序和校准参数转换成归一化的遥控指令(/rc)
+//!
+use crate::driver::RcChannels;
+use crate::mbus;
+use crate::message::*;
+use xtask::{Queue, TaskBuilder};
+
+static mut RC_MAP: Option<RcMap> = None;
+static mut Q: Option<Queue<RcChannels>> = None;
+
+pub fn start() {
+    unsafe {
+        Q.replace(Queue::with_capacity(10));
+        RC_MAP.replace(RcMap::default());
+    }
+    mbus::bus().subscribe("/rc/raw", |_, msg| match msg {
+        Message::RcChannels(rc) => {
+            if let Some(q) = unsafe { Q.as_mut() } {
+                q.push_back_isr(rc).ok();
+            }
+        }
+        _ => {}
+    });
+    TaskBuilder::new()
+        .name("rc")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(|| unsafe {
+            loop {
+                if let Some(q) = Q.as_mut() {
+                    if let Some(raw) = q.pop_front() {
+                        //接收机已进入失控保护，交给failsafe处理
+                        if raw.failsafe {
+                            continue;
+                        }
+                        if let Some(map) = RC_MAP.as_ref() {
+                            mbus::bus().publish("/rc", Message::RemoteControl(map.map(&raw)));
+                        }
+                    }
+                }
+            }
+        });
+}
+
+/// 替换映射参数
+pub fn configure(map: RcMap) {
+    xtask::sync::free(|_| unsafe {
+        RC_MAP.replace(map);
+    });
+}
+
+/// 摇杆
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stick {
+    Roll = 0,
+    Pitch = 1,
+    Yaw = 2,
+    Throttle = 3,
+}
+
+/// 通道顺序
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    /// 副翼、升降、油门、方向
+    Aetr,
+    /// 油门、副翼、升降、方向
+    Taer,
+    /// 自定义，依次为横滚、俯仰、偏航、油门所在的通道号(从0开始)
+    Custom([u8; 4]),
+}
+
+impl ChannelOrder {
+    /// 横滚、俯仰、偏航、油门所在的通道号
+    pub fn indices(&self) -> [usize; 4] {
+        match self {
+            ChannelOrder::Aetr => [0, 1, 3, 2],
+            ChannelOrder::Taer => [1, 2, 3, 0],
+            ChannelOrder::Custom(ch) => [
+                ch[0] as usize,
+                ch[1] as usize,
+                ch[2] as usize,
+                ch[3] as usize,
+            ],
+        }
+    }
+}
+
+/// 单通道校准参数，单位微秒
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    pub min: u16,       //最小值
+    pub mid: u16,       //中位
+    pub max: u16,       //最大值
+    pub reversed: bool, //反向
+    pub deadband: u16,  //中位死区
+    pub expo: f32,      //指数曲线，0.0-1.0
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            min: 1000,
+            mid: 1500,
+            max: 2000,
+            reversed: false,
+            deadband: 0,
+            expo: 0.0,
+        }
+    }
+}
+
+impl Calibration {
+    /// 摇杆量，-1.0~1.0
+    pub fn stick(&self, us: u16) -> f32 {
+        let v = us as f32 - self.mid as f32;
+        let span = if v >= 0.0 {
+            self.max as f32 - self.mid as f32
+        } else {
+            self.mid as f32 - self.min as f32
+        };
+        let db = self.deadband as f32;
+        if span <= db || libm::fabsf(v) <= db {
+            return 0.0;
+        }
+        //扣除死区后重新缩放，保证出死区时连续
+        let x = ((libm::fabsf(v) - db) / (span - db)).min(1.0);
+        let x = x * (1.0 - self.expo) + x * x * x * self.expo;
+        let x = if v < 0.0 { -x } else { x };
+        if self.reversed {
+            -x
+        } else {
+            x
+        }
+    }
+
+    /// 油门量，0.0~1.0
+    pub fn throttle(&self, us: u16) -> f32 {
+        let span = self.max as f32 - self.min as f32;
+        if span <= 0.0 {
+            return 0.0;
+        }
+        let t = ((us as f32 - self.min as f32) / span).max(0.0).min(1.0);
+        let t = t * (1.0 - self.expo) + t * t * t * self.expo;
+        if self.reversed {
+            1.0 - t
+        } else {
+            t
+        }
+    }
+}
+
+/// 遥控映射
+#[derive(Debug, Clone, Copy)]
+pub struct RcMap {
+    order: ChannelOrder,
+    calibration: [Calibration; 4], //按横滚、俯仰、偏航、油门排列
+}
+
+impl Default for RcMap {
+    fn default() -> Self {
+        Self::new(ChannelOrder::Aetr)
+    }
+}
+
+impl RcMap {
+    pub fn new(order: ChannelOrder) -> Self {
+        Self {
+            order,
+            calibration: [Calibration::default(); 4],
+        }
+    }
+
+    pub fn with_calibration(mut self, stick: Stick, calibration: Calibration) -> Self {
+        self.calibration[stick as usize] = calibration;
+        self
+    }
+
+    pub fn calibration(&self, stick: Stick) -> &Calibration {
+        &self.calibration[stick as usize]
+    }
+
+    /// 原始通道转遥控指令，摇杆以外的通道按顺序作为辅助通道
+    pub fn map(&self, raw: &RcChannels) -> RemoteControl {
+        let idx = self.order.indices();
+        let value = |stick: Stick| -> u16 {
+            let ch = idx[stick as usize];
+            let cal = &self.calibration[stick as usize];
+            if ch < raw.count as usize {
+                raw.channels[ch]
+            } else if stick == Stick::Throttle {
+                cal.min
+            } else {
+                cal.mid
+            }
+        };
+        let mut rc = RemoteControl {
+            roll: self.calibration[0].stick(value(Stick::Roll)),
+            pitch: self.calibration[1].stick(value(Stick::Pitch)),
+            yaw: self.calibration[2].stick(value(Stick::Yaw)),
+            throttle: self.calibration[3].throttle(value(Stick::Throttle)),
+            ..Default::default()
+        };
+        //自定义顺序时摇杆不一定在前四个通道
+        let mut n = 0;
+        for ch in 0..(raw.count as usize).min(raw.channels.len()) {
+            if idx.contains(&ch) || n >= rc.aux.len() {
+                continue;
+            }
+            rc.aux[n] = raw.channels[ch];
+            n += 1;
+        }
+        rc.aux_count = n as u8;
+        rc
+    }
+}
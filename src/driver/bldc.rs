@@ -1,74 +1,102 @@
 //! 无刷电机驱动
 
+use crate::driver::dshot::DshotCommand;
 use crate::mbus;
 use crate::message::*;
-use embedded_hal::PwmPin;
+use alloc::boxed::Box;
+
+/// 电调输出
+pub trait EscOutput {
+    fn enable(&mut self);
+    fn disable(&mut self);
+    /// 油门，0.0~1.0
+    fn set_throttle(&mut self, throttle: f32);
+    /// 发送DShot命令，不支持命令的输出返回false
+    fn command(&mut self, _cmd: DshotCommand) -> bool {
+        false
+    }
+}
+
+impl<T: EscOutput + ?Sized> EscOutput for Box<T> {
+    fn enable(&mut self) {
+        (**self).enable()
+    }
+
+    fn disable(&mut self) {
+        (**self).disable()
+    }
+
+    fn set_throttle(&mut self, throttle: f32) {
+        (**self).set_throttle(throttle)
+    }
+
+    fn command(&mut self, cmd: DshotCommand) -> bool {
+        (**self).command(cmd)
+    }
+}
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum State {
     Locked,
     Unlocked,
 }
-pub struct Motor<PWM> {
-    pwm: PWM,
+pub struct Motor<ESC> {
+    esc: ESC,
     state: State,
 }
 
-impl<PWM: PwmPin<Duty = u16>> Motor<PWM> {
-    pub fn new(pwm: PWM) -> Self {
+impl<ESC: EscOutput> Motor<ESC> {
+    pub fn new(esc: ESC) -> Self {
         Self {
-            pwm,
+            esc,
             state: State::Locked,
         }
     }
 }
 
-impl<PWM: PwmPin<Duty = u16>> Motor<PWM> {
+impl<ESC: EscOutput> Motor<ESC> {
     /// 锁定马达，绿关，红开
     pub fn lock(&mut self) {
         self.state = State::Locked;
-        self.pwm.disable();
+        self.esc.disable();
         mbus::bus().call("/led/g/off", Message::Control(Signal::Led));
         mbus::bus().call("/led/r/on", Message::Control(Signal::Led));
     }
-    /// 解锁马达，红闪3下，绿开
+    /// 解锁马达，红关，绿开
     pub fn unlock(&mut self) {
         self.state = State::Unlocked;
-        for _ in 0..6 {
-            mbus::bus().call("/led/r/toggle", Message::Control(Signal::Led));
-            xtask::delay_us(1000 * 500);
-        }
+        mbus::bus().call("/led/r/off", Message::Control(Signal::Led));
         mbus::bus().call("/led/g/on", Message::Control(Signal::Led));
-        self.pwm.enable();
+        self.esc.enable();
     }
 
     /// 最小油门
     pub fn lowest(&mut self) {
-        if self.state == State::Unlocked {
-            self.pwm.set_duty(self.pwm.get_max_duty() / 2);
-        }
+        self.throttle(0.0)
     }
 
     /// 半油门
     pub fn half(&mut self) {
-        if self.state == State::Unlocked {
-            self.throttle(0.75)
-        }
+        self.throttle(0.5)
     }
 
     /// 全油门
     pub fn full(&mut self) {
-        if self.state == State::Unlocked {
-            self.pwm.set_duty(self.pwm.get_max_duty());
-        }
+        self.throttle(1.0)
     }
 
-    /// 给油，duty范围0.1-0.5
-    pub fn throttle(&mut self, duty: f32) {
+    /// 给油，范围0.0-1.0，锁定时输出停转(DShot电调需要持续收到帧)
+    pub fn throttle(&mut self, throttle: f32) {
         if self.state == State::Unlocked {
-            self.pwm
-                .set_duty((self.pwm.get_max_duty() as f32 * (duty + 0.5)) as u16);
+            self.esc.set_throttle(throttle);
             mbus::bus().call("/led/b/toggle", Message::None);
+        } else {
+            self.esc.set_throttle(0.0);
         }
     }
+
+    /// 发送DShot命令(蜂鸣、转向等)，只能在锁定时发送，不支持的命令返回false
+    pub fn command(&mut self, cmd: DshotCommand) -> bool {
+        self.state == State::Locked && self.esc.command(cmd)
+    }
 }
@@ -0,0 +1,346 @@
+//! CRSF(Crossfire/ExpressLRS)协议编解码
+//!
+//! 帧格式: 地址 + 长度 + 类型 + 负载 + CRC8，长度=类型+负载+CRC的字节数，
+//! CRC8(多项式0xD5)覆盖类型和负载。多字节字段为大端。
+//!
+use super::sbus::{raw_to_us, unpack_channels};
+use super::{LinkStatistics, RcChannels};
+use alloc::vec::Vec;
+
+/// 飞控地址，接收机发给飞控的帧以它开头，飞控回传遥测也用它
+pub const ADDRESS_FLIGHT_CONTROLLER: u8 = 0xC8;
+/// 最大帧长度
+pub const MAX_FRAME_SIZE: usize = 64;
+
+pub const FRAME_GPS: u8 = 0x02;
+pub const FRAME_BATTERY_SENSOR: u8 = 0x08;
+pub const FRAME_LINK_STATISTICS: u8 = 0x14;
+pub const FRAME_RC_CHANNELS_PACKED: u8 = 0x16;
+pub const FRAME_ATTITUDE: u8 = 0x1E;
+pub const FRAME_FLIGHT_MODE: u8 = 0x21;
+
+const RC_CHANNELS_PAYLOAD_SIZE: usize = 22;
+const LINK_STATISTICS_PAYLOAD_SIZE: usize = 10;
+
+/// 解码出的数据包
+#[derive(Debug, Clone, Copy)]
+pub enum CrsfPacket {
+    RcChannels(RcChannels),
+    LinkStatistics(LinkStatistics),
+}
+
+/// CRC8 DVB-S2
+pub fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, b| {
+        crc ^= *b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0xD5
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// 按帧类型和负载组帧
+pub fn encode(frame_type: u8, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(payload.len() + 4);
+    buf.push(ADDRESS_FLIGHT_CONTROLLER);
+    buf.push(payload.len() as u8 + 2);
+    buf.push(frame_type);
+    buf.extend_from_slice(payload);
+    buf.push(crc8(&buf[2..]));
+    buf
+}
+
+/// 姿态帧，单位弧度
+pub fn attitude(pitch: f32, roll: f32, yaw: f32) -> Vec<u8> {
+    let mut payload = [0u8; 6];
+    payload[0..2].copy_from_slice(&rad_to_i16(pitch).to_be_bytes());
+    payload[2..4].copy_from_slice(&rad_to_i16(roll).to_be_bytes());
+    payload[4..6].copy_from_slice(&rad_to_i16(yaw).to_be_bytes());
+    encode(FRAME_ATTITUDE, &payload)
+}
+
+/// 电池帧，电压单位V，电流单位A，已用容量单位mAh，剩余电量百分比
+pub fn battery(voltage: f32, current: f32, capacity: u32, remaining: u8) -> Vec<u8> {
+    let mut payload = [0u8; 8];
+    payload[0..2].copy_from_slice(&((voltage * 10.0) as u16).to_be_bytes());
+    payload[2..4].copy_from_slice(&((current * 10.0) as u16).to_be_bytes());
+    payload[4..7].copy_from_slice(&capacity.min(0xFF_FFFF).to_be_bytes()[1..4]);
+    payload[7] = remaining;
+    encode(FRAME_BATTERY_SENSOR, &payload)
+}
+
+/// 飞行模式帧，以0结尾的字符串
+pub fn flight_mode(mode: &str) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(mode.len() + 1);
+    payload.extend_from_slice(mode.as_bytes());
+    payload.push(0);
+    encode(FRAME_FLIGHT_MODE, &payload)
+}
+
+//弧度x10000
+#[inline]
+fn rad_to_i16(rad: f32) -> i16 {
+    (rad * 10000.0) as i16
+}
+
+fn decode_link_statistics(p: &[u8]) -> LinkStatistics {
+    LinkStatistics {
+        uplink_rssi_1: p[0],
+        uplink_rssi_2: p[1],
+        uplink_link_quality: p[2],
+        uplink_snr: p[3] as i8,
+        active_antenna: p[4],
+        rf_mode: p[5],
+        uplink_tx_power: p[6],
+        downlink_rssi: p[7],
+        downlink_link_quality: p[8],
+        downlink_snr: p[9] as i8,
+    }
+}
+
+/// 逐字节解析，CRC错误时按地址字节重新同步
+#[derive(Debug, Clone, Copy)]
+pub struct CrsfParser {
+    buf: [u8; MAX_FRAME_SIZE],
+    len: usize,
+    link_quality: Option<u8>, //最近一次上行链路质量
+}
+
+impl CrsfParser {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; MAX_FRAME_SIZE],
+            len: 0,
+            link_quality: None,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// 输入一个字节，收齐一帧且校验通过返回解码结果
+    pub fn parse(&mut self, b: u8) -> Option<CrsfPacket> {
+        if self.len == 0 && b != ADDRESS_FLIGHT_CONTROLLER {
+            return None;
+        }
+        self.buf[self.len] = b;
+        self.len += 1;
+        loop {
+            if self.len < 2 {
+                return None;
+            }
+            let frame_len = self.buf[1] as usize;
+            if frame_len < 2 || frame_len > MAX_FRAME_SIZE - 2 {
+                self.resync();
+                continue;
+            }
+            let total = frame_len + 2;
+            if self.len < total {
+                return None;
+            }
+            if crc8(&self.buf[2..total - 1]) != self.buf[total - 1] {
+                self.resync();
+                continue;
+            }
+            let packet = self.decode(self.buf[2], total);
+            //重新同步后缓冲区里可能还留有下一帧的数据
+            self.buf.copy_within(total..self.len, 0);
+            self.len -= total;
+            return packet;
+        }
+    }
+
+    fn decode(&mut self, frame_type: u8, total: usize) -> Option<CrsfPacket> {
+        let payload = &self.buf[3..total - 1];
+        match frame_type {
+            FRAME_RC_CHANNELS_PACKED if payload.len() == RC_CHANNELS_PAYLOAD_SIZE => {
+                let mut rc = RcChannels::default();
+                unpack_channels(payload)
+                    .iter()
+                    .enumerate()
+                    .for_each(|(i, raw)| rc.channels[i] = raw_to_us(*raw));
+                rc.count = 16;
+                //CRSF通道帧没有失控标志，以链路质量归零作为失控
+                rc.failsafe = self.link_quality == Some(0);
+                Some(CrsfPacket::RcChannels(rc))
+            }
+            FRAME_LINK_STATISTICS if payload.len() >= LINK_STATISTICS_PAYLOAD_SIZE => {
+                let stats = decode_link_statistics(payload);
+                self.link_quality = Some(stats.uplink_link_quality);
+                Some(CrsfPacket::LinkStatistics(stats))
+            }
+            _ => None,
+        }
+    }
+
+    /// 解析一段数据，每解出一个数据包回调一次
+    pub fn parse_slice<F: FnMut(CrsfPacket)>(&mut self, data: &[u8], mut f: F) {
+        data.iter().for_each(|b| {
+            if let Some(packet) = self.parse(*b) {
+                f(packet)
+            }
+        });
+    }
+
+    //从缓冲区里找下一个地址字节，丢弃之前的数据
+    fn resync(&mut self) {
+        match self.buf[1..self.len]
+            .iter()
+            .position(|b| *b == ADDRESS_FLIGHT_CONTROLLER)
+        {
+            Some(pos) => {
+                let start = pos + 1;
+                self.buf.copy_within(start..self.len, 0);
+                self.len -= start;
+            }
+            None => self.len = 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //按协议打包16个11位通道
+    fn pack_channels(channels: &[u16; 16]) -> [u8; RC_CHANNELS_PAYLOAD_SIZE] {
+        let mut payload = [0u8; RC_CHANNELS_PAYLOAD_SIZE];
+        let mut bits = 0u32;
+        let mut nbits = 0;
+        let mut i = 0;
+        for ch in channels {
+            bits |= (*ch as u32 & 0x07FF) << nbits;
+            nbits += 11;
+            while nbits >= 8 {
+                payload[i] = bits as u8;
+                bits >>= 8;
+                nbits -= 8;
+                i += 1;
+            }
+        }
+        payload
+    }
+
+    fn link_statistics(link_quality: u8) -> Vec<u8> {
+        encode(
+            FRAME_LINK_STATISTICS,
+            &[50, 60, link_quality, 10, 0, 4, 2, 70, 100, 0xF6],
+        )
+    }
+
+    fn parse_all(parser: &mut CrsfParser, data: &[u8]) -> Vec<CrsfPacket> {
+        let mut packets = Vec::new();
+        parser.parse_slice(data, |p| packets.push(p));
+        packets
+    }
+
+    #[test]
+    fn crc8_check_value() {
+        //CRC-8/DVB-S2的标准校验值
+        assert_eq!(crc8(b"123456789"), 0xBC);
+        assert_eq!(crc8(&[]), 0);
+    }
+
+    #[test]
+    fn telemetry_frames() {
+        let frame = battery(16.8, 12.3, 1500, 80);
+        assert_eq!(
+            &frame[..11],
+            &[
+                0xC8,
+                10,
+                FRAME_BATTERY_SENSOR,
+                0x00,
+                0xA8,
+                0x00,
+                0x7B,
+                0x00,
+                0x05,
+                0xDC,
+                80
+            ]
+        );
+        assert_eq!(frame[11], crc8(&frame[2..11]));
+
+        let frame = attitude(0.1, -0.2, 0.0);
+        assert_eq!(
+            &frame[..9],
+            &[0xC8, 8, FRAME_ATTITUDE, 0x03, 0xE8, 0xF8, 0x30, 0x00, 0x00]
+        );
+        assert_eq!(frame[9], crc8(&frame[2..9]));
+
+        let frame = flight_mode("ACRO");
+        assert_eq!(
+            &frame[..8],
+            &[0xC8, 7, FRAME_FLIGHT_MODE, b'A', b'C', b'R', b'O', 0]
+        );
+        assert_eq!(frame[8], crc8(&frame[2..8]));
+    }
+
+    #[test]
+    fn parse_rc_channels() {
+        let mut channels = [992u16; 16];
+        channels[0] = 172;
+        channels[15] = 1811;
+        let frame = encode(FRAME_RC_CHANNELS_PACKED, &pack_channels(&channels));
+        let packets = parse_all(&mut CrsfParser::new(), &frame);
+        assert_eq!(packets.len(), 1);
+        match packets[0] {
+            CrsfPacket::RcChannels(rc) => {
+                assert_eq!(rc.count, 16);
+                assert_eq!(rc.channels[0], 988);
+                assert_eq!(rc.channels[1], 1500);
+                assert_eq!(rc.channels[15], 2011);
+                assert!(!rc.failsafe);
+            }
+            _ => panic!("expected rc channels"),
+        }
+    }
+
+    #[test]
+    fn link_quality_zero_is_failsafe() {
+        let mut parser = CrsfParser::new();
+        let rc = encode(FRAME_RC_CHANNELS_PACKED, &pack_channels(&[992; 16]));
+        let mut data = link_statistics(0);
+        data.extend_from_slice(&rc);
+        let packets = parse_all(&mut parser, &data);
+        assert_eq!(packets.len(), 2);
+        match packets[0] {
+            CrsfPacket::LinkStatistics(stats) => {
+                assert_eq!(stats.uplink_link_quality, 0);
+                assert_eq!(stats.downlink_snr, -10);
+            }
+            _ => panic!("expected link statistics"),
+        }
+        assert!(matches!(packets[1], CrsfPacket::RcChannels(rc) if rc.failsafe));
+        //链路恢复
+        let mut data = link_statistics(100);
+        data.extend_from_slice(&rc);
+        let packets = parse_all(&mut parser, &data);
+        assert!(matches!(packets[1], CrsfPacket::RcChannels(rc) if !rc.failsafe));
+    }
+
+    #[test]
+    fn resync_after_garbage_and_bad_crc() {
+        let rc = encode(FRAME_RC_CHANNELS_PACKED, &pack_channels(&[992; 16]));
+        let mut bad = rc.clone();
+        let last = bad.len() - 1;
+        bad[last] ^= 0xFF;
+        //垃圾数据里混有地址字节和非法长度
+        let mut data = alloc::vec![0x00, 0xC8, 0xFF, 0x12, 0xC8];
+        data.extend_from_slice(&bad);
+        data.extend_from_slice(&rc);
+        data.extend_from_slice(&rc);
+        let packets = parse_all(&mut CrsfParser::new(), &data);
+        assert_eq!(packets.len(), 2);
+        assert!(packets
+            .iter()
+            .all(|p| matches!(p, CrsfPacket::RcChannels(rc) if rc.channels[0] == 1500)));
+    }
+}
@@ -0,0 +1,378 @@
+//! DShot数字油门协议
+//!
+//! 每帧16位，高位在前: 11位油门值、1位请求遥测、4位校验。油门值0为停转，1-47为命令，
+//! 48-2047为油门。每一位用一个PWM周期表示，高电平占周期3/4为1，3/8为0。
+//! 双向DShot的校验取反，电调在帧后约30us用同一根线以5/4位速率回传21位GCR编码的电周期，
+//! 由此得到eRPM，除以极对数即为转速。
+//! 本模块只负责帧编码、回传解码和油门映射，与具体的定时器、DMA无关。
+//!
+
+/// 停转
+pub const MOTOR_STOP: u16 = 0;
+/// 最小油门值
+pub const THROTTLE_MIN: u16 = 48;
+/// 最大油门值
+pub const THROTTLE_MAX: u16 = 2047;
+/// 一帧的位数
+pub const FRAME_BITS: usize = 16;
+/// DMA缓冲长度，帧后补两个低电平周期作为帧间隔
+pub const DMA_BUFFER_SIZE: usize = FRAME_BITS + 2;
+
+/// 速率
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DshotSpeed {
+    Dshot150,
+    Dshot300,
+    Dshot600,
+}
+
+impl DshotSpeed {
+    /// 位速率，单位Hz
+    pub fn bitrate(&self) -> u32 {
+        match self {
+            DshotSpeed::Dshot150 => 150_000,
+            DshotSpeed::Dshot300 => 300_000,
+            DshotSpeed::Dshot600 => 600_000,
+        }
+    }
+}
+
+/// DShot输出参数
+#[derive(Debug, Clone, Copy)]
+pub struct DshotConfig {
+    pub speed: DshotSpeed,
+    pub bidir: bool, //双向DShot，回传转速
+    pub poles: u8,   //电机磁极数
+}
+
+impl DshotConfig {
+    pub const fn new() -> Self {
+        Self {
+            speed: DshotSpeed::Dshot600,
+            bidir: true, //RPM陷波依赖回传的转速，电调固件不支持时关闭
+            poles: 14,
+        }
+    }
+}
+
+impl Default for DshotConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// DShot命令，只能在电机停转时发送
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum DshotCommand {
+    MotorStop = 0,
+    Beep1 = 1,
+    Beep2 = 2,
+    Beep3 = 3,
+    Beep4 = 4,
+    Beep5 = 5,
+    EscInfo = 6,
+    SpinDirection1 = 7,
+    SpinDirection2 = 8,
+    Mode3dOff = 9,
+    Mode3dOn = 10,
+    SettingsRequest = 11,
+    SaveSettings = 12,
+    SpinDirectionNormal = 20,
+    SpinDirectionReversed = 21,
+    Led0On = 22,
+    Led1On = 23,
+    Led2On = 24,
+    Led3On = 25,
+    Led0Off = 26,
+    Led1Off = 27,
+    Led2Off = 28,
+    Led3Off = 29,
+}
+
+impl DshotCommand {
+    /// 连续发送的帧数，修改设置的命令电调要求收到10次才生效
+    pub fn repeat(&self) -> u8 {
+        match self {
+            DshotCommand::SpinDirection1
+            | DshotCommand::SpinDirection2
+            | DshotCommand::Mode3dOff
+            | DshotCommand::Mode3dOn
+            | DshotCommand::SettingsRequest
+            | DshotCommand::SaveSettings
+            | DshotCommand::SpinDirectionNormal
+            | DshotCommand::SpinDirectionReversed => 10,
+            _ => 1,
+        }
+    }
+
+    /// 是否允许发送，油门只按单向映射，电调进入3D模式后会把低半段当成反转，所以不开启3D模式
+    pub fn supported(&self) -> bool {
+        *self != DshotCommand::Mode3dOn
+    }
+
+    /// 命令对应的帧值，设置类命令要求置遥测位
+    pub fn frame(&self) -> u16 {
+        frame(*self as u16, self.repeat() > 1)
+    }
+
+    /// 双向DShot下命令对应的帧值
+    pub fn frame_bidir(&self) -> u16 {
+        frame_bidir(*self as u16, self.repeat() > 1)
+    }
+}
+
+/// 油门0.0~1.0转DShot油门值，0为停转
+pub fn throttle_to_value(throttle: f32) -> u16 {
+    if !(throttle > 0.0) {
+        return MOTOR_STOP;
+    }
+    let span = (THROTTLE_MAX - THROTTLE_MIN) as f32;
+    THROTTLE_MIN + (throttle.min(1.0) * span + 0.5) as u16
+}
+
+/// 校验，对高12位按4位一组异或
+pub fn crc(packet: u16) -> u16 {
+    (packet ^ (packet >> 4) ^ (packet >> 8)) & 0x0f
+}
+
+/// 组帧，value为0-2047的油门值或命令
+pub fn frame(value: u16, telemetry: bool) -> u16 {
+    let packet = ((value & 0x07ff) << 1) | telemetry as u16;
+    (packet << 4) | crc(packet)
+}
+
+/// 双向DShot组帧，校验取反，电调据此回传转速
+pub fn frame_bidir(value: u16, telemetry: bool) -> u16 {
+    let packet = ((value & 0x07ff) << 1) | telemetry as u16;
+    (packet << 4) | (!crc(packet) & 0x0f)
+}
+
+/// 帧转成每一位的比较值，period为一位的定时器计数周期，末尾补0
+pub fn encode(frame: u16, period: u32, buf: &mut [u32; DMA_BUFFER_SIZE]) {
+    let one = period * 3 / 4;
+    let zero = period * 3 / 8;
+    for (i, v) in buf.iter_mut().enumerate() {
+        *v = if i < FRAME_BITS {
+            if frame & (0x8000 >> i) != 0 {
+                one
+            } else {
+                zero
+            }
+        } else {
+            0
+        };
+    }
+}
+
+/// 回传的位数，含起始位
+pub const TELEMETRY_BITS: usize = 21;
+
+/// 5位GCR码到4位的映射，无效码为0xff
+const GCR_TABLE: [u8; 32] = [
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x09, 0x0a, 0x0b, 0xff, 0x0d, 0x0e, 0x0f,
+    0xff, 0xff, 0x02, 0x03, 0xff, 0x05, 0x06, 0x07, 0xff, 0x00, 0x08, 0x01, 0xff, 0x04, 0x0c, 0xff,
+];
+
+/// 由回传线上电平跳变的时间戳还原21位电平，bit为回传一位的计数周期
+///
+/// 第一个时间戳是起始位的下降沿，最后一个跳变之后到21位为止都保持最后的电平
+pub fn edges_to_levels(edges: &[u32], bit: u32) -> Option<u32> {
+    if edges.len() < 2 || bit == 0 {
+        return None;
+    }
+    let mut levels = 0u32;
+    let mut count = 0usize;
+    let mut level = 0u32;
+    for w in edges.windows(2) {
+        let n = ((w[1].wrapping_sub(w[0]) + bit / 2) / bit) as usize;
+        if n == 0 || count + n > TELEMETRY_BITS {
+            return None;
+        }
+        for _ in 0..n {
+            levels = (levels << 1) | level;
+        }
+        count += n;
+        level ^= 1;
+    }
+    for _ in count..TELEMETRY_BITS {
+        levels = (levels << 1) | level;
+    }
+    Some(levels)
+}
+
+/// 解码21位电平，返回eRPM，校验失败返回None，电机停转返回0
+///
+/// 电平按NRZI(跳变为1)还原为20位GCR，每5位还原成4位，得到16位: 3位指数、9位尾数、4位校验，
+/// 电周期(us)为尾数左移指数位。
+pub fn decode_telemetry(levels: u32) -> Option<u32> {
+    let mut gcr = (levels ^ (levels >> 1)) & 0x000f_ffff;
+    let mut value = 0u32;
+    for i in 0..4 {
+        let nibble = GCR_TABLE[(gcr & 0x1f) as usize];
+        if nibble == 0xff {
+            return None;
+        }
+        value |= (nibble as u32) << (i * 4);
+        gcr >>= 5;
+    }
+    let mut csum = value;
+    csum ^= csum >> 8;
+    csum ^= csum >> 4;
+    if csum & 0x0f != 0x0f {
+        return None;
+    }
+    let value = value >> 4;
+    if value == 0x0fff {
+        return Some(0);
+    }
+    let period_us = (value & 0x01ff) << (value >> 9);
+    if period_us == 0 {
+        return None;
+    }
+    Some(60_000_000 / period_us)
+}
+
+/// eRPM转机械转速，poles为电机磁极数
+pub fn erpm_to_rpm(erpm: u32, poles: u8) -> f32 {
+    if poles < 2 {
+        return erpm as f32;
+    }
+    erpm as f32 / (poles / 2) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_crc() {
+        //Betaflight文档里的例子: 油门1046，不请求遥测
+        assert_eq!(crc(1046 << 1), 0x6);
+        assert_eq!(frame(1046, false), 0x82C6);
+        assert_eq!(frame_bidir(1046, false), 0x82C9);
+        assert_eq!(frame(1046, true), 0x82D7);
+        assert_eq!(frame(THROTTLE_MAX, false), 0xFFEE);
+        assert_eq!(DshotCommand::MotorStop.frame(), 0x0000);
+        assert_eq!(DshotCommand::Beep1.frame(), 0x0022);
+        //设置类命令置遥测位
+        assert_eq!(DshotCommand::SaveSettings.frame(), 0x0198);
+        assert_eq!(DshotCommand::SaveSettings.frame_bidir(), 0x0197);
+    }
+
+    #[test]
+    fn mode_3d_rejected() {
+        assert!(!DshotCommand::Mode3dOn.supported());
+        assert!(DshotCommand::Mode3dOff.supported());
+        assert!(DshotCommand::SpinDirectionReversed.supported());
+        assert!(DshotCommand::Beep1.supported());
+    }
+
+    #[test]
+    fn encode_bits() {
+        let mut buf = [1; DMA_BUFFER_SIZE];
+        encode(0x82C6, 80, &mut buf);
+        let expected = [1, 0, 0, 0, 0, 0, 1, 0, 1, 1, 0, 0, 0, 1, 1, 0];
+        for (v, bit) in buf.iter().zip(expected.iter()) {
+            assert_eq!(*v, if *bit == 1 { 60 } else { 30 });
+        }
+        assert_eq!(&buf[FRAME_BITS..], &[0, 0]);
+    }
+
+    //按电调的做法把电周期编码成21位电平: 12位数值加4位校验，每4位转5位GCR，再按NRZI转电平
+    fn telemetry_levels(period_us: u32) -> u32 {
+        let value = if period_us == 0 {
+            0x0fff
+        } else {
+            let mut exp = 0;
+            while period_us >> exp > 0x01ff {
+                exp += 1;
+            }
+            (exp << 9) | (period_us >> exp)
+        };
+        let csum = !(value ^ (value >> 4) ^ (value >> 8)) & 0x0f;
+        let packet = (value << 4) | csum;
+        let mut gcr = 0u32;
+        for i in (0..4).rev() {
+            let nibble = (packet >> (i * 4)) & 0x0f;
+            let code = GCR_TABLE.iter().position(|&n| n as u32 == nibble).unwrap() as u32;
+            gcr = (gcr << 5) | code;
+        }
+        //起始位为低电平，之后每个1翻转一次
+        let mut levels = 0u32;
+        let mut level = 0u32;
+        for i in (0..20).rev() {
+            level ^= (gcr >> i) & 1;
+            levels |= level << i;
+        }
+        levels
+    }
+
+    //电平转为跳变时间戳，每个跳变加一点抖动
+    fn levels_to_edges(levels: u32, bit: u32) -> alloc::vec::Vec<u32> {
+        let mut edges = alloc::vec![1000];
+        let mut level = 0;
+        for i in 1..TELEMETRY_BITS {
+            let l = (levels >> (TELEMETRY_BITS - 1 - i)) & 1;
+            if l != level {
+                let jitter = if edges.len() % 2 == 0 { bit / 5 } else { 0 };
+                edges.push(1000 + i as u32 * bit + jitter);
+                level = l;
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn gcr_decode() {
+        let bit = 64;
+        for period_us in [100u32, 511, 1000, 5000, 40000] {
+            let levels = telemetry_levels(period_us);
+            let edges = levels_to_edges(levels, bit);
+            assert_eq!(edges_to_levels(&edges, bit), Some(levels));
+            //周期按3位指数、9位尾数量化
+            let mut exp = 0;
+            while period_us >> exp > 0x01ff {
+                exp += 1;
+            }
+            let expected = 60_000_000 / ((period_us >> exp) << exp);
+            assert_eq!(decode_telemetry(levels), Some(expected));
+        }
+        assert_eq!(decode_telemetry(telemetry_levels(1000)), Some(60_000));
+        //停转
+        assert_eq!(decode_telemetry(telemetry_levels(0)), Some(0));
+    }
+
+    #[test]
+    fn gcr_rejects_corruption() {
+        let levels = telemetry_levels(1000);
+        //翻转一位电平会得到无效GCR码或者校验错误
+        for i in 0..20 {
+            assert_eq!(decode_telemetry(levels ^ (1 << i)), None, "bit {}", i);
+        }
+        //跳变太密或者超过21位
+        assert_eq!(edges_to_levels(&[0, 10], 64), None);
+        assert_eq!(edges_to_levels(&[0, 64 * 22], 64), None);
+        assert_eq!(edges_to_levels(&[0], 64), None);
+    }
+
+    #[test]
+    fn erpm_to_hz() {
+        //14极电机7对极，42000eRPM为6000rpm，即100Hz
+        let rpm = erpm_to_rpm(42_000, 14);
+        assert!((rpm - 6000.0).abs() < 1e-3);
+        assert!((rpm / 60.0 - 100.0).abs() < 1e-3);
+        assert_eq!(erpm_to_rpm(42_000, 0), 42_000.0);
+        assert_eq!(erpm_to_rpm(42_000, 2), 42_000.0);
+    }
+
+    #[test]
+    fn throttle_mapping() {
+        assert_eq!(throttle_to_value(0.0), MOTOR_STOP);
+        assert_eq!(throttle_to_value(-0.5), MOTOR_STOP);
+        assert_eq!(throttle_to_value(f32::NAN), MOTOR_STOP);
+        assert_eq!(throttle_to_value(0.5), 1048);
+        assert_eq!(throttle_to_value(1.0), THROTTLE_MAX);
+        assert_eq!(throttle_to_value(2.0), THROTTLE_MAX);
+    }
+}
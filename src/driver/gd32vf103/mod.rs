@@ -17,6 +17,12 @@ use xtask::bsp::longan_nano::hal::exti::ExtiLine;
 use xtask::bsp::longan_nano::hal::exti::TriggerEdge;
 use xtask::bsp::longan_nano::stdout;
 
+/// 上电以来的微秒数，由mcycle周期计数器换算
+pub fn now_us() -> u64 {
+    use xtask::arch::riscv::register::mcycle;
+    mcycle::read64() / (xtask::chip::CPU_CLOCK_HZ as u64 / 1_000_000)
+}
+
 pub unsafe fn init() {
     if let Some(dp) = Peripherals::take() {
         rcu::init(dp.RCU);
@@ -43,6 +49,12 @@ pub unsafe fn init() {
             &mut afio,
             rcu,
         );
+        servo::init(
+            dp.TIMER3,
+            (Some(pb.pb6), Some(pb.pb7), Some(pb.pb8), Some(pb.pb9)),
+            &mut afio,
+            rcu,
+        );
         mpu6050::init(dp.TIMER0, (pb.pb10, pb.pb11), rcu, dp.I2C1);
         //mpu6050_dmp::init((pb.pb10, pb.pb11), rcu, dp.I2C1);
     }
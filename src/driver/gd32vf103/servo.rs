@@ -1,5 +1,6 @@
-//! 舵机驱动
+//! 舵机驱动，TIMER3四个通道，标准50Hz PWM
 
+use crate::driver::pwm::PulseWidth;
 use embedded_hal::Pwm;
 use xtask::bsp::longan_nano::hal::gpio::gpiob::{PB6, PB7, PB8, PB9};
 use xtask::bsp::longan_nano::hal::pac::TIMER3;
@@ -13,6 +14,8 @@ use xtask::bsp::longan_nano::hal::{
 };
 
 static mut SERVO: Option<Servo> = None;
+/// 帧率
+const RATE_HZ: u32 = 50;
 
 pub struct Servo {
     pwm: PwmTimer<TIMER3, NoRemap>,
@@ -58,7 +61,7 @@ pub fn init<T>(
         rcu,
         afio,
     );
-    pwm.set_period(1000.hz());
+    pwm.set_period(RATE_HZ.hz());
     if pb6.is_some() {
         pwm.enable(Channel::CH0);
     }
@@ -86,3 +89,13 @@ impl Servo {
         self.pwm.set_duty(ch, (self.max_duty as f32 * duty) as u16);
     }
 }
+
+/// 单个舵机通道，用于driver::servo::Servo
+pub struct ServoChannel(pub Channel);
+
+impl PulseWidth for ServoChannel {
+    fn set_pulse_us(&mut self, us: f32) {
+        let period = 1_000_000.0 / RATE_HZ as f32;
+        servo().duty(self.0, us.max(0.0).min(period) / period);
+    }
+}
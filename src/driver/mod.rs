@@ -1,19 +1,35 @@
+// sbus和crsf共用USART3、DMA1 Stream1和PD9，ppm和它们同时启用会有两个遥控输入源，接收机只能选一个
+#[cfg(any(
+    all(feature = "sbus", feature = "crsf"),
+    all(feature = "sbus", feature = "ppm"),
+    all(feature = "crsf", feature = "ppm")
+))]
+compile_error!("receiver features sbus, crsf and ppm are mutually exclusive, use --no-default-features to select crsf or ppm");
+
 #[cfg(feature = "gd32vf103")]
 mod gd32vf103;
 #[cfg(feature = "gd32vf103")]
-pub use gd32vf103::{bldc, led, mpu6050, serial, servo};
+pub use gd32vf103::{led, serial, servo::ServoChannel};
 
 #[cfg(any(feature = "stm32f401ccu6", feature = "stm32f427vit6"))]
 pub mod stm32f4;
 
 pub mod bldc;
+pub mod crsf;
+pub mod dshot;
 pub mod mpu6050;
 pub mod ppm;
+pub mod pwm;
 pub mod sbus;
 pub mod servo;
 
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use bldc::{EscOutput, Motor};
 use nalgebra::UnitQuaternion;
 use nalgebra::Vector3;
+use pwm::PulseWidth;
+use servo::Servo;
 
 pub fn init() {
     #[cfg(feature = "gd32vf103")]
@@ -33,6 +49,42 @@ pub fn init() {
     }
 }
 
+/// 上电以来的毫秒数，u32约49天回绕，用wrapping_sub计算间隔。
+/// 由CPU周期计数器换算，不受任务调度延迟影响，没有芯片驱动时为0
+pub fn now_ms() -> u32 {
+    #[cfg(feature = "gd32vf103")]
+    return (gd32vf103::now_us() / 1000) as u32;
+    #[cfg(any(feature = "stm32f401ccu6", feature = "stm32f427vit6"))]
+    return (stm32f4::clock::now_us() / 1000) as u32;
+    #[allow(unreachable_code)]
+    0
+}
+
+/// 电机，各输出口的定时器和通道类型不同，统一成trait对象
+pub type OutputMotor = Motor<Box<dyn EscOutput>>;
+/// 舵机
+pub type OutputServo = Servo<Box<dyn PulseWidth>>;
+
+/// 板上的执行机构，驱动初始化时按机型分配输出口，机型模块启动时取走
+#[derive(Default)]
+pub struct Outputs {
+    pub motors: Vec<OutputMotor>, //按电机序号排列
+    pub servos: Vec<OutputServo>, //按舵机序号排列
+}
+
+static mut OUTPUTS: Option<Outputs> = None;
+
+pub(crate) fn set_outputs(outputs: Outputs) {
+    unsafe {
+        OUTPUTS.replace(outputs);
+    }
+}
+
+/// 取走执行机构，只能取一次，驱动没有初始化输出口时为空
+pub fn take_outputs() -> Outputs {
+    unsafe { OUTPUTS.take().unwrap_or_default() }
+}
+
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Euler {
     pub roll: f32,
@@ -46,6 +98,7 @@ impl Euler {
     }
 }
 
+/// IMU数据，驱动发布到"/imu/raw"的是芯片的轴(前左上)，"/imu"上已转为机体前右下
 #[derive(Copy, Clone, Debug, Default)]
 pub struct ImuData {
     pub accel: Option<Accel>,
@@ -119,8 +172,63 @@ pub struct Barometer {
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Distance(pub f32);
 
+/// GPS定位，经纬度用f64，f32在经纬度上只有约1米的分辨率
 #[derive(Copy, Clone, Debug, Default)]
 pub struct Gps {
-    pub longitude: f32,
-    pub latitude: f32,
+    pub longitude: f64,                 //经度，单位度
+    pub latitude: f64,                  //纬度，单位度
+    pub altitude: f32,                  //海拔，单位米
+    pub velocity: Option<Vector3<f32>>, //北东地速度，单位m/s
+    pub h_acc: f32,                     //位置精度，单位米，0为未知
+    pub s_acc: f32,                     //速度精度，单位m/s，0为未知
+    pub fix: bool,                      //3D定位有效
+}
+
+/// 遥控接收机最大通道数
+pub const MAX_RC_CHANNELS: usize = 18;
+/// 最多电机数
+pub const MAX_MOTORS: usize = 8;
+/// IMU采样频率，单位Hz。RPM陷波和动态陷波要求采样率高于最高陷波频率的2倍，
+/// MPU6050/MPU9250/ICM20602开启数字低通时最高输出1kHz。所有IMU驱动都按这个频率读取，
+/// 姿态融合、滤波、控制周期和遥测分频都由它计算
+pub const IMU_SAMPLE_HZ: u32 = 1000;
+
+/// 遥控接收机通道数据，通道值单位微秒
+#[derive(Copy, Clone, Debug, Default)]
+pub struct RcChannels {
+    pub channels: [u16; MAX_RC_CHANNELS],
+    pub count: u8,        //有效通道数
+    pub frame_lost: bool, //丢帧
+    pub failsafe: bool,   //接收机进入失控保护
+}
+
+/// 无线链路统计
+#[derive(Copy, Clone, Debug, Default)]
+pub struct LinkStatistics {
+    pub uplink_rssi_1: u8,         //上行天线1信号强度，单位-dBm
+    pub uplink_rssi_2: u8,         //上行天线2信号强度，单位-dBm
+    pub uplink_link_quality: u8,   //上行链路质量，百分比
+    pub uplink_snr: i8,            //上行信噪比，单位dB
+    pub active_antenna: u8,        //当前天线
+    pub rf_mode: u8,               //射频模式(包速率)
+    pub uplink_tx_power: u8,       //发射功率档位
+    pub downlink_rssi: u8,         //下行信号强度，单位-dBm
+    pub downlink_link_quality: u8, //下行链路质量，百分比
+    pub downlink_snr: i8,          //下行信噪比，单位dB
+}
+
+/// 电机转速，由电调回传
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MotorRpm {
+    pub index: u8, //电机序号，从0开始
+    pub rpm: f32,  //转速，单位转每分钟
+}
+
+/// 电池
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Battery {
+    pub voltage: f32,  //电压，单位V
+    pub current: f32,  //电流，单位A
+    pub capacity: u32, //已用容量，单位mAh
+    pub remaining: u8, //剩余电量百分比
 }
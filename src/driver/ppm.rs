@@ -1 +1,123 @@
-//! TODO
+//! PPM接收机解码
+//!
+//! PPM把所有通道串在一路信号里，相邻两个上升沿的间隔就是一个通道的脉宽(1000-2000us)，
+//! 一帧结束后是一段较长的同步间隔(一般大于3ms)。解码器只关心脉宽序列，
+//! 脉宽由定时器输入捕获测量后逐个输入。
+//!
+use super::RcChannels;
+
+/// 最大通道数
+pub const MAX_CHANNELS: usize = 12;
+/// 一帧最少通道数，少于这个数认为是干扰
+const MIN_CHANNELS: usize = 4;
+/// 同步间隔阈值，单位微秒
+const SYNC_GAP_US: u32 = 2700;
+/// 有效脉宽范围，单位微秒
+const MIN_PULSE_US: u32 = 750;
+const MAX_PULSE_US: u32 = 2250;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PpmDecoder {
+    channels: [u16; MAX_CHANNELS],
+    index: usize, //当前通道
+    synced: bool, //已找到同步间隔
+}
+
+impl PpmDecoder {
+    pub const fn new() -> Self {
+        Self {
+            channels: [0; MAX_CHANNELS],
+            index: 0,
+            synced: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.index = 0;
+        self.synced = false;
+    }
+
+    /// 输入一个脉宽(微秒)，遇到同步间隔且上一帧完整时返回通道数据
+    pub fn push(&mut self, width_us: u32) -> Option<RcChannels> {
+        if width_us >= SYNC_GAP_US {
+            let frame = if self.synced && self.index >= MIN_CHANNELS {
+                let mut rc = RcChannels::default();
+                rc.channels[..self.index].copy_from_slice(&self.channels[..self.index]);
+                rc.count = self.index as u8;
+                Some(rc)
+            } else {
+                None
+            };
+            self.synced = true;
+            self.index = 0;
+            return frame;
+        }
+        if !self.synced {
+            return None;
+        }
+        if width_us < MIN_PULSE_US || width_us > MAX_PULSE_US || self.index >= MAX_CHANNELS {
+            //脉宽异常或通道过多，丢弃本帧，等下一个同步间隔
+            self.reset();
+            return None;
+        }
+        self.channels[self.index] = width_us as u16;
+        self.index += 1;
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: [u32; 8] = [1500, 1100, 1900, 1000, 2000, 1500, 1200, 1800];
+
+    fn feed(decoder: &mut PpmDecoder, widths: &[u32]) -> Option<RcChannels> {
+        widths
+            .iter()
+            .fold(None, |last, w| decoder.push(*w).or(last))
+    }
+
+    #[test]
+    fn frame_after_sync_gap() {
+        let mut decoder = PpmDecoder::new();
+        //第一个同步间隔之前的脉宽不完整，丢弃
+        assert!(feed(&mut decoder, &[1500, 1500]).is_none());
+        assert!(decoder.push(4000).is_none());
+        assert!(feed(&mut decoder, &FRAME).is_none());
+        let rc = decoder.push(SYNC_GAP_US).unwrap();
+        assert_eq!(rc.count, 8);
+        let expect: alloc::vec::Vec<u16> = FRAME.iter().map(|w| *w as u16).collect();
+        assert_eq!(&rc.channels[..8], &expect[..]);
+        //紧接着的一帧同样按同步间隔切分
+        assert!(feed(&mut decoder, &FRAME[..6]).is_none());
+        assert_eq!(decoder.push(5000).unwrap().count, 6);
+    }
+
+    #[test]
+    fn invalid_pulse_drops_frame() {
+        let mut decoder = PpmDecoder::new();
+        decoder.push(4000);
+        //2250us以上、同步阈值以下的脉宽既不是通道也不是同步，丢弃本帧
+        assert!(feed(&mut decoder, &[1500, 1500, 1500, 1500, 2500]).is_none());
+        assert!(decoder.push(4000).is_none());
+    }
+
+    #[test]
+    fn channel_count_limits() {
+        let mut decoder = PpmDecoder::new();
+        decoder.push(4000);
+        //少于4个通道是干扰
+        feed(&mut decoder, &[1500, 1500, 1500]);
+        assert!(decoder.push(4000).is_none());
+        //正好12个通道
+        feed(&mut decoder, &[1500; MAX_CHANNELS]);
+        assert_eq!(decoder.push(4000).unwrap().count, MAX_CHANNELS as u8);
+        //超过12个通道丢弃
+        feed(&mut decoder, &[1500; MAX_CHANNELS + 1]);
+        assert!(decoder.push(4000).is_none());
+        //丢弃后要等到新的同步间隔
+        feed(&mut decoder, &FRAME);
+        assert_eq!(decoder.push(4000).unwrap().count, 8);
+    }
+}
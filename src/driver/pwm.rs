@@ -0,0 +1,213 @@
+//! 脉宽输出，按微秒设置脉宽
+//!
+//! 支持标准PWM(1000-2000us，50-490Hz)、OneShot125、OneShot42和Multishot。
+//! 同一定时器的通道共用周期，电调和舵机共用TIM2时两者的输出模式必须一致，在配置时检查。
+//!
+use crate::driver::bldc::EscOutput;
+use alloc::boxed::Box;
+use embedded_hal::PwmPin;
+
+/// 标准PWM最低帧率
+pub const STANDARD_MIN_RATE_HZ: u16 = 50;
+/// 标准PWM最高帧率
+pub const STANDARD_MAX_RATE_HZ: u16 = 490;
+
+static mut CONFIG: OutputConfig = OutputConfig::new();
+
+/// 输出模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PwmProtocol {
+    /// 标准PWM，1000-2000us，帧率50-490Hz
+    Standard { rate_hz: u16 },
+    /// 125-250us
+    OneShot125,
+    /// 42-84us
+    OneShot42,
+    /// 5-25us
+    Multishot,
+}
+
+impl PwmProtocol {
+    /// 最小和最大脉宽，单位us
+    pub fn pulse_range_us(&self) -> (f32, f32) {
+        match self {
+            PwmProtocol::Standard { .. } => (1000.0, 2000.0),
+            PwmProtocol::OneShot125 => (125.0, 250.0),
+            PwmProtocol::OneShot42 => (42.0, 84.0),
+            PwmProtocol::Multishot => (5.0, 25.0),
+        }
+    }
+
+    /// 帧率，单位Hz
+    pub fn rate_hz(&self) -> u32 {
+        match self {
+            PwmProtocol::Standard { rate_hz } => *rate_hz as u32,
+            PwmProtocol::OneShot125 => 2_000,
+            PwmProtocol::OneShot42 => 8_000,
+            PwmProtocol::Multishot => 32_000,
+        }
+    }
+
+    /// 定时器计数频率，保证脉宽范围内约1000级以上的分辨率
+    pub fn tick_hz(&self) -> u32 {
+        match self {
+            PwmProtocol::Standard { .. } => 1_000_000,
+            PwmProtocol::OneShot125 => 8_000_000,
+            PwmProtocol::OneShot42 => 24_000_000,
+            PwmProtocol::Multishot => 84_000_000,
+        }
+    }
+
+    /// 周期，单位us
+    pub fn period_us(&self) -> f32 {
+        1_000_000.0 / self.rate_hz() as f32
+    }
+
+    pub fn check(&self) -> Result<(), Error> {
+        if let PwmProtocol::Standard { rate_hz } = self {
+            if *rate_hz < STANDARD_MIN_RATE_HZ || *rate_hz > STANDARD_MAX_RATE_HZ {
+                return Err(Error::RateOutOfRange(*rate_hz));
+            }
+        }
+        Ok(())
+    }
+
+    /// 按定时器输入时钟计算预分频和重装载值
+    pub fn timer_config(&self, timclk: u32) -> Result<TimerConfig, Error> {
+        self.check()?;
+        let psc = (timclk / self.tick_hz()).max(1) - 1;
+        let arr = (timclk / (psc + 1) / self.rate_hz()).saturating_sub(1);
+        if arr > u16::MAX as u32 || psc > u16::MAX as u32 {
+            return Err(Error::Resolution);
+        }
+        Ok(TimerConfig {
+            psc: psc as u16,
+            arr: arr as u16,
+        })
+    }
+
+    /// 油门0.0~1.0转脉宽
+    pub fn throttle_to_us(&self, throttle: f32) -> f32 {
+        let (min, max) = self.pulse_range_us();
+        min + throttle.max(0.0).min(1.0) * (max - min)
+    }
+}
+
+/// 定时器配置，计数频率为timclk/(psc+1)，周期为arr+1个计数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimerConfig {
+    pub psc: u16,
+    pub arr: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// 标准PWM帧率超出50-490Hz
+    RateOutOfRange(u16),
+    /// 共用定时器的通道输出模式不一致
+    TimerGroupConflict(PwmProtocol, PwmProtocol),
+    /// 舵机只能用标准PWM
+    ServoProtocol(PwmProtocol),
+    /// 定时器时钟下达不到需要的周期或分辨率
+    Resolution,
+}
+
+/// 输出配置，电调和舵机共用TIM2，None表示不使用该定时器输出(比如电调用DShot)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputConfig {
+    pub esc: Option<PwmProtocol>,
+    pub servo: Option<PwmProtocol>,
+}
+
+impl OutputConfig {
+    pub const fn new() -> Self {
+        Self {
+            esc: Some(PwmProtocol::Standard { rate_hz: 50 }),
+            servo: Some(PwmProtocol::Standard { rate_hz: 50 }),
+        }
+    }
+
+    /// 检查定时器组约束，返回TIM2的输出模式
+    pub fn timer_group(&self) -> Result<Option<PwmProtocol>, Error> {
+        if let Some(servo) = self.servo {
+            if !matches!(servo, PwmProtocol::Standard { .. }) {
+                return Err(Error::ServoProtocol(servo));
+            }
+        }
+        match (self.esc, self.servo) {
+            (Some(esc), Some(servo)) if esc != servo => Err(Error::TimerGroupConflict(esc, servo)),
+            (Some(p), _) | (None, Some(p)) => p.check().map(|_| Some(p)),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+impl Default for OutputConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 修改输出配置，需在驱动初始化之前调用，不满足定时器组约束时返回错误
+pub fn configure(config: OutputConfig) -> Result<(), Error> {
+    config.timer_group()?;
+    unsafe {
+        CONFIG = config;
+    }
+    Ok(())
+}
+
+pub fn config() -> OutputConfig {
+    unsafe { CONFIG }
+}
+
+/// 按微秒设置脉宽
+pub trait PulseWidth {
+    fn set_pulse_us(&mut self, us: f32);
+}
+
+impl<T: PulseWidth + ?Sized> PulseWidth for Box<T> {
+    fn set_pulse_us(&mut self, us: f32) {
+        (**self).set_pulse_us(us)
+    }
+}
+
+/// 按微秒输出脉宽的通道
+pub struct PulseOutput<PWM> {
+    pwm: PWM,
+    protocol: PwmProtocol,
+}
+
+impl<PWM: PwmPin<Duty = u16>> PulseOutput<PWM> {
+    /// pwm所在定时器需已按protocol.timer_config配置好
+    pub fn new(pwm: PWM, protocol: PwmProtocol) -> Self {
+        Self { pwm, protocol }
+    }
+
+    pub fn protocol(&self) -> PwmProtocol {
+        self.protocol
+    }
+}
+
+impl<PWM: PwmPin<Duty = u16>> PulseWidth for PulseOutput<PWM> {
+    fn set_pulse_us(&mut self, us: f32) {
+        let period = self.protocol.period_us();
+        let max = self.pwm.get_max_duty() as f32;
+        let duty = (us.max(0.0).min(period) / period * max + 0.5) as u16;
+        self.pwm.set_duty(duty);
+    }
+}
+
+impl<PWM: PwmPin<Duty = u16>> EscOutput for PulseOutput<PWM> {
+    fn enable(&mut self) {
+        self.pwm.enable()
+    }
+
+    fn disable(&mut self) {
+        self.pwm.disable()
+    }
+
+    fn set_throttle(&mut self, throttle: f32) {
+        self.set_pulse_us(self.protocol.throttle_to_us(throttle));
+    }
+}
@@ -1 +1,257 @@
-//! TODO
+//! SBUS协议解析
+//!
+//! 帧格式共25字节: 帧头0x0F + 22字节通道数据(16通道x11位，低位在前) + 标志位 + 帧尾0x00
+//! 标志位: bit0 数字通道17，bit1 数字通道18，bit2 丢帧，bit3 失控保护
+//!
+use super::RcChannels;
+
+/// 帧长度
+pub const FRAME_SIZE: usize = 25;
+/// 模拟通道数
+pub const CHANNELS: usize = 16;
+
+const HEADER: u8 = 0x0F;
+const FOOTER: u8 = 0x00;
+
+const FLAG_CH17: u8 = 0x01;
+const FLAG_CH18: u8 = 0x02;
+const FLAG_FRAME_LOST: u8 = 0x04;
+const FLAG_FAILSAFE: u8 = 0x08;
+
+/// SBUS帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SbusFrame {
+    pub channels: [u16; CHANNELS], //原始通道值，范围0-2047
+    pub ch17: bool,                //数字通道17
+    pub ch18: bool,                //数字通道18
+    pub frame_lost: bool,          //丢帧
+    pub failsafe: bool,            //失控保护
+}
+
+impl SbusFrame {
+    /// 转换为以微秒为单位的通道数据
+    pub fn to_rc(&self) -> RcChannels {
+        let mut rc = RcChannels::default();
+        self.channels
+            .iter()
+            .enumerate()
+            .for_each(|(i, raw)| rc.channels[i] = raw_to_us(*raw));
+        rc.channels[16] = digital_to_us(self.ch17);
+        rc.channels[17] = digital_to_us(self.ch18);
+        rc.count = CHANNELS as u8 + 2;
+        rc.frame_lost = self.frame_lost;
+        rc.failsafe = self.failsafe;
+        rc
+    }
+}
+
+impl From<SbusFrame> for RcChannels {
+    fn from(frame: SbusFrame) -> Self {
+        frame.to_rc()
+    }
+}
+
+/// 原始通道值转微秒，172->988us，992->1500us，1811->2011us
+#[inline]
+pub fn raw_to_us(raw: u16) -> u16 {
+    ((raw as i32 - 992) * 5 / 8 + 1500) as u16
+}
+
+#[inline]
+fn digital_to_us(on: bool) -> u16 {
+    if on {
+        2000
+    } else {
+        1000
+    }
+}
+
+/// 解包16个11位通道，data至少22字节
+pub fn unpack_channels(data: &[u8]) -> [u16; CHANNELS] {
+    let mut channels = [0u16; CHANNELS];
+    let mut bits = 0u32;
+    let mut nbits = 0;
+    let mut ch = 0;
+    for b in data.iter().take(22) {
+        bits |= (*b as u32) << nbits;
+        nbits += 8;
+        while nbits >= 11 && ch < CHANNELS {
+            channels[ch] = (bits & 0x07FF) as u16;
+            bits >>= 11;
+            nbits -= 11;
+            ch += 1;
+        }
+    }
+    channels
+}
+
+/// 解码一个完整的25字节帧，帧头或帧尾不对返回None
+pub fn decode(frame: &[u8]) -> Option<SbusFrame> {
+    if frame.len() != FRAME_SIZE || frame[0] != HEADER || frame[FRAME_SIZE - 1] != FOOTER {
+        return None;
+    }
+    let flags = frame[23];
+    Some(SbusFrame {
+        channels: unpack_channels(&frame[1..23]),
+        ch17: flags & FLAG_CH17 != 0,
+        ch18: flags & FLAG_CH18 != 0,
+        frame_lost: flags & FLAG_FRAME_LOST != 0,
+        failsafe: flags & FLAG_FAILSAFE != 0,
+    })
+}
+
+/// 逐字节解析，遇到错位的帧按帧头重新同步
+#[derive(Debug, Clone, Copy)]
+pub struct SbusParser {
+    buf: [u8; FRAME_SIZE],
+    len: usize,
+}
+
+impl SbusParser {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; FRAME_SIZE],
+            len: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.len = 0;
+    }
+
+    /// 输入一个字节，收齐一帧返回解码结果
+    pub fn parse(&mut self, b: u8) -> Option<SbusFrame> {
+        if self.len == 0 && b != HEADER {
+            return None;
+        }
+        self.buf[self.len] = b;
+        self.len += 1;
+        if self.len < FRAME_SIZE {
+            return None;
+        }
+        match decode(&self.buf) {
+            Some(frame) => {
+                self.len = 0;
+                Some(frame)
+            }
+            None => {
+                self.resync();
+                None
+            }
+        }
+    }
+
+    /// 解析一段数据，每收齐一帧回调一次
+    pub fn parse_slice<F: FnMut(SbusFrame)>(&mut self, data: &[u8], mut f: F) {
+        data.iter().for_each(|b| {
+            if let Some(frame) = self.parse(*b) {
+                f(frame)
+            }
+        });
+    }
+
+    //从缓冲区里找下一个帧头，丢弃之前的数据
+    fn resync(&mut self) {
+        match self.buf[1..self.len].iter().position(|b| *b == HEADER) {
+            Some(pos) => {
+                let start = pos + 1;
+                self.buf.copy_within(start..self.len, 0);
+                self.len -= start;
+            }
+            None => self.len = 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //按协议打包16个11位通道
+    fn pack(channels: &[u16; CHANNELS], flags: u8) -> [u8; FRAME_SIZE] {
+        let mut frame = [0u8; FRAME_SIZE];
+        frame[0] = HEADER;
+        let mut bits = 0u32;
+        let mut nbits = 0;
+        let mut i = 1;
+        for ch in channels {
+            bits |= (*ch as u32 & 0x07FF) << nbits;
+            nbits += 11;
+            while nbits >= 8 {
+                frame[i] = bits as u8;
+                bits >>= 8;
+                nbits -= 8;
+                i += 1;
+            }
+        }
+        frame[23] = flags;
+        frame[24] = FOOTER;
+        frame
+    }
+
+    #[test]
+    fn decode_known_frame() {
+        //通道1为2047，通道2为1，其余为0
+        let mut frame = [0u8; FRAME_SIZE];
+        frame[0] = 0x0F;
+        frame[1] = 0xFF;
+        frame[2] = 0x0F;
+        frame[23] = FLAG_CH17 | FLAG_FAILSAFE;
+        let f = decode(&frame).unwrap();
+        assert_eq!(f.channels[0], 2047);
+        assert_eq!(f.channels[1], 1);
+        assert!(f.channels[2..].iter().all(|c| *c == 0));
+        assert!(f.ch17 && !f.ch18 && !f.frame_lost && f.failsafe);
+    }
+
+    #[test]
+    fn decode_roundtrip() {
+        let mut channels = [0u16; CHANNELS];
+        for (i, ch) in channels.iter_mut().enumerate() {
+            *ch = 172 + i as u16 * 100;
+        }
+        let f = decode(&pack(&channels, FLAG_CH18 | FLAG_FRAME_LOST)).unwrap();
+        assert_eq!(f.channels, channels);
+        assert!(!f.ch17 && f.ch18 && f.frame_lost && !f.failsafe);
+        let rc = f.to_rc();
+        assert_eq!(rc.count, 18);
+        assert_eq!(rc.channels[0], 988);
+        assert_eq!(rc.channels[16], 1000);
+        assert_eq!(rc.channels[17], 2000);
+        assert!(rc.frame_lost && !rc.failsafe);
+    }
+
+    #[test]
+    fn raw_to_us_endpoints() {
+        assert_eq!(raw_to_us(172), 988);
+        assert_eq!(raw_to_us(992), 1500);
+        assert_eq!(raw_to_us(1811), 2011);
+    }
+
+    #[test]
+    fn decode_rejects_bad_frame() {
+        let mut frame = pack(&[992; CHANNELS], 0);
+        assert!(decode(&frame[..24]).is_none());
+        frame[24] = 0x04;
+        assert!(decode(&frame).is_none());
+        frame[24] = FOOTER;
+        frame[0] = 0x00;
+        assert!(decode(&frame).is_none());
+    }
+
+    #[test]
+    fn parser_resync_after_garbage() {
+        let frame = pack(&[992; CHANNELS], FLAG_FAILSAFE);
+        let mut parser = SbusParser::new();
+        let mut frames = alloc::vec::Vec::new();
+        //垃圾数据里混有一个假帧头，收满25字节后校验失败，要从缓冲区里找到真帧头
+        let garbage = [0x12, 0x0F, 0x55, 0xAA, 0x00, 0x33];
+        parser.parse_slice(&garbage, |f| frames.push(f));
+        parser.parse_slice(&frame, |f| frames.push(f));
+        parser.parse_slice(&frame, |f| frames.push(f));
+        assert_eq!(frames.len(), 2);
+        assert!(frames
+            .iter()
+            .all(|f| f.channels == [992; CHANNELS] && f.failsafe));
+    }
+}
@@ -1,40 +1,101 @@
 //! 舵机驱动
+//!
+//! 输入-1.0~1.0的归一化指令，按行程端点、中位微调和反向映射成脉宽，可选限制转动速率。
+//! 输出只要求能按微秒设置脉宽，stm32f4的TIM2通道和gd32vf103的TIMER3通道都可以使用。
 
-use crate::mbus;
-use crate::message::*;
-use embedded_hal::PwmPin;
+use crate::driver::pwm::PulseWidth;
 
-pub struct Servo<PWM> {
-    pwm: PWM,
+/// 舵机参数，脉宽单位us
+#[derive(Debug, Clone, Copy)]
+pub struct ServoConfig {
+    pub min_us: f32,           //最小端点
+    pub center_us: f32,        //中位
+    pub max_us: f32,           //最大端点
+    pub trim_us: f32,          //中位微调
+    pub reversed: bool,        //反向
+    pub max_rate: Option<f32>, //最大转动速率，单位每秒指令变化量，满行程为2.0
 }
 
-impl<PWM: PwmPin<Duty = u16>> Servo<PWM> {
-    pub fn new(mut pwm: PWM) -> Self {
-        pwm.enable();
-        Self { pwm }
+impl ServoConfig {
+    pub const fn new() -> Self {
+        Self {
+            min_us: 1000.0,
+            center_us: 1500.0,
+            max_us: 2000.0,
+            trim_us: 0.0,
+            reversed: false,
+            max_rate: None,
+        }
+    }
+
+    /// 指令转脉宽，中位两侧分别按到端点的距离缩放，结果不超出端点
+    pub fn pulse_us(&self, command: f32) -> f32 {
+        let c = command.max(-1.0).min(1.0);
+        let c = if self.reversed { -c } else { c };
+        let center = self.center_us + self.trim_us;
+        let us = if c >= 0.0 {
+            center + c * (self.max_us - center)
+        } else {
+            center + c * (center - self.min_us)
+        };
+        us.max(self.min_us).min(self.max_us)
+    }
+}
+
+impl Default for ServoConfig {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-impl<PWM: PwmPin<Duty = u16>> Servo<PWM> {
-    /// 最小占空比
-    pub fn lowest_duty(&mut self) {
-        self.pwm.set_duty(0);
+pub struct Servo<OUT> {
+    output: OUT,
+    config: ServoConfig,
+    command: f32, //经过速率限制后的当前指令
+}
+
+impl<OUT: PulseWidth> Servo<OUT> {
+    /// 创建后回到中位
+    pub fn new(output: OUT, config: ServoConfig) -> Self {
+        let mut servo = Self {
+            output,
+            config,
+            command: 0.0,
+        };
+        servo.output.set_pulse_us(config.pulse_us(0.0));
+        servo
+    }
+
+    pub fn config(&self) -> &ServoConfig {
+        &self.config
+    }
+
+    pub fn set_config(&mut self, config: ServoConfig) {
+        self.config = config;
+        self.output.set_pulse_us(config.pulse_us(self.command));
     }
 
-    /// 半占空比
-    pub fn half_duty(&mut self) {
-        self.pwm.set_duty(self.pwm.get_max_duty() / 2);
+    /// 当前指令
+    pub fn command(&self) -> f32 {
+        self.command
     }
 
-    /// 全占空比
-    pub fn full_duty(&mut self) {
-        self.pwm.set_duty(self.pwm.get_max_duty());
+    /// 设置指令，-1.0~1.0，dt为距上次调用的时间，单位秒，用于速率限制
+    pub fn set(&mut self, command: f32, dt: f32) {
+        let target = command.max(-1.0).min(1.0);
+        self.command = match self.config.max_rate {
+            Some(rate) if rate > 0.0 => {
+                let step = rate * dt.max(0.0);
+                self.command + (target - self.command).max(-step).min(step)
+            }
+            _ => target,
+        };
+        self.output.set_pulse_us(self.config.pulse_us(self.command));
     }
 
-    /// 给油，duty范围0.1-0.5
-    pub fn set_duty(&mut self, duty: f32) {
-        self.pwm
-            .set_duty((self.pwm.get_max_duty() as f32 * duty) as u16);
-        mbus::bus().call("/led/g/toggle", Message::None);
+    /// 回中
+    pub fn center(&mut self) {
+        self.command = 0.0;
+        self.output.set_pulse_us(self.config.pulse_us(0.0));
     }
 }
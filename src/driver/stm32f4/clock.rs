@@ -0,0 +1,30 @@
+//! 系统时钟，用DWT周期计数器计时，不受任务调度延迟影响。
+//! 32位计数器在180MHz时约24秒溢出一次，读取时累加到64位，两次读取的间隔不能超过一次溢出，
+//! 失控保护等周期任务每几十毫秒读一次。
+//!
+use xtask::arch::cortex_m::peripheral::{DCB, DWT};
+use xtask::chip::CPU_CLOCK_HZ;
+
+static mut LAST: u32 = 0;
+static mut HIGH: u64 = 0;
+
+/// 打开周期计数器
+pub(crate) unsafe fn init() {
+    //DEMCR.TRCENA
+    (*DCB::PTR).demcr.modify(|r| r | (1 << 24));
+    (*DWT::PTR).cyccnt.write(0);
+    //DWT_CTRL.CYCCNTENA
+    (*DWT::PTR).ctrl.modify(|r| r | 1);
+}
+
+/// 上电以来的微秒数
+pub fn now_us() -> u64 {
+    xtask::sync::free(|_| unsafe {
+        let count = DWT::cycle_count();
+        if count < LAST {
+            HIGH += 1 << 32;
+        }
+        LAST = count;
+        (HIGH + count as u64) / (CPU_CLOCK_HZ as u64 / 1_000_000)
+    })
+}
@@ -0,0 +1,107 @@
+//! CRSF接收机，USART3全双工420000bps，RX走DMA1 Stream1 + 空闲中断，TX阻塞发送遥测
+//!
+use crate::driver::crsf::{CrsfPacket, CrsfParser};
+use crate::mbus;
+use crate::message::{Message, Telem};
+
+use super::nvic::NVICExt;
+use core::cell::RefCell;
+use cortex_m::interrupt::Mutex;
+use embedded_hal::serial::Write;
+use xtask::arch::cortex_m;
+use xtask::bsp::greenpill::hal::pac::{DMA1, USART3};
+use xtask::bsp::greenpill::hal::{
+    pac,
+    pac::interrupt,
+    serial::{Rx, Tx},
+};
+use xtask::{
+    arch::cortex_m::singleton,
+    bsp::greenpill::hal::dma::{
+        config::DmaConfig, PeripheralToMemory, Stream1, StreamsTuple, Transfer,
+    },
+};
+
+const DMA_BUFFER_SIZE: usize = 256;
+
+static mut DMA: Mutex<RefCell<Option<RxDma>>> = Mutex::new(RefCell::new(None));
+static mut TX: Option<Tx<USART3, u8>> = None;
+
+type RxDma =
+    Transfer<Stream1<DMA1>, 4, Rx<USART3>, PeripheralToMemory, &'static mut [u8; DMA_BUFFER_SIZE]>;
+
+trait USART3Ext {
+    fn clear_idle_interrupt();
+}
+
+impl USART3Ext for USART3 {
+    fn clear_idle_interrupt() {
+        unsafe {
+            let _ = (*Self::ptr()).sr.read();
+            let _ = (*Self::ptr()).dr.read();
+        }
+    }
+}
+
+pub unsafe fn init(mut rx: Rx<USART3, u8>, tx: Tx<USART3, u8>, dma: DMA1) {
+    rx.listen_idle();
+    let stream1 = StreamsTuple::new(dma).1;
+    let buf = singleton!(: [u8; DMA_BUFFER_SIZE] = [0; DMA_BUFFER_SIZE]).unwrap();
+    let mut dma = Transfer::init_peripheral_to_memory(
+        stream1,
+        rx,
+        buf,
+        None,
+        DmaConfig::default()
+            .memory_increment(true)
+            .fifo_enable(true),
+    );
+    dma.start(|_rx| {});
+    TX.replace(tx);
+    cortex_m::interrupt::free(|cs| *DMA.borrow(cs).borrow_mut() = Some(dma));
+    cortex_m::peripheral::NVIC::priority(pac::Interrupt::USART3, 0x01);
+    cortex_m::peripheral::NVIC::unmask(pac::Interrupt::USART3);
+    mbus::bus().register("/crsf/tx", |_, msg| match msg {
+        Message::Telem(Telem::Raw(b)) => {
+            if let Some(tx) = TX.as_mut() {
+                b.iter().try_for_each(|c| nb::block!(tx.write(*c))).ok();
+            }
+        }
+        _ => {}
+    });
+    log::info!("Initialize crsf ok")
+}
+
+#[interrupt]
+unsafe fn USART3() {
+    USART3::clear_idle_interrupt();
+    read_dma();
+}
+
+unsafe fn read_dma() {
+    static mut TRANSFER: Option<RxDma> = None;
+    let transfer = TRANSFER.get_or_insert_with(|| {
+        cortex_m::interrupt::free(|cs| DMA.borrow(cs).replace(None).unwrap())
+    });
+    static mut BUF: [u8; DMA_BUFFER_SIZE] = [0; DMA_BUFFER_SIZE];
+    static mut PARSER: CrsfParser = CrsfParser::new();
+    let remaining = transfer.number_of_transfers() as usize;
+    match transfer.next_transfer(&mut BUF) {
+        Ok((buf, _)) => {
+            let len = DMA_BUFFER_SIZE.saturating_sub(remaining);
+            PARSER.parse_slice(&buf[..len], |packet| {
+                xtask::sync::free(|_| match packet {
+                    CrsfPacket::RcChannels(rc) => {
+                        mbus::bus().publish_isr("/rc/raw", Message::RcChannels(rc));
+                    }
+                    CrsfPacket::LinkStatistics(stats) => {
+                        mbus::bus().publish_isr("/rc/link", Message::LinkStatistics(stats));
+                    }
+                })
+            });
+        }
+        Err(err) => {
+            log::error!("read_dma1 {:?}", err);
+        }
+    }
+}
@@ -0,0 +1,253 @@
+//! DShot输出，TIM5通道1(PA0)PWM，DMA1 Stream2通道6在每次比较匹配时写入下一位的CCR1
+//!
+//! DMA1的Stream1已被接收机串口占用，这里直接操作寄存器使用Stream2，不经过HAL的StreamsTuple。
+//!
+//! 双向DShot时输出反相(空闲高电平)，一帧发完后在DMA传输完成中断里把通道1切换成双边沿输入捕获，
+//! 同一个DMA流把跳变时间戳搬到CAPTURE，下一次发送前解码回传的转速并发布到/esc/rpm。
+//!
+use crate::driver::bldc::EscOutput;
+use crate::driver::dshot::{self, DshotCommand, DshotConfig, DMA_BUFFER_SIZE};
+use crate::driver::MotorRpm;
+use crate::mbus;
+use crate::message::Message;
+use core::sync::atomic::{AtomicU8, Ordering};
+use xtask::arch::cortex_m::peripheral::NVIC;
+use xtask::bsp::greenpill::hal::{
+    gpio::{Alternate, Pin, PushPull},
+    pac,
+    pac::{interrupt, Interrupt, TIM5},
+    rcc::Clocks,
+};
+
+use super::nvic::NVICExt;
+
+/// TIM5_CH1的DMA通道
+const DMA_CHANNEL: u32 = 6;
+/// CCR1相对TIM5基地址的偏移
+const CCR1_OFFSET: u32 = 0x34;
+/// 回传最多21位，跳变不会超过这么多
+const CAPTURE_SIZE: usize = 32;
+
+/// 空闲
+const PHASE_IDLE: u8 = 0;
+/// 正在发送
+const PHASE_SENDING: u8 = 1;
+/// 正在捕获回传
+const PHASE_CAPTURING: u8 = 2;
+
+static mut BUF: [u32; DMA_BUFFER_SIZE] = [0; DMA_BUFFER_SIZE];
+static mut CAPTURE: [u32; CAPTURE_SIZE] = [0; CAPTURE_SIZE];
+static PHASE: AtomicU8 = AtomicU8::new(PHASE_IDLE);
+static mut PIN: Option<Pin<'A', 0, Alternate<2, PushPull>>> = None;
+
+pub struct Dshot {
+    _tim: TIM5,
+    config: DshotConfig,
+    index: u8,                           //电机序号
+    period: u32,                         //一位的计数周期
+    pending: Option<(DshotCommand, u8)>, //待发送的命令和剩余次数
+}
+
+pub unsafe fn init(
+    tim: TIM5,
+    pin: Pin<'A', 0, Alternate<2, PushPull>>,
+    index: u8,
+    config: DshotConfig,
+    clocks: &Clocks,
+) -> Dshot {
+    let rcc = &*pac::RCC::ptr();
+    rcc.apb1enr.modify(|_, w| w.tim5en().set_bit());
+    rcc.ahb1enr.modify(|_, w| w.dma1en().set_bit());
+    let period = clocks.timclk1().raw() / config.speed.bitrate();
+    tim.cr1.reset();
+    tim.psc.write(|w| w.bits(0));
+    tim.ccr1.write(|w| w.bits(0));
+    //比较匹配或捕获时请求DMA(CC1DE)
+    tim.dier.write(|w| w.bits(1 << 9));
+    if config.bidir {
+        //回传时电调释放总线，靠上拉保持空闲高电平
+        (*pac::GPIOA::ptr())
+            .pupdr
+            .modify(|r, w| w.bits((r.bits() & !0b11) | 0b01));
+        NVIC::priority(Interrupt::DMA1_STREAM2, 0x01);
+        NVIC::unmask(Interrupt::DMA1_STREAM2);
+    }
+    PIN.replace(pin);
+    let esc = Dshot {
+        _tim: tim,
+        config,
+        index,
+        period,
+        pending: None,
+    };
+    esc.output_mode();
+    log::info!("Initialize dshot {:?} ok", config);
+    esc
+}
+
+impl Dshot {
+    //通道1配置为PWM输出，双向时反相
+    fn output_mode(&self) {
+        unsafe {
+            let tim = &*pac::TIM5::ptr();
+            tim.cr1.write(|w| w.bits(0));
+            tim.ccer.write(|w| w.bits(0));
+            //PWM模式1(OC1M=110)，CCR1预装载(OC1PE)
+            tim.ccmr1_output().write(|w| w.bits(0x68));
+            tim.arr.write(|w| w.bits(self.period - 1));
+            tim.ccr1.write(|w| w.bits(0));
+            tim.cnt.write(|w| w.bits(0));
+            tim.egr.write(|w| w.bits(0x01));
+            tim.ccer
+                .write(|w| w.bits(if self.config.bidir { 0x03 } else { 0x01 }));
+            //ARR预装载，启动计数
+            tim.cr1.write(|w| w.bits(0x81));
+        }
+    }
+
+    //停止捕获，解码回传的转速
+    fn read_telemetry(&mut self) {
+        unsafe {
+            let st = &(*pac::DMA1::ptr()).st[2];
+            st.cr.modify(|r, w| w.bits(r.bits() & !0x01));
+            while st.cr.read().bits() & 0x01 != 0 {}
+            let n = CAPTURE_SIZE - st.ndtr.read().bits() as usize;
+            //切换时残留的DMA请求会搬来一个0，计数器清零后真正的跳变不会在0时刻
+            let edges = &CAPTURE[..n];
+            let start = edges.iter().position(|&t| t != 0).unwrap_or(n);
+            //回传速率是发送的5/4
+            let erpm = dshot::edges_to_levels(&edges[start..], self.period * 4 / 5)
+                .and_then(dshot::decode_telemetry);
+            if let Some(erpm) = erpm {
+                mbus::bus().publish(
+                    "/esc/rpm",
+                    Message::MotorRpm(MotorRpm {
+                        index: self.index,
+                        rpm: dshot::erpm_to_rpm(erpm, self.config.poles),
+                    }),
+                );
+            }
+        }
+    }
+
+    fn transmit(&mut self, frame: u16) {
+        match PHASE.load(Ordering::Acquire) {
+            //上一帧还没发完就丢弃这一帧
+            PHASE_SENDING => return,
+            PHASE_CAPTURING => {
+                self.read_telemetry();
+                self.output_mode();
+            }
+            _ => {}
+        }
+        unsafe {
+            dshot::encode(frame, self.period, &mut BUF);
+            PHASE.store(PHASE_SENDING, Ordering::Release);
+            //内存到外设，双向时传输完成中断里切换到捕获
+            start_dma(
+                BUF.as_ptr() as u32,
+                DMA_BUFFER_SIZE,
+                (0b01 << 6) | if self.config.bidir { 1 << 4 } else { 0 },
+            );
+        }
+        if !self.config.bidir {
+            PHASE.store(PHASE_IDLE, Ordering::Release);
+        }
+    }
+
+    fn next_command(&mut self) -> Option<u16> {
+        let (cmd, n) = self.pending.take()?;
+        if n > 1 {
+            self.pending = Some((cmd, n - 1));
+        }
+        Some(if self.config.bidir {
+            cmd.frame_bidir()
+        } else {
+            cmd.frame()
+        })
+    }
+
+    fn frame(&self, value: u16) -> u16 {
+        if self.config.bidir {
+            dshot::frame_bidir(value, false)
+        } else {
+            dshot::frame(value, false)
+        }
+    }
+}
+
+//配置并启动DMA1 Stream2，外设地址固定为TIM5的CCR1，flags为传输方向和中断使能
+unsafe fn start_dma(mem: u32, len: usize, flags: u32) {
+    let dma = &*pac::DMA1::ptr();
+    let st = &dma.st[2];
+    //单向时上一帧的DMA可能还没结束
+    if st.cr.read().bits() & 0x01 != 0 {
+        return;
+    }
+    //清除Stream2的所有标志
+    dma.lifcr.write(|w| w.bits(0x3d << 16));
+    st.par
+        .write(|w| w.bits(pac::TIM5::ptr() as u32 + CCR1_OFFSET));
+    st.m0ar.write(|w| w.bits(mem));
+    st.ndtr.write(|w| w.bits(len as u32));
+    //通道6，高优先级，32位传输，内存地址递增，使能
+    st.cr.write(|w| {
+        w.bits(
+            (DMA_CHANNEL << 25)
+                | (0b10 << 16)
+                | (0b10 << 13)
+                | (0b10 << 11)
+                | (1 << 10)
+                | flags
+                | 0x01,
+        )
+    });
+}
+
+//一帧发完，切换到双边沿输入捕获，计数器自由运行
+#[interrupt]
+unsafe fn DMA1_STREAM2() {
+    let dma = &*pac::DMA1::ptr();
+    dma.lifcr.write(|w| w.bits(0x3d << 16));
+    if PHASE.load(Ordering::Acquire) != PHASE_SENDING {
+        return;
+    }
+    let tim = &*pac::TIM5::ptr();
+    tim.ccer.write(|w| w.bits(0));
+    //通道1映射到TI1(CC1S=01)
+    tim.ccmr1_input().write(|w| w.bits(0x01));
+    tim.arr.write(|w| w.bits(u32::MAX));
+    //更新事件装载ARR并清零计数器
+    tim.egr.write(|w| w.bits(0x01));
+    //CC1P和CC1NP都置位为双边沿捕获
+    tim.ccer.write(|w| w.bits(0x0b));
+    //外设到内存
+    start_dma(CAPTURE.as_ptr() as u32, CAPTURE_SIZE, 0);
+    PHASE.store(PHASE_CAPTURING, Ordering::Release);
+}
+
+impl EscOutput for Dshot {
+    fn enable(&mut self) {}
+
+    /// DShot没有关断输出的说法，锁定时持续发送停转
+    fn disable(&mut self) {
+        self.transmit(self.frame(dshot::MOTOR_STOP));
+    }
+
+    /// 有待发送的命令时先发命令
+    fn set_throttle(&mut self, throttle: f32) {
+        let frame = self
+            .next_command()
+            .unwrap_or_else(|| self.frame(dshot::throttle_to_value(throttle)));
+        self.transmit(frame);
+    }
+
+    fn command(&mut self, cmd: DshotCommand) -> bool {
+        if !cmd.supported() {
+            log::warn!("dshot command {:?} not supported", cmd);
+            return false;
+        }
+        self.pending = Some((cmd, cmd.repeat()));
+        true
+    }
+}
@@ -1,7 +1,6 @@
-use crate::driver::ImuData;
+use crate::driver::{ImuData, IMU_SAMPLE_HZ};
 use crate::filter::dither::DitherFilter;
 use crate::mbus;
-use ahrs::{Ahrs, Madgwick};
 use icm20689::{Builder, SpiInterface, ICM20689};
 use nalgebra::Vector3;
 use shared_bus::{NullMutex, SpiProxy};
@@ -93,8 +92,8 @@ pub(crate) unsafe fn init(
     use xtask::Delay;
 
     log::info!("Initialize Icm20602");
-    let sample_rate = 100;
-    MADGWICK.replace(Madgwick::new(1.0 / sample_rate as f32, 0.1));
+    //和其他IMU一样按IMU_SAMPLE_HZ读取，下游的滤波和控制周期都按这个频率计算
+    let sample_rate = IMU_SAMPLE_HZ;
     let mut mpu = Builder::new_spi(spi, ncs);
     let mut delay = Delay::new();
     if let Err(err) = mpu.setup(&mut delay) {
@@ -102,7 +101,7 @@ pub(crate) unsafe fn init(
     } else {
         MPU.replace(mpu);
         let mut timer = Timer1::new(tim, clocks).counter_hz();
-        timer.start((sample_rate as u32).Hz()).ok();
+        timer.start(sample_rate.Hz()).ok();
         timer.listen(Event::Update);
         TIMER.replace(timer);
         NVIC::unmask(Interrupt::TIM1_UP_TIM10);
@@ -123,7 +122,7 @@ unsafe fn timer_isr() {
                         let acc = Vector3::new(acc[0], acc[1], acc[2]);
                         let gyro = Vector3::new(gyro[0], gyro[1], gyro[2]);
                         let data = ImuData::default().gyro(gyro).accel(acc);
-                        mbus::mbus().publish_isr("/imu", crate::message::Message::ImuData(data));
+                        mbus::bus().publish_isr("/imu/raw", crate::message::Message::ImuData(data));
                     }
                     Err(err) => {
                         log::error!("Icm20602 error {:?}", err);
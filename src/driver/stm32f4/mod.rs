@@ -1,3 +1,12 @@
+// DShot只接了PA0一路电调，多旋翼需要四路，只能用PWM类协议
+#[cfg(all(feature = "multi-rotor", not(feature = "helix"), feature = "dshot"))]
+compile_error!("multi-rotor needs 4 motors but dshot only drives PA0 on stm32f4, disable dshot");
+
+pub mod clock;
+#[cfg(feature = "crsf")]
+pub mod crsf;
+#[cfg(feature = "dshot")]
+pub mod dshot;
 #[cfg(feature = "icm20602")]
 pub mod icm20602;
 pub mod led;
@@ -6,9 +15,19 @@ pub mod mpu6050;
 #[cfg(feature = "mpu9250")]
 pub mod mpu9250;
 pub mod nvic;
+#[cfg(feature = "ppm")]
+pub mod ppm;
+#[cfg(feature = "sbus")]
 pub mod sbus;
 pub mod telem;
 
+use crate::driver::bldc::{EscOutput, Motor};
+use crate::driver::pwm::{PulseOutput, PwmProtocol, TimerConfig};
+use crate::driver::servo::{Servo, ServoConfig};
+use crate::driver::{set_outputs, Outputs};
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
 use shared_bus::{BusManager, BusManagerSimple, NullMutex};
 use xtask::bsp::greenpill::hal::{
     flash::FlashExt,
@@ -83,6 +102,7 @@ pub unsafe fn init() {
     log::info!("CPU_CLOCK {}Hz", CPU_CLOCK_HZ);
     log::info!("SYSTICK_CLOCK {}Hz", SYSTICK_CLOCK_HZ);
     log::info!("OSTICK_CLOCK {}Hz", TICK_CLOCK_HZ);
+    clock::init();
 
     if let Some(dp) = pac::Peripherals::take() {
         let rcc = dp.RCC.constrain();
@@ -109,6 +129,7 @@ pub unsafe fn init() {
             }
         }
 
+        #[cfg(feature = "sbus")]
         match dp.USART3.rx(
             gpiod.pd9.into_alternate(),
             Config::default().baudrate(100000.bps()).dma(DC::Rx),
@@ -121,6 +142,22 @@ pub unsafe fn init() {
                 panic!("{:?}", err);
             }
         }
+        #[cfg(feature = "crsf")]
+        match dp.USART3.serial(
+            (gpiod.pd8.into_alternate(), gpiod.pd9.into_alternate()),
+            Config::default().baudrate(420_000.bps()).dma(DC::Rx),
+            &clocks,
+        ) {
+            Ok(serial) => {
+                let (tx, rx) = serial.split();
+                crsf::init(rx, tx, dp.DMA1);
+            }
+            Err(err) => {
+                panic!("{:?}", err);
+            }
+        }
+        #[cfg(feature = "ppm")]
+        ppm::init(dp.TIM4, gpiod.pd12.into_alternate(), &clocks);
 
         log::info!(
             "Flash Address:0x{:x},Length={}, DualBank:{}",
@@ -132,23 +169,106 @@ pub unsafe fn init() {
         led::init(gpioc.pc13);
         #[cfg(feature = "stm32f427vit6")]
         led::init(gpioc.pc6, gpioc.pc7, gpioa.pa8);
-        let channels = (
+        //舵机和电调共用TIM2，按输出模式设置周期和分辨率，定时器组约束不满足时不能继续
+        let output = crate::driver::pwm::config();
+        #[cfg(feature = "dshot")]
+        let output = crate::driver::pwm::OutputConfig {
+            esc: None,
+            ..output
+        };
+        let protocol = match output.timer_group() {
+            Ok(p) => p.unwrap_or(PwmProtocol::Standard { rate_hz: 50 }),
+            Err(err) => panic!("{:?}", err),
+        };
+        let timer = match protocol.timer_config(clocks.timclk1().raw()) {
+            Ok(timer) => timer,
+            Err(err) => panic!("{:?}", err),
+        };
+        log::info!("TIM2 {:?} {:?}", protocol, timer);
+        #[cfg(not(feature = "dshot"))]
+        let (ch1, ch2, ch3, ch4) = dp
+            .TIM2
+            .pwm_hz(
+                (
+                    gpioa.pa0.into_alternate(),
+                    gpioa.pa1.into_alternate(),
+                    gpioa.pa2.into_alternate(),
+                    gpioa.pa3.into_alternate(),
+                ),
+                protocol.rate_hz().Hz(),
+                &clocks,
+            )
+            .split();
+        //DShot的位速率和舵机不同，PA0改由TIM5输出
+        #[cfg(feature = "dshot")]
+        let (ch2, ch3, ch4) = dp
+            .TIM2
+            .pwm_hz(
+                (
+                    gpioa.pa1.into_alternate(),
+                    gpioa.pa2.into_alternate(),
+                    gpioa.pa3.into_alternate(),
+                ),
+                protocol.rate_hz().Hz(),
+                &clocks,
+            )
+            .split();
+        tim2_config(timer);
+        #[cfg(not(feature = "dshot"))]
+        let ch1 = PulseOutput::new(ch1, protocol);
+        //直升机只有一个主电机，双向DShot回传的转速按0号电机发布到/esc/rpm
+        #[cfg(feature = "dshot")]
+        let ch1 = dshot::init(
+            dp.TIM5,
             gpioa.pa0.into_alternate(),
-            gpioa.pa1.into_alternate(),
-            gpioa.pa2.into_alternate(),
-            gpioa.pa3.into_alternate(),
+            0,
+            crate::driver::dshot::DshotConfig::new(),
+            &clocks,
+        );
+
+        let ch1: Box<dyn EscOutput> = Box::new(ch1);
+        let (ch2, ch3, ch4) = (
+            PulseOutput::new(ch2, protocol),
+            PulseOutput::new(ch3, protocol),
+            PulseOutput::new(ch4, protocol),
         );
-        let (ch1, ch2, ch3, ch4) = dp.TIM2.pwm_hz(channels, 128.kHz(), &clocks).split();
-
-        log::info!("Initialize motor");
-        //主旋翼
-        let motor = super::bldc::Motor::new(ch1);
-        log::info!("Initialize servos");
-        //斜盘舵机
-        let servo1 = super::servo::Servo::new(ch2);
-        let servo2 = super::servo::Servo::new(ch3);
-        let servo3 = super::servo::Servo::new(ch4);
+        //多旋翼四个输出口都接电调，DShot和多旋翼不能同时启用
+        #[cfg(all(
+            feature = "multi-rotor",
+            not(feature = "helix"),
+            not(feature = "dshot")
+        ))]
+        let outputs = {
+            log::info!("Initialize motors");
+            Outputs {
+                motors: vec![
+                    Motor::new(ch1),
+                    Motor::new(Box::new(ch2)),
+                    Motor::new(Box::new(ch3)),
+                    Motor::new(Box::new(ch4)),
+                ],
+                servos: Vec::new(),
+            }
+        };
+        //直升机PA0接主旋翼电调，PA1-PA3接斜盘舵机；固定翼PA0接电调，PA1-PA3接舵机
+        #[cfg(not(all(
+            feature = "multi-rotor",
+            not(feature = "helix"),
+            not(feature = "dshot")
+        )))]
+        let outputs = {
+            log::info!("Initialize motor and servos");
+            Outputs {
+                motors: vec![Motor::new(ch1)],
+                servos: vec![
+                    Servo::new(Box::new(ch2), ServoConfig::new()),
+                    Servo::new(Box::new(ch3), ServoConfig::new()),
+                    Servo::new(Box::new(ch4), ServoConfig::new()),
+                ],
+            }
+        };
         //todo 锁尾舵机/尾旋翼
+        set_outputs(outputs);
         #[cfg(feature = "stm32f401ccu6")]
         {
             let scl = gpiob
@@ -227,3 +347,12 @@ pub unsafe fn init() {
         }
     }
 }
+
+//覆盖HAL按频率算出的分频，得到输出模式要求的计数分辨率
+unsafe fn tim2_config(timer: TimerConfig) {
+    let tim = &*pac::TIM2::ptr();
+    tim.psc.write(|w| w.bits(timer.psc as u32));
+    tim.arr.write(|w| w.bits(timer.arr as u32));
+    //更新事件立即装载
+    tim.egr.write(|w| w.bits(0x01));
+}
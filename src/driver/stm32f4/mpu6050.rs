@@ -1,8 +1,8 @@
 use crate::driver::mpu6050::*;
+use crate::driver::IMU_SAMPLE_HZ;
 use crate::filter::dither::DitherFilter;
 use crate::filter::Filter;
 use crate::mbus;
-use ahrs::{Ahrs, Madgwick};
 use shared_bus::{I2cProxy, NullMutex};
 #[cfg(feature = "stm32f401ccu6")]
 use xtask::bsp::greenpill::hal::pac::I2C1;
@@ -74,6 +74,7 @@ pub(crate) unsafe fn init(
     clocks: &Clocks,
 ) {
     log::info!("Initialize mpu6050");
+    let sample_rate = IMU_SAMPLE_HZ as u16;
     match Mpu6050::new(i2c).with_sample_rate(sample_rate).build() {
         Ok(mpu) => {
             MPU.replace(mpu);
@@ -108,8 +109,7 @@ pub(crate) unsafe fn init(
     clocks: &Clocks,
 ) {
     log::info!("Initialize mpu6050");
-    let sample_rate = 1000;
-    MADGWICK.replace(Madgwick::new(1.0 / sample_rate as f32, 0.1));
+    let sample_rate = IMU_SAMPLE_HZ as u16;
     match Mpu6050::new(i2c).with_sample_rate(sample_rate).build() {
         Ok(mpu) => {
             MPU.replace(mpu);
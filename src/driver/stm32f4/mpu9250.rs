@@ -1,5 +1,5 @@
 use super::nvic::NVICExt;
-use crate::driver::{Accel, Compass, Gyro, ImuData};
+use crate::driver::{Accel, Compass, Gyro, ImuData, IMU_SAMPLE_HZ};
 use crate::mbus;
 use crate::message::Message;
 use core::cell::RefCell;
@@ -74,7 +74,8 @@ pub(crate) unsafe fn init(
     ncs: Pin<'A', 4, Output<PushPull>>,
     clocks: &Clocks,
 ) {
-    let sample_rate = 100;
+    //默认配置下陀螺仪和加速度计以1kHz输出，定时器按同样的频率读取
+    let sample_rate = IMU_SAMPLE_HZ;
     let mut delay = Delay::new();
     match Mpu9250::marg_default(spi, ncs, &mut delay) {
         Ok(mut mpu) => {
@@ -84,7 +85,7 @@ pub(crate) unsafe fn init(
             }
 
             let mut timer = Timer1::new(tim, clocks).counter_hz();
-            timer.start((sample_rate as u32).Hz()).ok();
+            timer.start(sample_rate.Hz()).ok();
             timer.listen(Event::Update);
 
             interrupt::free(|cs| *MPU.borrow(cs).borrow_mut() = Some(mpu));
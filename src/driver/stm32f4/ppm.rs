@@ -0,0 +1,66 @@
+//! PPM输入，TIM4通道1(PD12)上升沿输入捕获，定时器计数频率1MHz
+//!
+use super::nvic::NVICExt;
+use crate::driver::ppm::PpmDecoder;
+use crate::mbus;
+use crate::message::Message;
+use core::cell::RefCell;
+use xtask::arch::cortex_m::interrupt;
+use xtask::arch::cortex_m::interrupt::Mutex;
+use xtask::arch::cortex_m::peripheral::NVIC;
+use xtask::bsp::greenpill::hal::{
+    gpio::{Alternate, Pin, PushPull},
+    pac,
+    pac::{interrupt, Interrupt, TIM4},
+    rcc::Clocks,
+};
+
+static mut TIMER: Mutex<RefCell<Option<TIM4>>> = Mutex::new(RefCell::new(None));
+static mut PIN: Option<Pin<'D', 12, Alternate<2, PushPull>>> = None;
+
+pub unsafe fn init(tim: TIM4, pin: Pin<'D', 12, Alternate<2, PushPull>>, clocks: &Clocks) {
+    (*pac::RCC::ptr())
+        .apb1enr
+        .modify(|_, w| w.tim4en().set_bit());
+    //1MHz计数，计满65.5ms回绕
+    let psc = clocks.timclk1().raw() / 1_000_000 - 1;
+    tim.cr1.reset();
+    tim.psc.write(|w| w.psc().bits(psc as u16));
+    tim.arr.write(|w| w.bits(0xffff));
+    //通道1映射到TI1(CC1S=01)，输入滤波fCK_INT N=8(IC1F=0011)
+    tim.ccmr1_input().write(|w| w.bits(0x31));
+    //上升沿捕获
+    tim.ccer
+        .write(|w| w.cc1p().clear_bit().cc1np().clear_bit().cc1e().set_bit());
+    tim.dier.write(|w| w.cc1ie().set_bit());
+    tim.egr.write(|w| w.ug().set_bit());
+    tim.sr.write(|w| w.bits(0));
+    tim.cr1.modify(|_, w| w.cen().set_bit());
+
+    PIN.replace(pin);
+    interrupt::free(|cs| *TIMER.borrow(cs).borrow_mut() = Some(tim));
+    NVIC::priority(Interrupt::TIM4, 0x01);
+    NVIC::unmask(Interrupt::TIM4);
+    log::info!("Initialize ppm ok");
+}
+
+#[interrupt]
+unsafe fn TIM4() {
+    static mut TIM: Option<TIM4> = None;
+    static mut DECODER: PpmDecoder = PpmDecoder::new();
+    static mut LAST: u16 = 0;
+    let tim =
+        TIM.get_or_insert_with(|| interrupt::free(|cs| TIMER.borrow(cs).replace(None).unwrap()));
+    if tim.sr.read().cc1if().bit_is_clear() {
+        return;
+    }
+    //读CCR1同时清除捕获标志
+    let now = tim.ccr1.read().bits() as u16;
+    let width = now.wrapping_sub(*LAST) as u32;
+    *LAST = now;
+    if let Some(rc) = DECODER.push(width) {
+        xtask::sync::free(|_| {
+            mbus::bus().publish_isr("/rc/raw", Message::RcChannels(rc));
+        })
+    }
+}
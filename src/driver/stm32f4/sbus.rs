@@ -1,8 +1,8 @@
+use crate::driver::sbus::SbusParser;
 use crate::mbus;
-use crate::message::{Message, Telem};
+use crate::message::Message;
 
 use super::nvic::NVICExt;
-use alloc::vec::Vec;
 use core::cell::RefCell;
 use cortex_m::interrupt::Mutex;
 use xtask::arch::cortex_m;
@@ -75,15 +75,17 @@ unsafe fn read_dma() {
         cortex_m::interrupt::free(|cs| DMA.borrow(cs).replace(None).unwrap())
     });
     static mut BUF: [u8; DMA_BUFFER_SIZE] = [0; DMA_BUFFER_SIZE];
-
+    static mut PARSER: SbusParser = SbusParser::new();
+    //空闲中断时DMA还剩多少字节没传，据此算出本次收到的长度
+    let remaining = transfer.number_of_transfers() as usize;
     match transfer.next_transfer(&mut BUF) {
         Ok((buf, _)) => {
-            let mut data = Vec::new();
-            data.extend_from_slice(buf);
-            //TODO SBUS decode
-            xtask::sync::free(|_| {
-                mbus::bus().publish_isr("/telem/sbus", Message::Telem(Telem::Raw(data)));
-            })
+            let len = DMA_BUFFER_SIZE.saturating_sub(remaining);
+            PARSER.parse_slice(&buf[..len], |frame| {
+                xtask::sync::free(|_| {
+                    mbus::bus().publish_isr("/rc/raw", Message::RcChannels(frame.to_rc()));
+                })
+            });
         }
         Err(err) => {
             log::error!("read_dma1 {:?}", err);
@@ -0,0 +1,290 @@
+//! 固定翼
+//!
+//! 手动模式摇杆直通舵面；阻尼模式在摇杆量上减去角速度反馈；
+//! 自稳模式横滚、俯仰摇杆对应限幅内的目标角度，松杆自动改平，偏航仍为阻尼。
+
+use crate::acs::mixer::fixed_wing::{self, WingConfig, WingType};
+use crate::acs::pid::{Limit, Pid};
+use crate::app::arming::ArmingState;
+use crate::app::failsafe::FailsafeStage;
+use crate::app::modes::FlightMode;
+use crate::driver::bldc::{EscOutput, Motor};
+use crate::driver::pwm::PulseWidth;
+use crate::driver::servo::Servo;
+use crate::driver::{self, ImuData, IMU_SAMPLE_HZ};
+use crate::mbus;
+use crate::message::*;
+use alloc::vec::Vec;
+use xtask::{Queue, TaskBuilder};
+
+static mut WING: WingConfig = WingConfig::new(WingType::Conventional);
+
+/// 修改舵面布局，需在start之前调用
+pub fn configure(wing: WingConfig) {
+    super::set_msp_mixer_mode(wing.wing_type.msp_mixer_mode());
+    unsafe {
+        WING = wing;
+    }
+}
+
+pub fn wing() -> WingConfig {
+    unsafe { WING }
+}
+
+pub fn start() {
+    let q = Queue::new();
+    let sender = q.clone();
+    let fs_sender = q.clone();
+    let arming_sender = q.clone();
+    let mode_sender = q.clone();
+    let imu_sender = q.clone();
+    TaskBuilder::new()
+        .name("fixed")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(move || sampling(q));
+    mbus::bus().subscribe("/rc", move |_, msg| {
+        if let Err(err) = sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/failsafe", move |_, msg| {
+        if let Err(err) = fs_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/arming", move |_, msg| {
+        if let Err(err) = arming_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/mode", move |_, msg| {
+        if let Err(err) = mode_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/imu", move |_, msg| {
+        if let Err(err) = imu_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+}
+
+fn sampling(recv: Queue<Message>) {
+    let mut rc = RemoteControl::default();
+    let mut mode = StabMode::Manual;
+    let mut lost = false;
+    //IMU数据没有时间戳，控制周期按采样频率计算
+    let dt = 1.0 / IMU_SAMPLE_HZ as f32;
+    log::info!("Fixed wing {:?}", wing().wing_type);
+    let outputs = driver::take_outputs();
+    let mut plane = match FixedWing::new(
+        outputs.motors.into_iter().next(),
+        outputs.servos,
+        wing(),
+        StabConfig::new(),
+    ) {
+        Some(plane) => plane,
+        //舵机不够时不输出，只取走消息，避免队列满了一直报错
+        None => loop {
+            recv.pop_front();
+        },
+    };
+    loop {
+        if let Some(msg) = recv.pop_front() {
+            match msg {
+                //上锁时电机停转，舵面仍然响应，便于起飞前检查
+                Message::ImuData(imu) => plane.update(&rc, &imu, dt),
+                Message::RemoteControl(cmd) => {
+                    //失联时只接受失控保护给出的替代指令
+                    if lost == cmd.failsafe {
+                        rc = cmd;
+                    }
+                }
+                Message::Arming(state) => {
+                    match state {
+                        ArmingState::Armed => plane.arm(),
+                        ArmingState::Disarmed => plane.disarm(),
+                    }
+                    log::info!("Fixed wing {:?}", state);
+                }
+                Message::FlightMode(m) => {
+                    mode = m.into();
+                    //失联期间保持自稳，恢复后按新模式飞行
+                    if !lost {
+                        plane.set_mode(mode);
+                    }
+                    log::info!("Fixed wing {:?}", mode);
+                }
+                Message::Failsafe(stage) => {
+                    lost = matches!(stage, FailsafeStage::Active(_));
+                    //失联时自稳改平
+                    plane.set_mode(if lost { StabMode::Angle } else { mode });
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 增稳模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StabMode {
+    /// 手动，摇杆直通舵面
+    Manual,
+    /// 角速度阻尼
+    Rate,
+    /// 角度限制自稳
+    Angle,
+}
+
+impl From<FlightMode> for StabMode {
+    fn from(mode: FlightMode) -> Self {
+        match mode {
+            FlightMode::Manual => StabMode::Manual,
+            FlightMode::Trick | FlightMode::Autotune => StabMode::Rate,
+            FlightMode::Hover | FlightMode::Auto | FlightMode::Following => StabMode::Angle,
+        }
+    }
+}
+
+/// 增稳参数，角度单位弧度，角速度单位弧度每秒
+#[derive(Debug, Clone, Copy)]
+pub struct StabConfig {
+    pub max_roll: f32,     //自稳最大横滚角
+    pub max_pitch: f32,    //自稳最大俯仰角
+    pub level_kp: f32,     //自稳角度误差到舵量的比例
+    pub level_ki: f32,     //自稳角度误差积分
+    pub damping: [f32; 3], //横滚、俯仰、偏航角速度阻尼
+}
+
+impl StabConfig {
+    pub const fn new() -> Self {
+        Self {
+            max_roll: 0.785398,  //45°
+            max_pitch: 0.523599, //30°
+            level_kp: 2.0,
+            level_ki: 0.0,
+            damping: [0.3, 0.3, 0.2],
+        }
+    }
+}
+
+impl Default for StabConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 固定翼，机体坐标系横滚右倾为正，俯仰抬头为正，偏航机头右转为正
+pub struct FixedWing<ESC, OUT> {
+    motor: Option<Motor<ESC>>, //油门，滑翔机没有
+    servos: Vec<Servo<OUT>>,   //舵机，按舵面布局的顺序排列，少于舵面数时多出的舵面不输出
+    wing: WingConfig,
+    stab: StabConfig,
+    mode: StabMode,
+    roll_ctl: Pid<f32>,  //横滚角控制器
+    pitch_ctl: Pid<f32>, //俯仰角控制器
+    flap: f32,           //襟翼，0.0~1.0
+}
+
+impl<ESC: EscOutput, OUT: PulseWidth> FixedWing<ESC, OUT> {
+    /// 舵机数少于舵面布局需要的数量时返回None，不能只驱动一侧舵面
+    pub fn new(
+        motor: Option<Motor<ESC>>,
+        servos: Vec<Servo<OUT>>,
+        wing: WingConfig,
+        stab: StabConfig,
+    ) -> Option<Self> {
+        if servos.len() < wing.servo_count() {
+            log::error!(
+                "{:?} needs {} servos, got {}",
+                wing.wing_type,
+                wing.servo_count(),
+                servos.len()
+            );
+            return None;
+        }
+        Some(Self {
+            motor,
+            servos,
+            wing,
+            stab,
+            mode: StabMode::Manual,
+            roll_ctl: level_pid(&stab),
+            pitch_ctl: level_pid(&stab),
+            flap: 0.0,
+        })
+    }
+
+    pub fn mode(&self) -> StabMode {
+        self.mode
+    }
+
+    /// 切换模式时清掉自稳控制器的积分
+    pub fn set_mode(&mut self, mode: StabMode) {
+        if mode != self.mode {
+            self.roll_ctl.reset();
+            self.pitch_ctl.reset();
+            self.mode = mode;
+        }
+    }
+
+    pub fn set_flap(&mut self, flap: f32) {
+        self.flap = flap.max(0.0).min(1.0);
+    }
+
+    pub fn arm(&mut self) {
+        self.motor.iter_mut().for_each(|m| m.unlock());
+    }
+
+    pub fn disarm(&mut self) {
+        self.motor.iter_mut().for_each(|m| m.lock());
+    }
+
+    /// 由遥控指令和姿态计算舵面指令，缺少姿态数据时退回手动，dt单位秒
+    pub fn stabilize(&mut self, rc: &RemoteControl, imu: &ImuData, dt: f32) -> (f32, f32, f32) {
+        let (roll, pitch, yaw) = (rc.roll, rc.pitch, rc.yaw);
+        let gyro = match imu.gyro {
+            Some(gyro) if self.mode != StabMode::Manual => gyro,
+            _ => return (roll, pitch, yaw),
+        };
+        let [dr, dp, dy] = self.stab.damping;
+        let yaw = yaw - dy * gyro.z;
+        //推杆为低头，与俯仰角速度方向相反，阻尼项符号也相反
+        match (self.mode, imu.euler) {
+            (StabMode::Angle, Some(euler)) => {
+                let roll = self.roll_ctl.next1(
+                    euler.roll,
+                    roll.max(-1.0).min(1.0) * self.stab.max_roll,
+                    dt,
+                );
+                let pitch = -self.pitch_ctl.next1(
+                    euler.pitch,
+                    -pitch.max(-1.0).min(1.0) * self.stab.max_pitch,
+                    dt,
+                );
+                (roll - dr * gyro.x, pitch + dp * gyro.y, yaw)
+            }
+            _ => (roll - dr * gyro.x, pitch + dp * gyro.y, yaw),
+        }
+    }
+
+    /// 增稳、混控后输出到舵机和电调，dt为距上次调用的时间，单位秒
+    pub fn update(&mut self, rc: &RemoteControl, imu: &ImuData, dt: f32) {
+        let (roll, pitch, yaw) = self.stabilize(rc, imu, dt);
+        let out = fixed_wing::mix(&self.wing, roll, pitch, yaw, self.flap);
+        for (servo, cmd) in self.servos.iter_mut().zip(out.iter()) {
+            servo.set(*cmd, dt);
+        }
+        if let Some(motor) = self.motor.as_mut() {
+            motor.throttle(rc.throttle);
+        }
+    }
+}
+
+fn level_pid(stab: &StabConfig) -> Pid<f32> {
+    Pid::new(stab.level_kp, stab.level_ki, 0.0)
+        .with_limit_integral(Limit::new(-0.3, 0.3))
+        .with_limit_out(Limit::new(-1.0, 1.0))
+}
@@ -1,14 +1,32 @@
-use crate::acs::pid::Pid;
-use crate::driver::bldc::Motor;
+//! 直升机
+//!
+//! 姿态控制器的横滚、俯仰力矩经斜盘混控输出到三个斜盘舵机，偏航力矩输出到尾舵。
+//! 没有油门曲线和总距曲线，油门杆同时控制主电机和总距。
+
+use super::Tuning;
+use crate::acs::attitude::{self, AttitudeConfig, AttitudeController, AttitudeMode};
+use crate::acs::mixer::swashplate::{self, SwashConfig, SwashType};
+use crate::acs::rates;
+use crate::app::arming::ArmingState;
+use crate::app::failsafe::FailsafeStage;
+use crate::app::modes::FlightMode;
+use crate::driver::bldc::{EscOutput, Motor};
+use crate::driver::pwm::PulseWidth;
 use crate::driver::servo::Servo;
+use crate::driver::{self, ImuData, IMU_SAMPLE_HZ};
 use crate::mbus;
 use crate::message::*;
-use xtask::fsm::Machine;
+use alloc::vec::Vec;
 use xtask::{Queue, TaskBuilder};
 
 pub fn start() {
     let q = Queue::new();
     let sender = q.clone();
+    let fs_sender = q.clone();
+    let arming_sender = q.clone();
+    let mode_sender = q.clone();
+    let imu_sender = q.clone();
+    let battery_sender = q.clone();
     TaskBuilder::new()
         .name("heli")
         .priority(1)
@@ -19,14 +37,51 @@ pub fn start() {
             log::error!("error {:?}", err);
         }
     });
+    mbus::bus().subscribe("/failsafe", move |_, msg| {
+        if let Err(err) = fs_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/arming", move |_, msg| {
+        if let Err(err) = arming_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/mode", move |_, msg| {
+        if let Err(err) = mode_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/imu", move |_, msg| {
+        if let Err(err) = imu_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/battery", move |_, msg| {
+        if let Err(err) = battery_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
 }
 
 fn sampling(recv: Queue<Message>) {
     let mut imu_count = 0u64;
-    #[cfg(feature = "mpu9250")]
-    let m = 10;
-    #[cfg(any(feature = "mpu6050", feature = "icm20602"))]
-    let m = 100;
+    let mut rc = RemoteControl::default();
+    let mut state = State::default();
+    let mut before_lost = State::default();
+    let mut mode = FlightMode::Manual;
+    let mut voltage = None;
+    //指示灯10Hz翻转
+    let m = IMU_SAMPLE_HZ as u64 / 10;
+    //IMU数据没有时间戳，控制周期按采样频率计算
+    let dt = 1.0 / IMU_SAMPLE_HZ as f32;
+    let outputs = driver::take_outputs();
+    let mut heli = Helix::new(
+        outputs.motors.into_iter().next(),
+        outputs.servos,
+        SwashConfig::new(SwashType::H120),
+        AttitudeController::new(AttitudeConfig::new().with_params()),
+    );
     loop {
         if let Some(msg) = recv.pop_front() {
             match msg {
@@ -35,7 +90,69 @@ fn sampling(recv: Queue<Message>) {
                         mbus::bus().call("/led/r/toggle", Message::None);
                     }
                     imu_count += 1;
+                    //上锁时失联，恢复的状态仍为上锁
+                    let flying = if state == State::Lost {
+                        before_lost.flying()
+                    } else {
+                        state.flying()
+                    };
+                    if let Some(heli) = heli.as_mut() {
+                        if flying {
+                            //失联时自稳改平
+                            heli.ctl.set_mode(if state == State::Lost {
+                                AttitudeMode::Angle
+                            } else {
+                                super::attitude_mode(mode)
+                            });
+                            heli.tuning.set_active(state == State::Autotune);
+                            heli.update(&rc, &data, voltage, dt);
+                        } else {
+                            //上锁时电机停转，DShot电调需要持续收到帧
+                            heli.eu.motor.throttle(0.0);
+                        }
+                    }
+                }
+                Message::RemoteControl(cmd) => {
+                    //失联时只接受失控保护给出的替代指令
+                    if (state == State::Lost) == cmd.failsafe {
+                        rc = cmd;
+                    }
+                }
+                Message::Arming(ArmingState::Armed) => {
+                    if state == State::Locked {
+                        state = mode.into();
+                        if let Some(heli) = heli.as_mut() {
+                            heli.arm();
+                        }
+                    }
+                }
+                Message::Arming(ArmingState::Disarmed) => {
+                    state = State::Locked;
+                    before_lost = State::Locked;
+                    if let Some(heli) = heli.as_mut() {
+                        heli.disarm();
+                    }
+                }
+                Message::Battery(b) => voltage = Some(b.voltage),
+                Message::FlightMode(m) => {
+                    mode = m;
+                    //失联期间只记下模式，恢复后按新模式飞行
+                    match state {
+                        State::Lost if before_lost != State::Locked => before_lost = mode.into(),
+                        State::Locked | State::Crash | State::Lost => {}
+                        _ => state = mode.into(),
+                    }
                 }
+                Message::Failsafe(stage) => match stage {
+                    FailsafeStage::Active(_) if state != State::Lost => {
+                        before_lost = state;
+                        state = State::Lost;
+                    }
+                    FailsafeStage::Idle if state == State::Lost => {
+                        state = before_lost;
+                    }
+                    _ => {}
+                },
 
                 _ => {}
             }
@@ -44,7 +161,7 @@ fn sampling(recv: Queue<Message>) {
 }
 
 // 状态
-#[derive(Default)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub enum State {
     #[default]
     Locked, //锁定
@@ -57,32 +174,122 @@ pub enum State {
     Auto,      //自动
     Following, //跟随
     Trick,     //特技
+    Autotune,  //自整定
     Lost,      //失联
     Crash,     //坠机
 }
 
+impl State {
+    //上锁和坠机时不做姿态控制
+    fn flying(&self) -> bool {
+        !matches!(self, State::Locked | State::Crash)
+    }
+}
+
+impl From<FlightMode> for State {
+    fn from(mode: FlightMode) -> Self {
+        match mode {
+            FlightMode::Manual => State::Manual,
+            FlightMode::Hover => State::Hover,
+            FlightMode::Auto => State::Auto,
+            FlightMode::Following => State::Following,
+            FlightMode::Trick => State::Trick,
+            FlightMode::Autotune => State::Autotune,
+        }
+    }
+}
+
 // 直升机
-pub struct Helix<PWM> {
-    fsm: Machine<State, Message>,
-    speed: u32,             //速度
-    height: u32,            //高度
-    roll: f32,              //翻滚角
-    pitch: f32,             //俯仰角
-    yaw: f32,               //偏航角
-    position: (f32, f32),   //当前位置
-    eu: ExecutionUnit<PWM>, //执行单元
-    ctl: Controller,        //控制器单元
+pub struct Helix<ESC, OUT> {
+    eu: ExecutionUnit<ESC, OUT>, //执行单元
+    ctl: AttitudeController,     //姿态控制器
+    tuning: Tuning,              //自整定
 }
 
-struct Controller {
-    angle_ctl: Pid<f32>, //角度控制器
-    accel_ctl: Pid<f32>, //加速度控制器
+struct ExecutionUnit<ESC, OUT> {
+    motor: Motor<ESC>,          //主旋翼
+    servo1: Servo<OUT>,         //斜盘舵机1
+    servo2: Servo<OUT>,         //斜盘舵机2
+    servo3: Servo<OUT>,         //斜盘舵机3
+    servo4: Option<Servo<OUT>>, //yaw，没有时尾桨由尾陀螺仪控制
+    swash: SwashConfig,         //斜盘混控参数
+}
+
+impl<ESC: EscOutput, OUT: PulseWidth> ExecutionUnit<ESC, OUT> {
+    /// 横滚、俯仰、总距经斜盘混控后输出到三个斜盘舵机，dt单位秒
+    fn swash(&mut self, roll: f32, pitch: f32, collective: f32, dt: f32) {
+        let [s1, s2, s3] = swashplate::mix(&self.swash, roll, pitch, collective);
+        self.servo1.set(s1, dt);
+        self.servo2.set(s2, dt);
+        self.servo3.set(s3, dt);
+    }
+
+    /// 尾舵，-1.0~1.0
+    fn yaw(&mut self, yaw: f32, dt: f32) {
+        if let Some(servo) = self.servo4.as_mut() {
+            servo.set(yaw, dt);
+        }
+    }
 }
 
-struct ExecutionUnit<PWM> {
-    motor: Motor<PWM>,  //主旋翼
-    servo1: Servo<PWM>, //roll
-    servo2: Servo<PWM>, //pitch
-    servo3: Servo<PWM>, //
-    servo4: Servo<PWM>, //yaw
+impl<ESC: EscOutput, OUT: PulseWidth> Helix<ESC, OUT> {
+    /// servos依次为三个斜盘舵机和尾舵，缺少主电机或斜盘舵机时返回None
+    pub fn new(
+        motor: Option<Motor<ESC>>,
+        servos: Vec<Servo<OUT>>,
+        swash: SwashConfig,
+        ctl: AttitudeController,
+    ) -> Option<Self> {
+        let mut servos = servos.into_iter();
+        match (motor, servos.next(), servos.next(), servos.next()) {
+            (Some(motor), Some(servo1), Some(servo2), Some(servo3)) => Some(Self {
+                eu: ExecutionUnit {
+                    motor,
+                    servo1,
+                    servo2,
+                    servo3,
+                    servo4: servos.next(),
+                    swash,
+                },
+                ctl,
+                tuning: Tuning::new(),
+            }),
+            _ => {
+                log::error!("Helix needs a motor and 3 swash servos");
+                None
+            }
+        }
+    }
+
+    /// 解锁时按参数表重建控制器，地面站修改的参数在这时生效，自整定重新开始
+    pub fn arm(&mut self) {
+        self.ctl.set_config(self.ctl.config().with_params());
+        self.tuning.restart();
+        self.eu.motor.unlock();
+    }
+
+    pub fn disarm(&mut self) {
+        self.eu.motor.lock();
+    }
+
+    /// 姿态控制后经斜盘和尾舵输出，voltage为电池电压，用于电压补偿，缺少姿态数据时不输出
+    pub fn update(&mut self, rc: &RemoteControl, imu: &ImuData, voltage: Option<f32>, dt: f32) {
+        let (quat, gyro) = match (imu.quaternion, imu.gyro) {
+            (Some(quat), Some(gyro)) => (quat, gyro),
+            _ => return,
+        };
+        //地面站可能修改了速率曲线
+        self.ctl.set_rates(rates::profile());
+        self.ctl.schedule(rc.throttle, voltage);
+        let mut torque = self.ctl.update(&attitude::stick(rc), &quat, &gyro, dt);
+        self.tuning.update(&mut self.ctl, &mut torque, &gyro, dt);
+        //斜盘正俯仰为前倾低头，力矩抬头为正
+        self.eu
+            .swash(torque[0], -torque[1], rc.throttle * 2.0 - 1.0, dt);
+        self.eu.yaw(torque[2], dt);
+        //力矩在控制器里已经乘过电压补偿系数，总距是桨距角不补偿，只补偿主电机油门
+        self.eu
+            .motor
+            .throttle(rc.throttle * self.ctl.output_scale());
+    }
 }
@@ -1,2 +1,120 @@
+//! 机型，按feature选择直升机、多旋翼或固定翼
+//!
+//! 同时启用多个机型时和混控编号一样，直升机优先，其次是多旋翼。
+//! 机型模块取走驱动初始化好的电机和舵机，订阅/imu，每个IMU数据执行一次控制。
+
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+use crate::acs::attitude::{AttitudeController, AttitudeMode};
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+use crate::acs::autotune::{Autotune, RelayConfig, TuneRule};
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+use crate::app::modes::FlightMode;
+use core::sync::atomic::{AtomicU8, Ordering};
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+use nalgebra::Vector3;
+
+#[cfg(feature = "fixed")]
+pub mod fixed_wing;
 #[cfg(feature = "helix")]
 mod helix;
+#[cfg(feature = "multi-rotor")]
+pub mod multirotor;
+
+//MSP_MIXER_CONFIG上报的混控编号，机型模块配置时更新
+#[cfg(feature = "helix")]
+static MIXER_MODE: AtomicU8 = AtomicU8::new(15);
+#[cfg(all(feature = "multi-rotor", not(feature = "helix")))]
+static MIXER_MODE: AtomicU8 = AtomicU8::new(3);
+#[cfg(all(
+    feature = "fixed",
+    not(any(feature = "helix", feature = "multi-rotor"))
+))]
+static MIXER_MODE: AtomicU8 = AtomicU8::new(14);
+#[cfg(not(any(feature = "helix", feature = "multi-rotor", feature = "fixed")))]
+static MIXER_MODE: AtomicU8 = AtomicU8::new(0);
+
+/// 当前机架的MSP混控编号
+pub fn msp_mixer_mode() -> u8 {
+    MIXER_MODE.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set_msp_mixer_mode(mode: u8) {
+    MIXER_MODE.store(mode, Ordering::Relaxed);
+}
+
+/// 启动当前机型
+pub fn start() {
+    #[cfg(feature = "helix")]
+    helix::start();
+    #[cfg(all(feature = "multi-rotor", not(feature = "helix")))]
+    multirotor::start();
+    #[cfg(all(
+        feature = "fixed",
+        not(any(feature = "helix", feature = "multi-rotor"))
+    ))]
+    fixed_wing::start();
+}
+
+/// 飞行模式对应的姿态控制模式，手动、特技和自整定只控制角速度
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+pub(crate) fn attitude_mode(mode: FlightMode) -> AttitudeMode {
+    match mode {
+        FlightMode::Manual | FlightMode::Trick | FlightMode::Autotune => AttitudeMode::Acro,
+        FlightMode::Hover | FlightMode::Auto | FlightMode::Following => AttitudeMode::Angle,
+    }
+}
+
+/// 自整定，飞行模式为Autotune时在姿态控制器算出力矩之后替换被测轴的力矩
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+pub(crate) struct Tuning {
+    active: bool,
+    autotune: Option<Autotune>, //整定结束后为None，退出再进入Autotune模式重新整定
+}
+
+#[cfg(any(feature = "helix", feature = "multi-rotor"))]
+impl Tuning {
+    pub const fn new() -> Self {
+        Self {
+            active: false,
+            autotune: None,
+        }
+    }
+
+    /// 进入、退出Autotune模式
+    pub fn set_active(&mut self, active: bool) {
+        if active != self.active {
+            self.active = active;
+            self.restart();
+        }
+    }
+
+    /// 解锁时从横滚轴重新开始
+    pub fn restart(&mut self) {
+        self.autotune = if self.active {
+            Some(Autotune::new(RelayConfig::new(), TuneRule::SomeOvershoot))
+        } else {
+            None
+        };
+    }
+
+    /// 在ctl.update之后调用，整定结束后用参数表里的结果重建控制器
+    pub fn update(
+        &mut self,
+        ctl: &mut AttitudeController,
+        torque: &mut Vector3<f32>,
+        gyro: &Vector3<f32>,
+        dt: f32,
+    ) {
+        let autotune = match self.autotune.as_mut() {
+            Some(autotune) => autotune,
+            None => return,
+        };
+        autotune.update(torque, &ctl.rate_setpoint(), gyro, dt);
+        if autotune.axis().is_none() {
+            log::info!("autotune done {:?}", autotune.results());
+            let config = ctl.config().with_params();
+            ctl.set_config(config);
+            self.autotune = None;
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! 多旋翼
+
+use super::Tuning;
+use crate::acs::attitude::{self, AttitudeConfig, AttitudeController};
+use crate::acs::mixer::multirotor::{Mixer, MixerType};
+use crate::acs::rates;
+use crate::app::arming::ArmingState;
+use crate::app::failsafe::FailsafeStage;
+use crate::app::modes::FlightMode;
+use crate::driver::bldc::{EscOutput, Motor};
+use crate::driver::{self, ImuData, IMU_SAMPLE_HZ};
+use crate::mbus;
+use crate::message::*;
+use alloc::vec::Vec;
+use xtask::{Queue, TaskBuilder};
+
+static mut MIXER: Option<Mixer> = None;
+
+/// 修改机架类型，需在start之前调用
+pub fn configure(mixer: Mixer) {
+    super::set_msp_mixer_mode(mixer.mixer_type().msp_mixer_mode());
+    unsafe {
+        MIXER.replace(mixer);
+    }
+}
+
+pub fn mixer() -> Mixer {
+    unsafe { MIXER.unwrap_or_else(|| Mixer::new(MixerType::QuadX)) }
+}
+
+pub fn start() {
+    let q = Queue::new();
+    let sender = q.clone();
+    let fs_sender = q.clone();
+    let arming_sender = q.clone();
+    let battery_sender = q.clone();
+    let imu_sender = q.clone();
+    let mode_sender = q.clone();
+    TaskBuilder::new()
+        .name("multirotor")
+        .priority(1)
+        .stack_size(1024)
+        .spawn(move || sampling(q));
+    mbus::bus().subscribe("/rc", move |_, msg| {
+        if let Err(err) = sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/failsafe", move |_, msg| {
+        if let Err(err) = fs_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/arming", move |_, msg| {
+        if let Err(err) = arming_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/battery", move |_, msg| {
+        if let Err(err) = battery_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/imu", move |_, msg| {
+        if let Err(err) = imu_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+    mbus::bus().subscribe("/mode", move |_, msg| {
+        if let Err(err) = mode_sender.push_back_isr(msg) {
+            log::error!("error {:?}", err);
+        }
+    });
+}
+
+fn sampling(recv: Queue<Message>) {
+    let mut rc = RemoteControl::default();
+    let mut armed = false;
+    let mut lost = false;
+    let mut mode = FlightMode::Manual;
+    let mut voltage = None;
+    //IMU数据没有时间戳，控制周期按采样频率计算
+    let dt = 1.0 / IMU_SAMPLE_HZ as f32;
+    let mixer = mixer();
+    log::info!(
+        "Multirotor {:?}, {} motors",
+        mixer.mixer_type(),
+        mixer.motor_count()
+    );
+    let mut vehicle = match Multirotor::new(
+        mixer,
+        driver::take_outputs().motors,
+        AttitudeController::new(AttitudeConfig::new().with_params()),
+    ) {
+        Some(vehicle) => vehicle,
+        //电机不够时不启动，只取走消息，避免队列满了一直报错
+        None => loop {
+            recv.pop_front();
+        },
+    };
+    loop {
+        if let Some(msg) = recv.pop_front() {
+            match msg {
+                Message::ImuData(imu) => {
+                    if armed {
+                        vehicle.update(&rc, &imu, voltage, dt);
+                    } else {
+                        //上锁时电机输出停转，DShot电调需要持续收到帧
+                        vehicle.output(0.0, 0.0, 0.0, 0.0);
+                    }
+                }
+                Message::RemoteControl(cmd) => {
+                    //失联时只接受失控保护给出的替代指令
+                    if lost == cmd.failsafe {
+                        rc = cmd;
+                    }
+                }
+                Message::Arming(state) => {
+                    armed = state == ArmingState::Armed;
+                    if armed {
+                        vehicle.arm();
+                    } else {
+                        vehicle.disarm();
+                    }
+                    log::info!("Multirotor armed {}", armed);
+                }
+                Message::FlightMode(m) => {
+                    mode = m;
+                    vehicle
+                        .controller_mut()
+                        .set_mode(super::attitude_mode(mode));
+                    vehicle.set_autotune(!lost && mode == FlightMode::Autotune);
+                }
+                Message::Failsafe(stage) => {
+                    lost = matches!(stage, FailsafeStage::Active(_));
+                    //失联时停止自整定
+                    vehicle.set_autotune(!lost && mode == FlightMode::Autotune);
+                }
+                Message::Battery(b) => voltage = Some(b.voltage),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// 多旋翼
+pub struct Multirotor<ESC> {
+    mixer: Mixer,
+    motors: Vec<Motor<ESC>>, //按混控规则的顺序排列
+    ctl: AttitudeController, //姿态控制器
+    tuning: Tuning,          //自整定
+}
+
+impl<ESC: EscOutput> Multirotor<ESC> {
+    /// 电机数少于混控规则数时返回None，缺电机的机架不能解锁
+    pub fn new(mixer: Mixer, motors: Vec<Motor<ESC>>, ctl: AttitudeController) -> Option<Self> {
+        if motors.len() < mixer.motor_count() {
+            log::error!(
+                "{:?} needs {} motors, got {}",
+                mixer.mixer_type(),
+                mixer.motor_count(),
+                motors.len()
+            );
+            return None;
+        }
+        Some(Self {
+            mixer,
+            motors,
+            ctl,
+            tuning: Tuning::new(),
+        })
+    }
+
+    pub fn controller_mut(&mut self) -> &mut AttitudeController {
+        &mut self.ctl
+    }
+
+    /// 开始或结束自整定
+    pub fn set_autotune(&mut self, active: bool) {
+        self.tuning.set_active(active);
+    }
+
+    /// 解锁时按参数表重建控制器，地面站修改的参数在这时生效，自整定重新开始
+    pub fn arm(&mut self) {
+        self.ctl.set_config(self.ctl.config().with_params());
+        self.tuning.restart();
+        self.motors.iter_mut().for_each(|m| m.unlock());
+    }
+
+    pub fn disarm(&mut self) {
+        self.motors.iter_mut().for_each(|m| m.lock());
+    }
+
+    /// 油门0.0~1.0，横滚、俯仰、偏航-1.0~1.0，混控后输出到各电机
+    pub fn output(&mut self, throttle: f32, roll: f32, pitch: f32, yaw: f32) {
+        let out = self.mixer.mix(throttle, roll, pitch, yaw);
+        for (motor, t) in self
+            .motors
+            .iter_mut()
+            .zip(out.iter())
+            .take(self.mixer.motor_count())
+        {
+            motor.throttle(*t);
+        }
+    }
+
+    /// 姿态控制后混控输出，voltage为电池电压，用于电压补偿，缺少姿态数据时不输出
+    pub fn update(&mut self, rc: &RemoteControl, imu: &ImuData, voltage: Option<f32>, dt: f32) {
+        let (quat, gyro) = match (imu.quaternion, imu.gyro) {
+            (Some(quat), Some(gyro)) => (quat, gyro),
+            _ => return,
+        };
+        //地面站可能修改了速率曲线
+        self.ctl.set_rates(rates::profile());
+        self.ctl.schedule(rc.throttle, voltage);
+        let mut torque = self.ctl.update(&attitude::stick(rc), &quat, &gyro, dt);
+        self.tuning.update(&mut self.ctl, &mut torque, &gyro, dt);
+        //力矩在控制器里已经乘过电压补偿系数，这里只补偿油门
+        let throttle = rc.throttle * self.ctl.output_scale();
+        //混控表的俯仰系数与摇杆一致，推杆为正，力矩为抬头为正
+        self.output(throttle, torque[0], -torque[1], torque[2]);
+    }
+}
@@ -12,6 +12,7 @@ mod driver;
 mod drone;
 mod mbus;
 mod message;
+mod param;
 
 #[cfg(any(feature = "stm32f401ccu6", feature = "stm32f427vit6"))]
 use xtask::arch::cortex_m::rt;
@@ -71,6 +72,8 @@ fn main() -> ! {
     driver::init();
     // 启动应用
     app::start();
+    // 启动机型控制
+    drone::start();
     //启动调度器
     xtask::start()
 }
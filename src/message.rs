@@ -1,6 +1,13 @@
 use alloc::vec::Vec;
 
-use crate::driver::{Accel, Barometer, Compass, Distance, Gps, Gyro, ImuData};
+use crate::app::arming::ArmingState;
+use crate::app::failsafe::FailsafeStage;
+use crate::app::modes::FlightMode;
+use crate::driver::{
+    Accel, Barometer, Battery, Compass, Distance, Gps, Gyro, ImuData, LinkStatistics, MotorRpm,
+    RcChannels, MAX_RC_CHANNELS,
+};
+use nalgebra::{UnitQuaternion, Vector3};
 
 #[derive(Debug, Clone)]
 pub enum Message {
@@ -22,9 +29,25 @@ pub enum Message {
     Control(Signal),
 
     //遥控信号
-    RemoteControl(RC),
+    RemoteControl(RemoteControl),
+    //失控保护阶段
+    Failsafe(FailsafeStage),
+    //解锁状态
+    Arming(ArmingState),
+    //飞行模式
+    FlightMode(FlightMode),
+    //接收机通道
+    RcChannels(RcChannels),
+    //无线链路统计
+    LinkStatistics(LinkStatistics),
+    //电池
+    Battery(Battery),
+    //电机转速
+    MotorRpm(MotorRpm),
     //遥测数据
     Telem(Telem),
+    //组合导航
+    Navigation(Navigation),
     None,
 }
 
@@ -40,11 +63,28 @@ pub enum Signal {
     Motor {},
     Servo {},
 }
-#[derive(Debug, Clone)]
-pub enum RC {
-    Hover,
-    TurnLeft(f32),  //左转
-    TrunRight(f32), //右转
-    ReturnFlight,   //返航
-    Move(f32, f32), //移动
+/// 辅助通道数
+pub const MAX_AUX_CHANNELS: usize = MAX_RC_CHANNELS - 4;
+
+/// 遥控指令，摇杆量已按校准参数归一化
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RemoteControl {
+    pub roll: f32,                    //横滚，-1.0~1.0，右为正
+    pub pitch: f32,                   //俯仰，-1.0~1.0，前推为正
+    pub yaw: f32,                     //偏航，-1.0~1.0，右为正
+    pub throttle: f32,                //油门，0.0~1.0
+    pub aux: [u16; MAX_AUX_CHANNELS], //辅助通道，单位微秒
+    pub aux_count: u8,                //有效辅助通道数
+    pub failsafe: bool,               //失控保护生成的替代指令
+}
+
+/// 组合导航结果
+#[derive(Debug, Clone, Copy)]
+pub struct Navigation {
+    pub position: Vector3<f32>,        //相对原点的北东地位置，单位米
+    pub velocity: Vector3<f32>,        //北东地速度，单位m/s
+    pub attitude: UnitQuaternion<f32>, //机体前右下到北东地
+    pub gyro_bias: Vector3<f32>,       //陀螺仪零偏，单位rad/s
+    pub accel_bias: Vector3<f32>,      //加速度计零偏，单位m/s²
+    pub origin: (f64, f64, f32),       //原点纬度、经度、海拔
 }
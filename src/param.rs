@@ -0,0 +1,53 @@
+//! 参数表
+//!
+//! 按名字保存的运行时参数，没有设置过的参数由使用方给出默认值，重启后恢复默认。
+//! 参数名用小写加下划线，例如rate_roll_p。
+//! 角速度环参数(包括自整定结果)可以由地面站通过MSP_PID读出、MSP_SET_PID修改，
+//! 需要保留的整定结果由地面站读出后在下次上电时写回。
+//!
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// 最多参数个数
+pub const MAX_PARAMS: usize = 64;
+
+static PARAMS: Mutex<Vec<(&'static str, f32)>> = Mutex::new(Vec::new());
+
+/// 读取参数，没有设置过时返回None
+pub fn get(name: &str) -> Option<f32> {
+    PARAMS
+        .lock()
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| *v)
+}
+
+/// 读取参数，没有设置过时返回默认值
+pub fn get_or(name: &str, default: f32) -> f32 {
+    get(name).unwrap_or(default)
+}
+
+/// 设置参数，参数表已满时返回false
+pub fn set(name: &'static str, value: f32) -> bool {
+    let mut params = PARAMS.lock();
+    if let Some(p) = params.iter_mut().find(|(n, _)| *n == name) {
+        p.1 = value;
+        return true;
+    }
+    if params.len() >= MAX_PARAMS {
+        log::warn!("param table full, drop {}", name);
+        return false;
+    }
+    params.push((name, value));
+    true
+}
+
+/// 恢复默认值
+pub fn remove(name: &str) {
+    PARAMS.lock().retain(|(n, _)| *n != name);
+}
+
+/// 所有设置过的参数
+pub fn list() -> Vec<(&'static str, f32)> {
+    PARAMS.lock().clone()
+}